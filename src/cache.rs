@@ -0,0 +1,148 @@
+use crate::db::Database;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// On-disk cache of chunk embeddings keyed by (content hash, model id).
+///
+/// Each model gets its own file under `embed_cache/`, so switching models never
+/// returns another model's vectors, and switching back picks the old ones up again.
+/// Anything else that changes the vectors, like a document prefix, belongs in the id.
+pub struct EmbeddingCache {
+    path: PathBuf,
+    entries: HashMap<u64, Vec<f32>>,
+    dirty: bool,
+}
+
+pub fn content_hash(text: &str) -> u64 {
+    xxh3_64(text.as_bytes())
+}
+
+impl EmbeddingCache {
+    /// Loads the cache for `model_id`. A missing or unreadable cache starts empty;
+    /// it only ever saves work, so it's never worth failing over.
+    pub fn load(data_dir: &Path, model_id: &str) -> Self {
+        let file_name = format!("{}.bin", model_id.replace(['/', '\\', ':'], "__"));
+        let path = data_dir.join("embed_cache").join(file_name);
+
+        let entries = match read_entries(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if path.exists() {
+                    eprintln!("⚠️  Ignoring unreadable embedding cache {:?}: {}", path, e);
+                }
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            entries,
+            dirty: false,
+        }
+    }
+
+    pub fn get(&self, text: &str) -> Option<&Vec<f32>> {
+        self.entries.get(&content_hash(text))
+    }
+
+    pub fn insert(&mut self, text: &str, vector: Vec<f32>) {
+        self.entries.insert(content_hash(text), vector);
+        self.dirty = true;
+    }
+
+    /// Drops entries whose text is no longer indexed, keeping the cache bounded
+    /// by the size of the vault rather than its edit history.
+    pub fn retain_texts<'a>(&mut self, texts: impl Iterator<Item = &'a str>) {
+        let live: HashSet<u64> = texts.map(content_hash).collect();
+        let before = self.entries.len();
+        self.entries.retain(|hash, _| live.contains(hash));
+        self.dirty |= self.entries.len() != before;
+    }
+
+    /// Prunes to the texts still in `db` and writes the cache if it changed.
+    pub fn save_for(&mut self, db: &Database) -> Result<()> {
+        self.retain_texts(db.chunks.iter().map(|c| c.text.as_str()));
+        self.save()
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Written aside and renamed over, so a crash mid-write keeps the old cache.
+        let dim = self.entries.values().next().map_or(0, |v| v.len());
+        let tmp_path = self.path.with_extension("bin.tmp");
+        let mut writer = BufWriter::new(fs::File::create(&tmp_path)?);
+        writer.write_all(&(dim as u32).to_le_bytes())?;
+        for (hash, vector) in &self.entries {
+            writer.write_all(&hash.to_le_bytes())?;
+            for x in vector {
+                writer.write_all(&x.to_le_bytes())?;
+            }
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn read_entries(path: &Path) -> Result<HashMap<u64, Vec<f32>>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut buf4 = [0u8; 4];
+    let mut buf8 = [0u8; 8];
+
+    reader.read_exact(&mut buf4)?;
+    let dim = u32::from_le_bytes(buf4) as usize;
+
+    let mut entries = HashMap::new();
+    loop {
+        match reader.read_exact(&mut buf8) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let hash = u64::from_le_bytes(buf8);
+        let mut vector = Vec::with_capacity(dim);
+        for _ in 0..dim {
+            reader.read_exact(&mut buf4)?;
+            vector.push(f32::from_le_bytes(buf4));
+        }
+        entries.insert(hash, vector);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cache_keyed_by_model() -> Result<()> {
+        let tmp = tempdir()?;
+
+        let mut cache = EmbeddingCache::load(tmp.path(), "BAAI/bge-small-en-v1.5");
+        cache.insert("hello world", vec![0.5, 0.25]);
+        cache.save()?;
+
+        // A different model must not see the first model's vectors.
+        let other = EmbeddingCache::load(tmp.path(), "BAAI/bge-base-en-v1.5");
+        assert!(other.get("hello world").is_none());
+
+        // Switching back reuses them.
+        let back = EmbeddingCache::load(tmp.path(), "BAAI/bge-small-en-v1.5");
+        assert_eq!(back.get("hello world"), Some(&vec![0.5, 0.25]));
+
+        Ok(())
+    }
+}
@@ -1,43 +1,412 @@
+use crate::config::Config;
+use crate::markdown;
+use anyhow::Result;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
 pub struct Chunker {
     pub chunk_size: usize,
     pub chunk_overlap: usize,
+    /// A trailing remainder with fewer new characters than this is merged into the
+    /// preceding chunk instead of becoming a fragment of its own. A document's only
+    /// chunk is always kept, however short, so tiny notes stay searchable.
+    pub min_chunk_chars: usize,
+    /// Chunks are cut shorter where needed to stay within this many tokens, so the
+    /// model doesn't silently truncate them.
+    pub token_limit: Option<TokenLimit>,
 }
 
 impl Default for Chunker {
     fn default() -> Self {
+        Config::default().chunker()
+    }
+}
+
+/// A token budget for chunks, counted with the embedding model's tokenizer.
+#[derive(Clone)]
+pub struct TokenLimit {
+    tokenizer: Arc<Tokenizer>,
+    pub max_tokens: usize,
+}
+
+impl TokenLimit {
+    pub fn new(tokenizer: &Tokenizer, max_tokens: usize) -> Result<Self> {
+        // Counting has to see every token, not just those the model would keep.
+        let mut tokenizer = tokenizer.clone();
+        tokenizer
+            .with_truncation(None)
+            .map_err(|e| anyhow::anyhow!("Tokenizer error: {e}"))?;
+        tokenizer.with_padding(None);
+        Ok(Self {
+            tokenizer: Arc::new(tokenizer),
+            max_tokens,
+        })
+    }
+
+    /// Tokens in `text` as the model sees it, special tokens included.
+    pub fn count(&self, text: &str) -> usize {
+        self.tokenizer
+            .encode(text, true)
+            .map_or(usize::MAX, |encoding| encoding.len())
+    }
+
+    /// The budget left after `text`, e.g. a header added to each chunk later.
+    pub fn reserve(&self, text: &str) -> Self {
+        let used = self
+            .tokenizer
+            .encode(text, false)
+            .map_or(0, |encoding| encoding.len());
         Self {
-            chunk_size: 1000,
-            chunk_overlap: 200,
+            tokenizer: self.tokenizer.clone(),
+            max_tokens: self.max_tokens.saturating_sub(used).max(1),
+        }
+    }
+
+    fn fits(&self, chars: &[char]) -> bool {
+        self.count(&chars.iter().collect::<String>()) <= self.max_tokens
+    }
+
+    /// The furthest end up to `end` at which `chars[start..end]` fits, cutting
+    /// after whitespace when possible. At least one character is always kept.
+    fn fit(&self, chars: &[char], start: usize, end: usize) -> usize {
+        if self.fits(&chars[start..end]) {
+            return end;
         }
+        let cuts: Vec<usize> = (start + 1..end)
+            .filter(|&i| chars[i - 1].is_whitespace())
+            .collect();
+        let fitting = cuts.partition_point(|&cut| self.fits(&chars[start..cut]));
+        if fitting > 0 {
+            return cuts[fitting - 1];
+        }
+        let ends: Vec<usize> = (start + 2..end).collect();
+        let fitting = ends.partition_point(|&end| self.fits(&chars[start..end]));
+        start + 1 + fitting
     }
 }
 
 impl Chunker {
+    /// Keeps every chunk within `limit`.
+    pub fn with_token_limit(mut self, limit: TokenLimit) -> Self {
+        self.token_limit = Some(limit);
+        self
+    }
+
     pub fn chunk(&self, text: &str) -> Vec<String> {
-        if text.is_empty() {
-            return Vec::new();
-        }
+        let chars: Vec<char> = text.chars().collect();
+        self.spans(text)
+            .into_iter()
+            .map(|(start, end)| chars[start..end].iter().collect())
+            .collect()
+    }
 
-        let mut chunks = Vec::new();
+    /// The `(start, end)` character ranges of each chunk of `text`.
+    pub fn spans(&self, text: &str) -> Vec<(usize, usize)> {
+        self.spans_within(text, self.token_limit.as_ref())
+    }
+
+    fn spans_within(&self, text: &str, token_limit: Option<&TokenLimit>) -> Vec<(usize, usize)> {
         let chars: Vec<char> = text.chars().collect();
+        let len = chars.len();
+        let mut spans = Vec::new();
         let mut start = 0;
+        // How far back a cut may move to land on a sentence or word boundary.
+        let tolerance = self.chunk_size / 10;
 
-        while start < chars.len() {
-            let end = (start + self.chunk_size).min(chars.len());
-            let chunk: String = chars[start..end].iter().collect();
-            chunks.push(chunk);
+        while start < len {
+            let mut end = (start + self.chunk_size).min(len);
+            if len - end < self.min_chunk_chars {
+                end = len;
+            } else if end < len {
+                end = snap_back(&chars, end, end.saturating_sub(tolerance).max(start + 1));
+            }
+            let mut overlap = self.chunk_overlap;
+            if let Some(limit) = token_limit {
+                let fitted = limit.fit(&chars, start, end);
+                if fitted < end {
+                    // Overlap in proportion to the shortened chunk, so it still advances.
+                    overlap = overlap.min((fitted - start) / 2);
+                    end = fitted;
+                }
+            }
+            // A link cut in two would be embedded with its brackets on both halves,
+            // so the cut moves before it, or after it if that would halve the chunk.
+            if let Some((open, close)) = enclosing_link(&chars, end) {
+                if open > start + (end - start) / 2 {
+                    end = open;
+                } else if token_limit.is_none_or(|limit| limit.fits(&chars[start..close])) {
+                    end = close;
+                }
+            }
+            spans.push((start, end));
 
-            if end == chars.len() {
+            if end == len {
                 break;
             }
 
-            start += self.chunk_size - self.chunk_overlap;
+            // A cut moved back to a boundary mustn't leave the overlap reaching past
+            // the chunk's start.
+            let previous = start;
+            overlap = overlap.min(end - start - 1);
+            start = end - overlap;
+            if overlap > 0 {
+                start = snap_back(
+                    &chars,
+                    start,
+                    start.saturating_sub(tolerance).max(previous + 1),
+                );
+            }
+            // The previous chunk ends after any link the overlap starts in.
+            if let Some((open, close)) = enclosing_link(&chars, start) {
+                if open > previous {
+                    start = open;
+                } else if close <= end {
+                    start = close;
+                }
+            }
         }
 
+        spans
+    }
+
+    /// Chunks a markdown document along its headings: each section is one chunk if
+    /// it fits in `chunk_size`, and is split with `spans` otherwise. A document
+    /// without headings chunks the same as with `spans`.
+    pub fn chunk_markdown(&self, text: &str) -> Vec<MarkdownChunk> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut chunks = Vec::new();
+        for section in sections(text) {
+            let body: String = chars[section.start..section.end].iter().collect();
+            if body.trim().is_empty() {
+                continue;
+            }
+            // Leave room for the breadcrumb that chunks get labelled with.
+            let limit = self
+                .token_limit
+                .as_ref()
+                .map(|limit| limit.reserve(&section.breadcrumb));
+            let len = section.end - section.start;
+            let fits = limit
+                .as_ref()
+                .is_none_or(|limit| limit.count(&body) <= limit.max_tokens);
+            let spans = if len <= self.chunk_size && fits {
+                vec![(0, len)]
+            } else {
+                self.spans_within(&body, limit.as_ref())
+            };
+            let body: Vec<char> = body.chars().collect();
+            for (start, end) in spans {
+                chunks.push(MarkdownChunk {
+                    text: body[start..end].iter().collect(),
+                    span: (section.start + start, section.start + end),
+                    breadcrumb: section.breadcrumb.clone(),
+                });
+            }
+        }
         chunks
     }
 }
 
+/// The cut at or before `pos`, but not before `floor`, that falls on the nearest
+/// sentence boundary (after `.`, `!` or `?` and whitespace), or else the nearest
+/// word boundary (after whitespace). `pos` itself if there's neither in range.
+fn snap_back(chars: &[char], pos: usize, floor: usize) -> usize {
+    let mut words = (floor.max(1)..=pos)
+        .rev()
+        .filter(|&i| chars[i - 1].is_whitespace());
+    let sentence = words
+        .clone()
+        .find(|&i| i >= 2 && matches!(chars[i - 2], '.' | '!' | '?'));
+    sentence.or_else(|| words.next()).unwrap_or(pos)
+}
+
+/// The range of the `[[wikilink]]` that a cut at `pos` would split, if any.
+/// Links don't span lines.
+fn enclosing_link(chars: &[char], pos: usize) -> Option<(usize, usize)> {
+    let line_start = chars[..pos]
+        .iter()
+        .rposition(|&c| c == '\n')
+        .map_or(0, |i| i + 1);
+    let open = (line_start..pos.saturating_sub(1))
+        .rev()
+        .take_while(|&i| !(chars[i] == ']' && chars[i + 1] == ']'))
+        .find(|&i| chars[i] == '[' && chars[i + 1] == '[')?;
+    let close = (pos.saturating_sub(1)..chars.len().saturating_sub(1))
+        .take_while(|&i| chars[i] != '\n')
+        .find(|&i| chars[i] == ']' && chars[i + 1] == ']')?;
+    Some((open, close + 2))
+}
+
+/// A chunk from `Chunker::chunk_markdown`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownChunk {
+    pub text: String,
+    /// The character range of `text` in the document.
+    pub span: (usize, usize),
+    /// The headings the chunk is under, outermost first, e.g. "Project > Goals".
+    /// Empty before the first heading.
+    pub breadcrumb: String,
+}
+
+/// A run of a document from one heading line up to the next.
+struct Section {
+    start: usize,
+    end: usize,
+    breadcrumb: String,
+}
+
+/// Splits `text` into sections at its ATX (`## Goals`) and setext (`Goals` over
+/// `===` or `---`) headings, ignoring code fences and frontmatter. A heading with
+/// no body before the next heading joins that next section, so it stays with the
+/// content it introduces.
+fn sections(text: &str) -> Vec<Section> {
+    let mut sections = vec![Section {
+        start: 0,
+        end: 0,
+        breadcrumb: String::new(),
+    }];
+    // Whether the last section has anything besides its heading so far.
+    let mut has_body = false;
+    let mut trail: Vec<(usize, String)> = Vec::new();
+    // The previous line, which a setext underline would turn into a heading, with
+    // its start and whether the section had a body before it.
+    let mut previous: Option<(usize, String, bool)> = None;
+    let mut fence: Option<&str> = None;
+    let mut in_frontmatter = false;
+    let mut offset = 0;
+
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += line.chars().count();
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        if i == 0 && line == "---" {
+            in_frontmatter = true;
+            continue;
+        }
+        if in_frontmatter {
+            in_frontmatter = line.trim_end() != "---";
+            continue;
+        }
+        if let Some(marker) = fence {
+            if markdown::closes_fence(line, marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if let Some(marker) = markdown::opening_fence(line) {
+            fence = Some(marker);
+            has_body = true;
+            previous = None;
+            continue;
+        }
+
+        let heading = match (atx_heading(line), setext_level(line), previous.take()) {
+            (Some((level, title)), _, _) => Some((start, level, title.to_string(), has_body)),
+            (None, Some(level), Some((title_start, title, body_before))) => {
+                Some((title_start, level, title, body_before))
+            }
+            _ => None,
+        };
+        let Some((heading_start, level, title, body_before)) = heading else {
+            if !line.trim().is_empty() {
+                previous = Some((start, line.trim().to_string(), has_body));
+                has_body = true;
+            }
+            continue;
+        };
+
+        if body_before {
+            sections.last_mut().unwrap().end = heading_start;
+            sections.push(Section {
+                start: heading_start,
+                end: 0,
+                breadcrumb: String::new(),
+            });
+        }
+        has_body = false;
+        trail.retain(|(l, _)| *l < level);
+        trail.push((level, title));
+        sections.last_mut().unwrap().breadcrumb = trail
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .collect::<Vec<_>>()
+            .join(" > ");
+    }
+
+    sections.last_mut().unwrap().end = offset;
+    sections
+}
+
+/// The level and title of an ATX heading line like `## Goals ##`.
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.len() - trimmed.trim_start_matches('#').len();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// The heading level a setext underline (`===` or `---`) gives the line above it.
+fn setext_level(line: &str) -> Option<usize> {
+    let trimmed = line.trim();
+    if line.len() - line.trim_start().len() > 3 || trimmed.is_empty() {
+        return None;
+    }
+    if trimmed.chars().all(|c| c == '=') {
+        Some(1)
+    } else if trimmed.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Per-note chunking settings from frontmatter (`obra_chunk_size`,
+/// `obra_chunk_overlap`), for notes the global settings don't suit.
+#[derive(Debug, Default, Deserialize)]
+pub struct ChunkOverrides {
+    #[serde(rename = "obra_chunk_size")]
+    pub chunk_size: Option<usize>,
+    #[serde(rename = "obra_chunk_overlap")]
+    pub chunk_overlap: Option<usize>,
+}
+
+impl ChunkOverrides {
+    /// Reads the overrides from a note's frontmatter. Frontmatter that isn't valid
+    /// YAML has no overrides; it's the user's note, not obra's config.
+    pub fn from_frontmatter(yaml: &str) -> Self {
+        serde_yaml::from_str(yaml).unwrap_or_default()
+    }
+
+    /// `base` with these overrides applied, or an error if the result couldn't chunk.
+    pub fn apply(&self, base: &Chunker) -> Result<Chunker> {
+        let chunker = Chunker {
+            chunk_size: self.chunk_size.unwrap_or(base.chunk_size),
+            chunk_overlap: self.chunk_overlap.unwrap_or(base.chunk_overlap),
+            min_chunk_chars: base.min_chunk_chars,
+            token_limit: base.token_limit.clone(),
+        };
+        if chunker.chunk_size == 0 {
+            anyhow::bail!("obra_chunk_size must be at least 1");
+        }
+        if chunker.chunk_overlap >= chunker.chunk_size {
+            anyhow::bail!(
+                "obra_chunk_overlap ({}) must be smaller than the chunk size ({})",
+                chunker.chunk_overlap,
+                chunker.chunk_size
+            );
+        }
+        Ok(chunker)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -47,6 +416,8 @@ mod tests {
         let chunker = Chunker {
             chunk_size: 10,
             chunk_overlap: 0,
+            min_chunk_chars: 0,
+            token_limit: None,
         };
         let text = "abcdefghij0123456789";
         let chunks = chunker.chunk(text);
@@ -60,6 +431,8 @@ mod tests {
         let chunker = Chunker {
             chunk_size: 10,
             chunk_overlap: 5,
+            min_chunk_chars: 0,
+            token_limit: None,
         };
         let text = "abcdefghij01234";
         let chunks = chunker.chunk(text);
@@ -70,10 +443,181 @@ mod tests {
         assert_eq!(chunks[1], "fghij01234");
     }
 
+    #[test]
+    fn test_cuts_avoid_splitting_wikilinks() {
+        let chunker = Chunker {
+            chunk_size: 30,
+            chunk_overlap: 4,
+            min_chunk_chars: 0,
+            token_limit: None,
+        };
+        let text = "Dinner tonight is [[Recipes/Pasta|fresh pasta]] again, and tomorrow too.";
+        let chunks = chunker.chunk(text);
+        assert!(chunks
+            .iter()
+            .any(|c| c.contains("[[Recipes/Pasta|fresh pasta]]")));
+        for chunk in &chunks {
+            assert_eq!(chunk.matches("[[").count(), chunk.matches("]]").count());
+        }
+    }
+
+    #[test]
+    fn test_overlap_snaps_to_sentence_and_word_boundaries() {
+        let chunker = Chunker {
+            chunk_size: 60,
+            chunk_overlap: 20,
+            min_chunk_chars: 0,
+            token_limit: None,
+        };
+        let text = "The cake was delicious. Everyone asked for the recipe! \
+                    We baked another one on Sunday. It vanished within the hour? \
+                    Next time we will make two cakes at once.";
+        let chars: Vec<char> = text.chars().collect();
+        let spans = chunker.spans(text);
+        assert!(spans.len() > 2);
+        for &(start, end) in &spans {
+            // No chunk starts or ends in the middle of a word.
+            assert!(start == 0 || chars[start - 1] == ' ', "starts mid-word");
+            assert!(end == chars.len() || chars[end - 1] == ' ', "ends mid-word");
+        }
+        let chunks = chunker.chunk(text);
+        assert_eq!(
+            chunks[0],
+            "The cake was delicious. Everyone asked for the recipe! "
+        );
+        assert!(chunks.iter().skip(1).all(|c| !c.starts_with("was")));
+
+        // Consecutive chunks still overlap and always move forward.
+        for pair in spans.windows(2) {
+            assert!(pair[0].0 < pair[1].0 && pair[1].0 < pair[0].1);
+        }
+        assert_eq!(spans.last().unwrap().1, chars.len());
+    }
+
     #[test]
     fn test_empty_text() {
         let chunker = Chunker::default();
         let chunks = chunker.chunk("");
         assert!(chunks.is_empty());
     }
+
+    #[test]
+    fn test_tiny_tail_is_merged() {
+        let chunker = Chunker {
+            chunk_size: 10,
+            chunk_overlap: 0,
+            min_chunk_chars: 4,
+            token_limit: None,
+        };
+        let chunks = chunker.chunk("abcdefghij0123456789xyz");
+        // "xyz" is under the minimum, so it joins the previous chunk.
+        assert_eq!(chunks, ["abcdefghij", "0123456789xyz"]);
+        assert!(chunks.iter().all(|c| c.chars().count() >= 4));
+    }
+
+    #[test]
+    fn test_markdown_chunks_follow_headings() {
+        let chunker = Chunker::default();
+        let note = "# Project\nWhy we do it.\n## Goals\nShip it.\n## Risks\nScope creep.\n";
+        let chunks = chunker.chunk_markdown(note);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].text, "## Goals\nShip it.\n");
+        assert_eq!(chunks[1].breadcrumb, "Project > Goals");
+        assert_eq!(chunks[2].breadcrumb, "Project > Risks");
+        let (start, end) = chunks[2].span;
+        let span: String = note.chars().skip(start).take(end - start).collect();
+        assert_eq!(span, chunks[2].text);
+
+        // A `#` line in a code fence isn't a heading.
+        let fenced = "# Setup\n```sh\n# install deps\nmake\n```\nDone.\n";
+        let chunks = chunker.chunk_markdown(fenced);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].breadcrumb, "Setup");
+
+        // Without headings it's the sliding window.
+        let plain = "word ".repeat(400);
+        let spans: Vec<_> = chunker
+            .chunk_markdown(&plain)
+            .iter()
+            .map(|c| c.span)
+            .collect();
+        assert_eq!(spans, chunker.spans(&plain));
+    }
+
+    #[test]
+    fn test_markdown_heading_edge_cases() {
+        let chunker = Chunker::default();
+
+        // A heading with nothing under it stays with the next section.
+        let chunks = chunker.chunk_markdown("# Trip\n## Day 1\nBeach.\n## Day 2\nHike.\n");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].text, "# Trip\n## Day 1\nBeach.\n");
+        assert_eq!(chunks[0].breadcrumb, "Trip > Day 1");
+
+        // Setext headings; frontmatter's closing `---` isn't one.
+        let note = "---\ntags: [a]\n---\nIntro.\n\nTitle\n=====\nBody.\nPart\n----\nMore.\n";
+        let chunks = chunker.chunk_markdown(note);
+        let crumbs: Vec<&str> = chunks.iter().map(|c| c.breadcrumb.as_str()).collect();
+        assert_eq!(crumbs, ["", "Title", "Title > Part"]);
+        assert!(chunks[1].text.starts_with("Title\n====="));
+
+        // An oversized section falls back to the window, keeping its breadcrumb.
+        let long = format!("# Log\n{}", "entry ".repeat(300));
+        let chunks = chunker.chunk_markdown(&long);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.breadcrumb == "Log"));
+    }
+
+    #[test]
+    fn test_chunks_stay_within_token_limit() -> Result<()> {
+        use std::collections::HashMap;
+        use tokenizers::models::wordlevel::WordLevel;
+        use tokenizers::pre_tokenizers::whitespace::Whitespace;
+
+        let vocab: HashMap<String, u32> = [("[UNK]", 0), ("la", 1)]
+            .into_iter()
+            .map(|(token, id)| (token.to_string(), id))
+            .collect();
+        let model = WordLevel::builder()
+            .vocab(vocab.into_iter().collect())
+            .unk_token("[UNK]".into())
+            .build()
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace {}));
+
+        // 3 characters per token, so 1000 characters would be ~333 tokens.
+        let text = "la ".repeat(1000);
+        let limit = TokenLimit::new(&tokenizer, 64)?;
+        let chunker = Chunker::default().with_token_limit(limit.clone());
+        let spans = chunker.spans(&text);
+        assert!(spans.len() > 1);
+        let chars: Vec<char> = text.chars().collect();
+        for &(start, end) in &spans {
+            assert!(limit.count(&chars[start..end].iter().collect::<String>()) <= 64);
+            // Cut after whitespace, not mid-word.
+            assert!(end == chars.len() || chars[end - 1] == ' ');
+        }
+        assert_eq!(spans.last().unwrap().1, chars.len());
+
+        let chunks = chunker.chunk_markdown(&format!("# Notes\n{}", text));
+        assert!(chunks.iter().all(|c| limit.count(&c.text) <= 64));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_overrides_are_validated() -> Result<()> {
+        let base = Chunker::default();
+
+        let overrides = ChunkOverrides::from_frontmatter("tags: [a]\nobra_chunk_size: 500\n");
+        let chunker = overrides.apply(&base)?;
+        assert_eq!((chunker.chunk_size, chunker.chunk_overlap), (500, 200));
+
+        let overlap_too_big = ChunkOverrides::from_frontmatter("obra_chunk_size: 100\n");
+        assert!(overlap_too_big.apply(&base).is_err());
+        assert!(ChunkOverrides::from_frontmatter("obra_chunk_size: 0")
+            .apply(&base)
+            .is_err());
+        Ok(())
+    }
 }
@@ -1,14 +1,460 @@
+use crate::chunker::Chunker;
+use crate::db::VectorPrecision;
+use crate::error::ObraError;
+use crate::markdown::{CodeBlocks, FieldRoles, ThinNotes};
+use crate::search::{SearchMode, SearchOptions};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+/// The default embedding model.
 pub const MODEL_ID: &str = "BAAI/bge-small-en-v1.5";
+/// What the English BGE models were trained to expect before a search query;
+/// passages go in plain.
+pub const QUERY_INSTRUCTION: &str = "Represent this sentence for searching relevant passages: ";
+/// Folders never indexed, on top of the config's `ignore_folders`.
 pub const IGNORE_FOLDERS: &[&str] = &[".obsidian", ".git", ".stfolder", "templates"];
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub vault_path: PathBuf,
+    /// Hugging Face id of a BERT embedding model. Changing it means re-indexing
+    /// with `obra --force`, unless the new model has the same dimension.
+    #[serde(default = "default_model_id")]
+    pub model_id: String,
+    /// More folders to leave out of the index, besides `IGNORE_FOLDERS`: names like
+    /// `Archive`, matching a folder anywhere, or paths from the vault root like
+    /// `Daily/*`, where `*` and `?` match within one level.
+    #[serde(default)]
+    pub ignore_folders: Vec<String>,
+    /// Extensions of the files indexed as notes. `canvas` files are indexed by the
+    /// text on their cards.
+    #[serde(default = "default_indexed_extensions")]
+    pub indexed_extensions: Vec<String>,
+    /// How often, in seconds, the daemon saves changes the watcher made to the index.
+    /// Saving writes the whole index, so doing it per change stalls on large vaults.
+    /// 0 saves after every change.
+    #[serde(default = "default_save_interval_secs")]
+    pub save_interval_secs: u64,
+    /// Chunks embedded (and inserted) per forward pass; bounds peak memory while indexing.
+    #[serde(default = "default_embed_batch_size")]
+    pub embed_batch_size: usize,
+    /// Embedding batches run through the model at once while indexing, each costing
+    /// memory. See `bench_embed_concurrency` for timing it on a machine.
+    #[serde(default = "default_embed_concurrency")]
+    pub embed_concurrency: usize,
+    /// Fall back to fuzzy filename matching when semantic search finds nothing.
+    #[serde(default)]
+    pub fuzzy_fallback: bool,
+    /// Characters per chunk. Changing it or `chunk_overlap` moves chunk boundaries,
+    /// so existing notes keep their old chunks until `obra --force`.
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    /// Characters each chunk repeats from the end of the one before; less than `chunk_size`.
+    #[serde(default = "default_chunk_overlap")]
+    pub chunk_overlap: usize,
+    /// Trailing chunk fragments shorter than this are merged into the previous chunk.
+    #[serde(default = "default_min_chunk_chars")]
+    pub min_chunk_chars: usize,
+    /// Cap on chunks fetched per query; the actual count scales with chunks per file.
+    #[serde(default = "default_max_candidates")]
+    pub max_candidates: usize,
+    /// Chunks fetched per query at least, however few results are wanted.
+    #[serde(default = "default_search_limit")]
+    pub search_limit: usize,
+    /// Results shown per search, unless `--limit` says otherwise.
+    #[serde(default = "default_max_results")]
+    pub max_results: usize,
+    /// Results scoring this or worse are dropped. Scores are cosine distances less
+    /// any boosts, so lower is better; raise it for more hits, lower it for fewer.
+    #[serde(default = "default_score_threshold")]
+    pub score_threshold: f32,
+    /// Instruction prepended to each chunk when embedding it (not stored). Some
+    /// models expect one on the document side; BGE doesn't.
+    #[serde(default)]
+    pub document_prefix: String,
+    /// Upper bound on indexed chunks; the least recently modified are evicted
+    /// past it. Unlimited when unset.
+    #[serde(default)]
+    pub max_chunks: Option<usize>,
+    /// How many matching chunks each file carries in grouped search results.
+    #[serde(default = "default_chunks_per_file")]
+    pub chunks_per_file: usize,
+    /// Sync before searching once the index is older than this many hours (the
+    /// daemon syncs in the background instead). 0 turns the check off.
+    #[serde(default = "default_auto_sync_hours")]
+    pub auto_sync_hours: i64,
+    /// Whether fenced code blocks are embedded: `include`, `exclude` or `code-only`.
+    #[serde(default)]
+    pub code_blocks: CodeBlocks,
+    /// Notes with fewer words of prose than `min_prose_words`, not counting links
+    /// and headings: `embed` them anyway, index them `title-only`, or `skip` them.
+    #[serde(default)]
+    pub thin_notes: ThinNotes,
+    #[serde(default = "default_min_prose_words")]
+    pub min_prose_words: usize,
+    /// Decimal places for scores shown to the user. Ranking uses full precision.
+    #[serde(default = "default_score_precision")]
+    pub score_precision: usize,
+    /// How document vectors are stored: `f32`, `f16` for half the index size, or `i8`
+    /// for a quarter. Leaving `i8` takes a re-index (`obra --force`).
+    #[serde(default)]
+    pub document_precision: VectorPrecision,
+    /// Precision queries are rounded to before searching; at least `document_precision`.
+    #[serde(default)]
+    pub query_precision: VectorPrecision,
+    /// Keep a full-precision copy of every vector in `stored_vectors.bin`, so a lost
+    /// or quantized index can be rebuilt (`obra compact`, `obra verify --fix`) without
+    /// the model. Costs 4 bytes per dimension per chunk, on disk and in memory: about
+    /// 1.5 KB a chunk with the default model, more than an f32 index itself. Off by default.
+    #[serde(default)]
+    pub store_vectors: bool,
+    /// Append each search to `queries.jsonl` in the data dir. Off by default; nothing
+    /// is sent anywhere. Clear it with `obra clear-query-log`.
+    #[serde(default)]
+    pub log_queries: bool,
+    /// Score boost for notes whose title or a frontmatter alias equals the query,
+    /// large enough to put them first. 0 disables it.
+    #[serde(default = "default_exact_title_boost")]
+    pub exact_title_boost: f32,
+    /// Score boost for recently modified notes: the full amount for a note edited just
+    /// now, halving every `recency_half_life_days`. 0 (the default) disables it.
+    #[serde(default)]
+    pub recency_boost: f32,
+    #[serde(default = "default_recency_half_life_days")]
+    pub recency_half_life_days: f32,
+    /// Score boost for notes that link to, or are linked from, one of the best
+    /// results, through `[[wikilinks]]`. 0 (the default) disables it.
+    #[serde(default)]
+    pub link_boost: f32,
+    /// Cut results at the largest drop in score, relative to the best match, on top
+    /// of the fixed confidence threshold.
+    #[serde(default)]
+    pub adaptive_cutoff: bool,
+    /// Pick results by maximal marginal relevance, trading some relevance for
+    /// variety so near-duplicate notes don't fill the list. `--diverse` turns it on
+    /// for one search.
+    #[serde(default)]
+    pub diverse: bool,
+    /// How `diverse` weighs relevance against difference from the results already
+    /// picked, from 0 to 1. 1 is plain relevance ranking.
+    #[serde(default = "default_mmr_lambda")]
+    pub mmr_lambda: f32,
+    /// Per metadata field, whether it goes into the embedded text (`embed-context`),
+    /// is only stored for filtering (`filter-only`) or is dropped (`ignored`).
+    #[serde(default)]
+    pub fields: FieldRoles,
+    /// Queries the daemon embeds at startup, so their first search skips the model.
+    #[serde(default)]
+    pub preload_queries: Vec<String>,
+    /// Query vectors the daemon keeps, least recently used dropped first. 0 disables it.
+    #[serde(default = "default_query_cache_size")]
+    pub query_cache_size: usize,
+    /// Consecutive failures after which the daemon stops retrying a note until it
+    /// changes. 0 retries forever.
+    #[serde(default = "default_max_index_failures")]
+    pub max_index_failures: u32,
+    /// When a note changes, keep the vectors of its leading chunks that are
+    /// unchanged and embed only the rest. Saves work on append-only notes such as
+    /// journals and logs.
+    #[serde(default)]
+    pub reuse_unchanged_chunks: bool,
+    /// Search further when the nearest vectors include orphans (vectors whose chunk
+    /// metadata is gone), so index drift doesn't quietly cost results.
+    #[serde(default = "default_true")]
+    pub compensate_orphans: bool,
+    /// Cap on results from any one top-level folder, so a single project can't fill
+    /// the list; the next-best notes from other folders take their place. Off by default.
+    #[serde(default)]
+    pub max_per_folder: Option<usize>,
+    /// Tokens embedded per chunk, below the model's maximum for faster indexing at
+    /// the cost of some context. Defaults to the model's maximum.
+    #[serde(default)]
+    pub max_seq_len: Option<usize>,
+    /// Chunk notes section by section along their headings, each chunk labelled
+    /// with its heading breadcrumb, instead of by a plain character window.
+    #[serde(default)]
+    pub split_on_headings: bool,
+    /// Retries of a model download that failed in a way that may be temporary.
+    #[serde(default = "default_download_retries")]
+    pub download_retries: u32,
+    /// Milliseconds before the first download retry, doubling for each one after.
+    #[serde(default = "default_download_backoff_ms")]
+    pub download_backoff_ms: u64,
+    /// Named sets of search settings, picked with `--profile NAME`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, SearchProfile>,
+    /// The profile used when `--profile` isn't given.
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    /// Address the daemon's HTTP endpoint (`obra daemon --http PORT`) listens on.
+    /// Anyone who can reach it can read your notes' snippets, so only widen it
+    /// from localhost on a network you trust.
+    #[serde(default = "default_http_bind")]
+    pub http_bind: String,
+    /// Command `obra open` runs with the note's absolute path appended, like
+    /// `code -w` or `$EDITOR`; `$NAME` words are read from the environment.
+    /// Unset, the note opens in the system's default app for it.
+    #[serde(default)]
+    pub opener: Option<String>,
+}
+
+/// Search settings that override the config's own when the profile is selected.
+/// Settings a profile leaves out keep their configured values.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchProfile {
+    pub limit: Option<usize>,
+    pub fuzzy_fallback: Option<bool>,
+    pub max_candidates: Option<usize>,
+    pub chunks_per_file: Option<usize>,
+    pub exact_title_boost: Option<f32>,
+    pub recency_boost: Option<f32>,
+    pub link_boost: Option<f32>,
+    pub adaptive_cutoff: Option<bool>,
+    pub diverse: Option<bool>,
+    pub mmr_lambda: Option<f32>,
+    pub max_per_folder: Option<usize>,
+    pub min_words: Option<usize>,
+    pub score_threshold: Option<f32>,
+    pub mode: Option<SearchMode>,
+}
+
+impl SearchProfile {
+    pub fn apply(&self, options: &mut SearchOptions) {
+        options.limit = self.limit.unwrap_or(options.limit);
+        options.fuzzy_fallback = self.fuzzy_fallback.unwrap_or(options.fuzzy_fallback);
+        options.max_candidates = self.max_candidates.unwrap_or(options.max_candidates);
+        options.chunks_per_file = self.chunks_per_file.unwrap_or(options.chunks_per_file);
+        options.exact_title_boost = self.exact_title_boost.unwrap_or(options.exact_title_boost);
+        options.recency_boost = self.recency_boost.unwrap_or(options.recency_boost);
+        options.link_boost = self.link_boost.unwrap_or(options.link_boost);
+        options.adaptive_cutoff = self.adaptive_cutoff.unwrap_or(options.adaptive_cutoff);
+        options.diverse = self.diverse.unwrap_or(options.diverse);
+        options.mmr_lambda = self.mmr_lambda.unwrap_or(options.mmr_lambda);
+        options.max_per_folder = self.max_per_folder.or(options.max_per_folder);
+        options.min_words = self.min_words.or(options.min_words);
+        options.score_threshold = self.score_threshold.unwrap_or(options.score_threshold);
+        options.mode = self.mode.unwrap_or(options.mode);
+    }
+}
+
+impl Config {
+    /// A chunker with the configured sizes. Notes can still override them in their
+    /// frontmatter (see `ChunkOverrides`).
+    pub fn chunker(&self) -> Chunker {
+        Chunker {
+            chunk_size: self.chunk_size,
+            chunk_overlap: self.chunk_overlap,
+            min_chunk_chars: self.min_chunk_chars,
+            token_limit: None,
+        }
+    }
+
+    /// The configured search settings, then those of the profile named `profile`
+    /// (or of `default_profile`).
+    pub fn search_options(&self, profile: Option<&str>) -> Result<SearchOptions> {
+        let mut options = SearchOptions {
+            limit: self.max_results,
+            fuzzy_fallback: self.fuzzy_fallback,
+            max_candidates: self.max_candidates,
+            min_candidates: self.search_limit,
+            score_threshold: self.score_threshold,
+            chunks_per_file: self.chunks_per_file,
+            query_precision: self.query_precision,
+            exact_title_boost: self.exact_title_boost,
+            recency_boost: self.recency_boost,
+            recency_half_life_days: self.recency_half_life_days,
+            link_boost: self.link_boost,
+            adaptive_cutoff: self.adaptive_cutoff,
+            diverse: self.diverse,
+            mmr_lambda: self.mmr_lambda,
+            filters: Vec::new(),
+            compensate_orphans: self.compensate_orphans,
+            max_per_folder: self.max_per_folder,
+            ..SearchOptions::default()
+        };
+        if let Some(profile) = self.profile(profile)? {
+            profile.apply(&mut options);
+        }
+        Ok(options)
+    }
+
+    /// The profile named `name`, or else `default_profile`; `None` if neither is set.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<&SearchProfile>> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(Some(profile)),
+            None if self.profiles.is_empty() => {
+                anyhow::bail!("No profile '{}': the config defines no profiles", name)
+            }
+            None => anyhow::bail!(
+                "No profile '{}'; the config defines {}",
+                name,
+                self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+fn default_embed_batch_size() -> usize {
+    32
+}
+
+fn default_embed_concurrency() -> usize {
+    1
+}
+
+fn default_chunk_size() -> usize {
+    1000
+}
+
+fn default_chunk_overlap() -> usize {
+    200
+}
+
+fn default_min_chunk_chars() -> usize {
+    100
+}
+
+fn default_query_cache_size() -> usize {
+    256
+}
+
+fn default_model_id() -> String {
+    MODEL_ID.to_string()
+}
+
+fn default_save_interval_secs() -> u64 {
+    5
+}
+
+fn default_indexed_extensions() -> Vec<String> {
+    vec!["md".to_string()]
+}
+
+pub fn default_max_candidates() -> usize {
+    1000
+}
+
+pub fn default_search_limit() -> usize {
+    20
+}
+
+pub fn default_max_results() -> usize {
+    5
+}
+
+pub fn default_score_threshold() -> f32 {
+    1.2
+}
+
+pub fn default_chunks_per_file() -> usize {
+    3
+}
+
+fn default_auto_sync_hours() -> i64 {
+    24
+}
+
+fn default_score_precision() -> usize {
+    4
+}
+
+pub fn default_exact_title_boost() -> f32 {
+    3.0
+}
+
+pub fn default_mmr_lambda() -> f32 {
+    0.5
+}
+
+pub fn default_recency_half_life_days() -> f32 {
+    30.0
+}
+
+fn default_http_bind() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_max_index_failures() -> u32 {
+    3
+}
+
+fn default_min_prose_words() -> usize {
+    3
+}
+
+fn default_download_retries() -> u32 {
+    3
+}
+
+fn default_download_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vault_path: PathBuf::new(),
+            model_id: default_model_id(),
+            ignore_folders: Vec::new(),
+            indexed_extensions: default_indexed_extensions(),
+            save_interval_secs: default_save_interval_secs(),
+            embed_batch_size: default_embed_batch_size(),
+            embed_concurrency: default_embed_concurrency(),
+            fuzzy_fallback: false,
+            chunk_size: default_chunk_size(),
+            chunk_overlap: default_chunk_overlap(),
+            min_chunk_chars: default_min_chunk_chars(),
+            max_candidates: default_max_candidates(),
+            search_limit: default_search_limit(),
+            max_results: default_max_results(),
+            score_threshold: default_score_threshold(),
+            document_prefix: String::new(),
+            max_chunks: None,
+            chunks_per_file: default_chunks_per_file(),
+            auto_sync_hours: default_auto_sync_hours(),
+            code_blocks: CodeBlocks::default(),
+            thin_notes: ThinNotes::default(),
+            min_prose_words: default_min_prose_words(),
+            score_precision: default_score_precision(),
+            document_precision: VectorPrecision::default(),
+            query_precision: VectorPrecision::default(),
+            store_vectors: false,
+            log_queries: false,
+            exact_title_boost: default_exact_title_boost(),
+            recency_boost: 0.0,
+            recency_half_life_days: default_recency_half_life_days(),
+            link_boost: 0.0,
+            adaptive_cutoff: false,
+            diverse: false,
+            mmr_lambda: default_mmr_lambda(),
+            fields: FieldRoles::default(),
+            preload_queries: Vec::new(),
+            query_cache_size: default_query_cache_size(),
+            max_index_failures: default_max_index_failures(),
+            reuse_unchanged_chunks: false,
+            compensate_orphans: true,
+            max_per_folder: None,
+            max_seq_len: None,
+            split_on_headings: false,
+            download_retries: default_download_retries(),
+            download_backoff_ms: default_download_backoff_ms(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            http_bind: default_http_bind(),
+            opener: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -16,40 +462,96 @@ pub struct AppPaths {
     pub data_dir: PathBuf,
     pub config_file: PathBuf,
     pub log_file: PathBuf,
+    /// The named vault these paths belong to; `None` for the default one.
+    pub vault: Option<String>,
 }
 
 impl AppPaths {
-    pub fn from_env() -> Result<Self> {
+    /// The standard locations of the default vault or of the named `vault`, with the
+    /// data directory optionally overridden.
+    pub fn from_env(data_dir: Option<PathBuf>, vault: Option<&str>) -> Result<Self> {
         let config_dir = dirs::config_dir()
             .context("Could not find config directory")?
             .join("obra");
-        let data_dir = dirs::data_dir()
-            .context("Could not find data directory")?
-            .join("obra");
+        let Some(name) = vault else {
+            let data_dir = match data_dir {
+                Some(dir) => dir,
+                None => dirs::data_dir()
+                    .context("Could not find data directory")?
+                    .join("obra"),
+            };
+            return Self::new(config_dir, data_dir);
+        };
+        let data_dir = match data_dir {
+            Some(dir) => dir,
+            None => dirs::data_dir()
+                .context("Could not find data directory")?
+                .join("obra")
+                .join(name),
+        };
+        Self::named(config_dir, data_dir, name)
+    }
 
-        Self::new(config_dir, data_dir)
+    /// The paths of vault `name`: its config goes in `vaults/<name>/` under
+    /// `config_dir`, and its index in `data_dir`.
+    pub fn named(config_dir: PathBuf, data_dir: PathBuf, name: &str) -> Result<Self> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(valid) {
+            anyhow::bail!(
+                "Invalid vault name {:?}; use letters, digits, '-' and '_'",
+                name
+            );
+        }
+        let mut paths = Self::new(config_dir.join("vaults").join(name), data_dir)?;
+        paths.vault = Some(name.to_string());
+        Ok(paths)
     }
 
     pub fn new(config_dir: PathBuf, data_dir: PathBuf) -> Result<Self> {
-        fs::create_dir_all(&config_dir)?;
-        fs::create_dir_all(&data_dir)?;
+        ensure_writable(&config_dir, "Config", "fix its permissions")?;
+        ensure_writable(
+            &data_dir,
+            "Data",
+            "fix its permissions or use --data-dir <DIR>",
+        )?;
 
         Ok(Self {
             config_file: config_dir.join("config.json"),
             log_file: data_dir.join("daemon.log"),
             data_dir,
+            vault: None,
         })
     }
 }
 
+/// Creates `dir` if needed and checks a file can be written in it, so an unwritable
+/// location fails at startup with a clear message rather than partway through indexing.
+pub fn ensure_writable(dir: &Path, kind: &str, remedy: &str) -> Result<()> {
+    let probe = dir.join(".obra-write-test");
+    fs::create_dir_all(dir)
+        .and_then(|()| fs::write(&probe, b""))
+        .and_then(|()| fs::remove_file(&probe))
+        .with_context(|| format!("{} directory {:?} is not writable; {}", kind, dir, remedy))
+}
+
 pub fn load_config(paths: &AppPaths) -> Result<Config> {
     if !paths.config_file.exists() {
-        return Err(anyhow::anyhow!(
-            "Configuration not found. Please run `obra --init <VAULT_PATH>` first."
-        ));
+        return Err(ObraError::NotInitialized.into());
     }
     let content = fs::read_to_string(&paths.config_file)?;
     let config: Config = serde_json::from_str(&content)?;
+    VectorPrecision::check_combination(config.document_precision, config.query_precision)
+        .context("Invalid config")?;
+    if config.chunk_size == 0 {
+        anyhow::bail!("Invalid config: chunk_size must be at least 1");
+    }
+    if config.chunk_overlap >= config.chunk_size {
+        anyhow::bail!(
+            "Invalid config: chunk_overlap ({}) must be smaller than chunk_size ({})",
+            config.chunk_overlap,
+            config.chunk_size
+        );
+    }
     Ok(config)
 }
 
@@ -64,6 +566,38 @@ mod tests {
     use super::*;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_chunk_settings_come_from_the_config() -> Result<()> {
+        let config_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let paths = AppPaths::new(
+            config_dir.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+        )?;
+
+        // Left out, they keep the sizes obra always used.
+        fs::write(&paths.config_file, r#"{"vault_path": "/tmp/vault"}"#)?;
+        let chunker = load_config(&paths)?.chunker();
+        assert_eq!((chunker.chunk_size, chunker.chunk_overlap), (1000, 200));
+
+        fs::write(
+            &paths.config_file,
+            r#"{"vault_path": "/tmp/vault", "chunk_size": 300, "chunk_overlap": 50, "min_chunk_chars": 0}"#,
+        )?;
+        let chunker = load_config(&paths)?.chunker();
+        assert_eq!((chunker.chunk_size, chunker.chunk_overlap), (300, 50));
+        let spans = chunker.spans(&"x".repeat(700));
+        assert_eq!(spans, [(0, 300), (250, 550), (500, 700)]);
+
+        fs::write(
+            &paths.config_file,
+            r#"{"vault_path": "/tmp/vault", "chunk_size": 300, "chunk_overlap": 300}"#,
+        )?;
+        let err = load_config(&paths).unwrap_err().to_string();
+        assert!(err.contains("chunk_overlap (300) must be smaller than chunk_size (300)"));
+        Ok(())
+    }
+
     #[test]
     fn test_config_save_load() -> Result<()> {
         let config_dir = tempdir()?;
@@ -75,6 +609,7 @@ mod tests {
 
         let config = Config {
             vault_path: PathBuf::from("/tmp/vault"),
+            ..Default::default()
         };
 
         save_config(&paths, &config)?;
@@ -84,6 +619,83 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_unwritable_data_dir_is_reported() -> Result<()> {
+        let config_dir = tempdir()?;
+        let tmp = tempdir()?;
+        // A file where a directory should be can't be created or written into,
+        // whoever runs the test (permission bits don't stop root).
+        let blocker = tmp.path().join("blocker");
+        fs::write(&blocker, "")?;
+
+        let err = AppPaths::new(config_dir.path().to_path_buf(), blocker.join("obra")).unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("Data directory"));
+        assert!(message.contains("not writable"));
+        assert!(message.contains("--data-dir"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_old_config_gets_search_defaults() -> Result<()> {
+        let config: Config = serde_json::from_str(r#"{"vault_path": "/tmp/vault"}"#)?;
+        assert_eq!(config.search_limit, 20);
+        assert_eq!(config.max_results, 5);
+        assert_eq!(config.score_threshold, 1.2);
+        assert_eq!(config.model_id, MODEL_ID);
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_vaults_keep_separate_indexes() -> Result<()> {
+        use crate::db::{ChunkMeta, Database};
+
+        let config_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let vault_paths = |name: &str| {
+            AppPaths::named(
+                config_dir.path().to_path_buf(),
+                data_dir.path().join(name),
+                name,
+            )
+        };
+        let work = vault_paths("work")?;
+        let personal = vault_paths("personal")?;
+        assert_ne!(work.config_file, personal.config_file);
+        assert!(vault_paths("../escape").is_err());
+
+        for (paths, vault) in [(&work, "/vaults/work"), (&personal, "/vaults/home")] {
+            let config = Config {
+                vault_path: PathBuf::from(vault),
+                ..Default::default()
+            };
+            save_config(paths, &config)?;
+        }
+        assert_eq!(
+            load_config(&work)?.vault_path,
+            PathBuf::from("/vaults/work")
+        );
+        assert_eq!(
+            load_config(&personal)?.vault_path,
+            PathBuf::from("/vaults/home")
+        );
+
+        let mut db = Database::open(&work.data_dir)?;
+        let chunk = ChunkMeta {
+            path: "plan.md".into(),
+            filename: "plan".into(),
+            text: "quarterly plan".into(),
+            word_count: 2,
+            ..Default::default()
+        };
+        db.insert_chunks(vec![chunk], vec![vec![0.1; crate::db::VECTOR_DIM]])?;
+        db.save_now()?;
+
+        assert_eq!(Database::open(&work.data_dir)?.chunks.len(), 1);
+        assert!(Database::open(&personal.data_dir)?.chunks.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_load_nonexistent_config() -> Result<()> {
         let config_dir = tempdir()?;
@@ -0,0 +1,254 @@
+use crate::config::{ensure_writable, load_config, AppPaths, Config};
+use crate::db::Database;
+use crate::embeddings::missing_model_files;
+use crate::error::ObraError;
+use crate::index::IndexStatus;
+use crate::ipc::{send, Request};
+use std::fs;
+
+/// How one of `obra doctor`'s checks came out.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass(String),
+    /// Worth knowing, but nothing is broken.
+    Warn {
+        detail: String,
+        hint: String,
+    },
+    Fail {
+        detail: String,
+        hint: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Pass(detail.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Fail {
+                detail: detail.into(),
+                hint: hint.into(),
+            },
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        matches!(self.outcome, Outcome::Fail { .. })
+    }
+
+    /// The check as `obra doctor` prints it, with the hint on a second line.
+    pub fn report(&self) -> String {
+        match &self.outcome {
+            Outcome::Pass(detail) => format!("✅ {}: {}", self.name, detail),
+            Outcome::Warn { detail, hint } => {
+                format!("⚠️  {}: {}\n   → {}", self.name, detail, hint)
+            }
+            Outcome::Fail { detail, hint } => {
+                format!("❌ {}: {}\n   → {}", self.name, detail, hint)
+            }
+        }
+    }
+}
+
+/// Checks the setup piece by piece: config, vault, data dir, model download,
+/// daemon and index. Checks that need the config are skipped without one.
+pub fn run_checks(paths: &AppPaths) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let config = match load_config(paths) {
+        Ok(config) => {
+            checks.push(Check::pass(
+                "Config",
+                paths.config_file.display().to_string(),
+            ));
+            Some(config)
+        }
+        Err(e) if ObraError::find(&e) == Some(ObraError::NotInitialized) => {
+            checks.push(Check::fail(
+                "Config",
+                format!("{} not found", paths.config_file.display()),
+                "Run `obra init <vault path>`",
+            ));
+            None
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "Config",
+                format!("{:#}", e),
+                format!(
+                    "Fix {} or run `obra init` again",
+                    paths.config_file.display()
+                ),
+            ));
+            None
+        }
+    };
+
+    if let Some(config) = &config {
+        checks.push(check_vault(config));
+    }
+    checks.push(
+        match ensure_writable(&paths.data_dir, "Data", "fix its permissions") {
+            Ok(()) => Check::pass("Data dir", paths.data_dir.display().to_string()),
+            Err(e) => Check::fail(
+                "Data dir",
+                format!("{:#}", e),
+                "Fix its permissions or pass --data-dir",
+            ),
+        },
+    );
+    if let Some(config) = &config {
+        checks.push(check_model(config));
+    }
+    checks.push(check_daemon());
+    checks.push(check_index(paths));
+    checks
+}
+
+fn check_vault(config: &Config) -> Check {
+    let vault = &config.vault_path;
+    match fs::read_dir(vault) {
+        Ok(_) => Check::pass("Vault", vault.display().to_string()),
+        Err(e) => Check::fail(
+            "Vault",
+            format!("can't read {}: {}", vault.display(), e),
+            "Run `obra init` with the vault's current path",
+        ),
+    }
+}
+
+fn check_model(config: &Config) -> Check {
+    let missing = missing_model_files(&config.model_id);
+    if missing.is_empty() {
+        Check::pass("Model", format!("{} downloaded", config.model_id))
+    } else {
+        Check::fail(
+            "Model",
+            format!("{} is missing {}", config.model_id, missing.join(", ")),
+            "Run `obra model-info` while online to download it",
+        )
+    }
+}
+
+fn check_daemon() -> Check {
+    match send::<IndexStatus>(Request::Status) {
+        Ok(status) => Check::pass("Daemon", format!("running, {} notes indexed", status.files)),
+        Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => Check {
+            name: "Daemon",
+            outcome: Outcome::Warn {
+                detail: "not running".to_string(),
+                hint: "Start it with `obra daemon` for faster searches and live sync".to_string(),
+            },
+        },
+        Err(e) if ObraError::find(&e) == Some(ObraError::ProtocolMismatch) => Check::fail(
+            "Daemon",
+            "running, but from a different version of obra",
+            "Quit it from the tray and run `obra daemon` again",
+        ),
+        Err(e) => Check::fail(
+            "Daemon",
+            format!("not answering: {:#}", e),
+            "Restart it with `obra stop` and `obra daemon`",
+        ),
+    }
+}
+
+fn check_index(paths: &AppPaths) -> Check {
+    let db = match Database::open_unrepaired(&paths.data_dir) {
+        Ok(db) => db,
+        Err(e) => {
+            return Check::fail(
+                "Index",
+                format!("can't open it: {:#}", e),
+                "Run `obra --force` to rebuild it",
+            )
+        }
+    };
+    match db.verify() {
+        Ok(report) if report.is_consistent() && db.is_incomplete() => Check::fail(
+            "Index",
+            "notes were dropped to repair it and haven't been indexed again",
+            "Run `obra --index`",
+        ),
+        Ok(report) if report.is_consistent() => Check::pass(
+            "Index",
+            format!("{} chunks, all with a vector", db.chunks.len()),
+        ),
+        Ok(report) => Check::fail(
+            "Index",
+            format!(
+                "{} orphaned vectors, {} chunks without a vector",
+                report.orphaned_vectors.len(),
+                report.missing_vectors.len()
+            ),
+            "Run `obra verify --fix`",
+        ),
+        Err(e) => Check::fail(
+            "Index",
+            format!("can't check it: {:#}", e),
+            "Run `obra --force` to rebuild it",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::save_config;
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    fn outcome<'a>(checks: &'a [Check], name: &str) -> Option<&'a Outcome> {
+        checks.iter().find(|c| c.name == name).map(|c| &c.outcome)
+    }
+
+    #[test]
+    fn test_doctor_checks_config_and_vault() -> Result<()> {
+        let config_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let vault_dir = tempdir()?;
+        let paths = AppPaths::new(
+            config_dir.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+        )?;
+
+        // Without a config, the checks that need one are skipped.
+        let checks = run_checks(&paths);
+        assert!(checks[0].failed());
+        assert!(checks[0].report().contains("obra init"));
+        assert!(outcome(&checks, "Vault").is_none());
+        assert!(matches!(
+            outcome(&checks, "Data dir"),
+            Some(Outcome::Pass(_))
+        ));
+        assert!(matches!(outcome(&checks, "Index"), Some(Outcome::Pass(_))));
+
+        let config = Config {
+            vault_path: vault_dir.path().join("moved"),
+            ..Default::default()
+        };
+        save_config(&paths, &config)?;
+        let checks = run_checks(&paths);
+        assert!(!checks[0].failed());
+        assert!(matches!(
+            outcome(&checks, "Vault"),
+            Some(Outcome::Fail { .. })
+        ));
+
+        fs::write(&paths.config_file, "{ not json")?;
+        assert!(run_checks(&paths)[0].report().starts_with("❌ Config: "));
+        Ok(())
+    }
+}
@@ -1,41 +1,517 @@
-use crate::config::MODEL_ID;
-use anyhow::Result;
+use crate::chunker::TokenLimit;
+use crate::config::QUERY_INSTRUCTION;
+use crate::error::ObraError;
+use crate::search::cosine_similarity;
+use anyhow::{Context, Result};
 use candle_core::{Device, Tensor};
 use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config as BertConfig, DTYPE};
-use hf_hub::api::sync::Api;
-use tokenizers::Tokenizer;
+use hf_hub::api::sync::{ApiBuilder, ApiError};
+use hf_hub::api::Progress;
+use hf_hub::Cache;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashMap, VecDeque};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokenizers::{Tokenizer, TruncationParams};
+
+/// Anything that can turn text into normalized vectors. Indexing and search are
+/// written against this so they can run without loading the BERT model.
+pub trait Embedder: Send + Sync {
+    /// Embeds passages: the notes' chunks.
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>>;
+
+    /// Embeds search queries, which a model for asymmetric retrieval may embed
+    /// differently from passages.
+    fn embed_queries(&self, queries: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.embed(queries)
+    }
+
+    /// Embeds a search query.
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        self.embed_queries(vec![query.to_string()])?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Embedding returned no vector for the query"))
+    }
+
+    /// Cosine similarity of two texts, both embedded as passages. For evaluation
+    /// and debugging; 1.0 means the same meaning to the model.
+    fn similarity(&self, a: &str, b: &str) -> Result<f32> {
+        let vectors = self.embed(vec![a.to_string(), b.to_string()])?;
+        match vectors.as_slice() {
+            [va, vb] => Ok(cosine_similarity(va, vb)),
+            _ => anyhow::bail!("Embedding returned {} vectors for 2 texts", vectors.len()),
+        }
+    }
+
+    /// The most tokens a text can have before the model truncates it, for sizing
+    /// chunks. `None` if the embedder doesn't truncate.
+    fn token_limit(&self) -> Result<Option<TokenLimit>> {
+        Ok(None)
+    }
+}
+
+/// The normalized mean of `vectors`: one vector standing for a whole note.
+pub fn mean_pool(vectors: &[Vec<f32>]) -> Option<Vec<f32>> {
+    let first = vectors.first()?;
+    let mut mean = vec![0.0; first.len()];
+    for vector in vectors {
+        for (m, x) in mean.iter_mut().zip(vector) {
+            *m += x;
+        }
+    }
+    let norm = mean.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        mean.iter_mut().for_each(|x| *x /= norm);
+    }
+    Some(mean)
+}
+
+/// Cached vectors by normalized query, plus use order for evicting the least
+/// recently used.
+type QueryCache = (HashMap<String, Vec<f32>>, VecDeque<String>);
+
+/// The cache key of `query`. The tokenizer ignores surrounding and repeated
+/// whitespace, so queries differing only in that embed the same.
+fn cache_key(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Wraps an embedder with an LRU cache of query vectors, so an interactive client
+/// re-sending the same string (e.g. while a query is being typed) skips the model.
+/// Vectors never go stale: the model is fixed for the life of the cache.
+pub struct QueryCachedEmbedder<E> {
+    inner: E,
+    capacity: usize,
+    cache: Mutex<QueryCache>,
+}
+
+impl<E: Embedder> QueryCachedEmbedder<E> {
+    pub fn new(inner: E, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            cache: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+
+    /// Embeds `queries` in one batch and caches them, so their first search is as
+    /// fast as a repeat. Only the last `capacity` of them stay cached.
+    pub fn preload(&self, queries: &[String]) -> Result<()> {
+        if queries.is_empty() || self.capacity == 0 {
+            return Ok(());
+        }
+        let vectors = self.inner.embed_queries(queries.to_vec())?;
+        for (query, vector) in queries.iter().zip(&vectors) {
+            self.remember(query, vector);
+        }
+        Ok(())
+    }
+
+    fn remember(&self, query: &str, vector: &[f32]) {
+        let key = cache_key(query);
+        let (map, order) = &mut *self.cache.lock().unwrap();
+        if self.capacity > 0 && map.insert(key.clone(), vector.to_vec()).is_none() {
+            order.push_back(key);
+            if order.len() > self.capacity {
+                if let Some(oldest) = order.pop_front() {
+                    map.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    /// The cached vector of `query`, which then counts as the most recently used.
+    fn lookup(&self, query: &str) -> Option<Vec<f32>> {
+        let key = cache_key(query);
+        let (map, order) = &mut *self.cache.lock().unwrap();
+        let vector = map.get(&key)?.clone();
+        if let Some(i) = order.iter().position(|k| *k == key) {
+            order.remove(i);
+        }
+        order.push_back(key);
+        Some(vector)
+    }
+}
+
+impl<E: Embedder> Embedder for QueryCachedEmbedder<E> {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        self.inner.embed(texts)
+    }
+
+    /// Only the queries not cached are embedded, in one batch.
+    fn embed_queries(&self, queries: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let cached: Vec<Option<Vec<f32>>> = queries.iter().map(|q| self.lookup(q)).collect();
+        let missing: Vec<String> = queries
+            .iter()
+            .zip(&cached)
+            .filter(|(_, vector)| vector.is_none())
+            .map(|(query, _)| query.clone())
+            .collect();
+        let mut embedded = if missing.is_empty() {
+            Vec::new()
+        } else {
+            self.inner.embed_queries(missing.clone())?
+        };
+        for (query, vector) in missing.iter().zip(&embedded) {
+            self.remember(query, vector);
+        }
+
+        embedded.reverse();
+        Ok(cached
+            .into_iter()
+            .map(|vector| vector.or_else(|| embedded.pop()).unwrap_or_default())
+            .collect())
+    }
+
+    fn token_limit(&self) -> Result<Option<TokenLimit>> {
+        self.inner.token_limit()
+    }
+
+    fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        if let Some(vector) = self.lookup(query) {
+            return Ok(vector);
+        }
+
+        let vector = self.inner.embed_query(query)?;
+        self.remember(query, &vector);
+        Ok(vector)
+    }
+}
+
+/// What the loaded model looks like, for `obra model-info` and dimension checks.
+#[derive(Debug, Clone)]
+pub struct ModelInfo {
+    pub model_id: String,
+    pub dimension: usize,
+    pub device: &'static str,
+    pub max_seq_len: usize,
+}
+
+impl ModelInfo {
+    fn new(
+        model_id: &str,
+        config: &BertConfig,
+        tokenizer: Option<&Tokenizer>,
+        device: &Device,
+    ) -> Self {
+        let device = match device {
+            Device::Cpu => "CPU",
+            Device::Cuda(_) => "CUDA",
+            Device::Metal(_) => "Metal",
+        };
+        // The tokenizer's truncation setting is what actually bounds input;
+        // fall back to the model's position embedding count.
+        let max_seq_len = tokenizer
+            .and_then(|t| t.get_truncation())
+            .map(|t| t.max_length)
+            .unwrap_or(config.max_position_embeddings);
+
+        Self {
+            model_id: model_id.to_string(),
+            dimension: config.hidden_size,
+            device,
+            max_seq_len,
+        }
+    }
+}
+
+/// How often, and how patiently, model downloads are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadRetry {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+}
+
+impl Default for DownloadRetry {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Runs `fetch` until it succeeds, fails permanently, or runs out of retries.
+fn with_retries<T>(
+    retry: DownloadRetry,
+    file: &str,
+    mut fetch: impl FnMut() -> std::result::Result<T, ApiError>,
+) -> Result<T> {
+    let mut backoff = retry.backoff;
+    let mut attempt = 0;
+    loop {
+        match fetch() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retry.retries && is_transient(&e) => {
+                attempt += 1;
+                eprintln!(
+                    "⚠️  Downloading {} failed ({}); retry {}/{} in {:?}",
+                    file, e, attempt, retry.retries, backoff
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(e) => {
+                let unreachable = is_unreachable(&e);
+                let err = anyhow::Error::new(e).context(format!("Could not download {}", file));
+                return Err(if unreachable {
+                    err.context(ObraError::ModelUnavailable)
+                } else {
+                    err
+                });
+            }
+        }
+    }
+}
+
+/// Whether a download error may go away by itself, like a dropped connection or a
+/// server error, as opposed to e.g. a missing file or a denied request.
+fn is_transient(error: &ApiError) -> bool {
+    match error {
+        ApiError::RequestError(e) => match e.as_ref() {
+            ureq::Error::Status(code, _) => matches!(code, 408 | 429 | 500..),
+            ureq::Error::Transport(_) => true,
+        },
+        ApiError::TooManyRetries(e) => is_transient(e),
+        ApiError::IoError(_) | ApiError::LockAcquisition(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether a download failed for want of a connection rather than an answer.
+fn is_unreachable(error: &ApiError) -> bool {
+    match error {
+        ApiError::RequestError(e) => matches!(e.as_ref(), ureq::Error::Transport(_)),
+        ApiError::TooManyRetries(e) => is_unreachable(e),
+        _ => false,
+    }
+}
+
+/// Whether `HF_HUB_OFFLINE` is set, as for the Hugging Face tools: the model is
+/// then only read from the local cache, never downloaded.
+fn offline_mode() -> bool {
+    std::env::var("HF_HUB_OFFLINE")
+        .is_ok_and(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+}
+
+/// The device `OBRA_DEVICE` names, failing if it isn't available.
+fn device_named(name: &str) -> Result<Device> {
+    let device = match name.to_ascii_lowercase().as_str() {
+        "cpu" => return Ok(Device::Cpu),
+        "cuda" => Device::new_cuda(0),
+        "metal" => Device::new_metal(0),
+        _ => anyhow::bail!("Unknown OBRA_DEVICE '{}'; use cpu, cuda or metal", name),
+    };
+    device.with_context(|| format!("OBRA_DEVICE={} but that device isn't available", name))
+}
+
+/// The device set by `OBRA_DEVICE`, or else the first GPU that works: Metal, or
+/// CUDA in builds with the `cuda` feature. Falls back to the CPU.
+fn select_device() -> Result<Device> {
+    if let Ok(name) = std::env::var("OBRA_DEVICE") {
+        return device_named(&name);
+    }
+    let cuda = || {
+        cfg!(feature = "cuda")
+            .then(|| Device::new_cuda(0).ok())
+            .flatten()
+    };
+    Ok(Device::new_metal(0)
+        .ok()
+        .or_else(cuda)
+        .unwrap_or(Device::Cpu))
+}
+
+/// The files `EmbeddingEngine::load` fetches from the model's repo.
+const MODEL_FILES: [&str; 3] = ["config.json", "tokenizer.json", "model.safetensors"];
+
+/// Which of the files `EmbeddingEngine::load` needs for `model_id` haven't been
+/// downloaded to the Hugging Face cache yet.
+pub fn missing_model_files(model_id: &str) -> Vec<&'static str> {
+    let repo = Cache::default().model(model_id.to_string());
+    MODEL_FILES
+        .into_iter()
+        .filter(|file| repo.get(file).is_none())
+        .collect()
+}
+
+/// A download progress bar on stderr, labelled with the model and file. Its
+/// length is only known once hf-hub starts the download.
+struct DownloadBar {
+    bar: ProgressBar,
+    model_id: String,
+}
+
+impl Progress for DownloadBar {
+    fn init(&mut self, size: usize, filename: &str) {
+        self.bar.set_length(size as u64);
+        self.bar.set_position(0);
+        self.bar
+            .set_message(format!("Downloading {} {}", self.model_id, filename));
+    }
+
+    fn update(&mut self, size: usize) {
+        self.bar.inc(size as u64);
+    }
+
+    fn finish(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+/// Whether to draw progress bars: only on a terminal, so piped output stays clean.
+fn show_progress() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+fn download_bar(model_id: &str) -> DownloadBar {
+    let style = ProgressStyle::with_template(
+        "{msg} [{wide_bar}] {bytes}/{total_bytes} {bytes_per_sec} ({eta})",
+    )
+    .expect("valid progress template")
+    .progress_chars("=> ");
+    DownloadBar {
+        bar: ProgressBar::new(0).with_style(style),
+        model_id: model_id.to_string(),
+    }
+}
+
+/// A spinner on stderr while the model is read into memory, so a long pause after
+/// the download isn't mistaken for a hang. Hidden off a terminal.
+fn loading_spinner(model_id: &str) -> ProgressBar {
+    if !show_progress() {
+        return ProgressBar::hidden();
+    }
+    let spinner =
+        ProgressBar::new_spinner().with_message(format!("Loading {} into memory", model_id));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+/// Paths of `MODEL_FILES` for `model_id` in the Hugging Face cache, downloading
+/// those that aren't there yet unless `offline`. Downloads show a progress bar on
+/// a terminal; cached files are used without one.
+fn model_files(model_id: &str, retry: DownloadRetry, offline: bool) -> Result<Vec<PathBuf>> {
+    if offline {
+        let missing = missing_model_files(model_id);
+        if !missing.is_empty() {
+            return Err(anyhow::Error::new(ObraError::ModelUnavailable).context(format!(
+                "HF_HUB_OFFLINE is set and {} isn't cached (missing {}); unset it to download the model",
+                model_id,
+                missing.join(", ")
+            )));
+        }
+        let repo = Cache::default().model(model_id.to_string());
+        return Ok(MODEL_FILES
+            .iter()
+            .filter_map(|file| repo.get(file))
+            .collect());
+    }
+
+    let cache = Cache::default().model(model_id.to_string());
+    let api = ApiBuilder::new().with_progress(false).build()?;
+    let repo = api.model(model_id.to_string());
+    let fetch = |file: &str| match cache.get(file) {
+        Some(path) => Ok(path),
+        None if show_progress() => repo.download_with_progress(file, download_bar(model_id)),
+        None => repo.download(file),
+    };
+    MODEL_FILES
+        .iter()
+        .map(|file| {
+            with_retries(retry, file, || fetch(file)).map_err(|e| {
+                if ObraError::find(&e) == Some(ObraError::ModelUnavailable) {
+                    e.context(format!(
+                        "Model {} isn't cached and there's no network to download it; connect once to fetch it",
+                        model_id
+                    ))
+                } else {
+                    e
+                }
+            })
+        })
+        .collect()
+}
+
+/// Downloads the files of `model_id` that aren't cached yet, without loading it.
+pub fn download_model(model_id: &str, retry: DownloadRetry) -> Result<()> {
+    model_files(model_id, retry, offline_mode()).map(|_| ())
+}
 
 pub struct EmbeddingEngine {
     model: BertModel,
     tokenizer: Tokenizer,
     device: Device,
+    info: ModelInfo,
+    /// Put before each search query; see `query_instruction`.
+    query_instruction: &'static str,
 }
 
-impl EmbeddingEngine {
-    pub fn new() -> Result<Self> {
-        let device = Device::new_metal(0).unwrap_or(Device::Cpu);
-        let api = Api::new()?;
-        let repo = api.model(MODEL_ID.to_string());
+/// The instruction `model_id` expects before search queries. Only the English BGE
+/// models are known to want one; others embed queries as they are.
+fn query_instruction(model_id: &str) -> &'static str {
+    let bge_english = model_id.starts_with("BAAI/bge-") && model_id.contains("-en");
+    if bge_english {
+        QUERY_INSTRUCTION
+    } else {
+        ""
+    }
+}
 
-        let config_path = repo.get("config.json")?;
-        let tokenizer_path = repo.get("tokenizer.json")?;
-        let weights_path = repo.get("model.safetensors")?;
+impl EmbeddingEngine {
+    /// Loads the BERT model `model_id` from Hugging Face, downloading any files not
+    /// yet cached (unless `HF_HUB_OFFLINE` is set) and retrying transient download
+    /// failures as `retry` says. Its dimension comes from the model's own config.
+    pub fn load(model_id: &str, retry: DownloadRetry) -> Result<Self> {
+        let device = select_device()?;
+        let downloading = !missing_model_files(model_id).is_empty();
+        let [config_path, tokenizer_path, weights_path]: [PathBuf; 3] =
+            model_files(model_id, retry, offline_mode())?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Model {} is missing files", model_id))?;
 
         let config: BertConfig = serde_json::from_str(&std::fs::read_to_string(config_path)?)?;
         let tokenizer = Tokenizer::from_file(tokenizer_path)
             .map_err(|e| anyhow::anyhow!("Tokenizer error: {e}"))?;
 
+        let spinner = if downloading {
+            loading_spinner(model_id)
+        } else {
+            ProgressBar::hidden()
+        };
         let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_path], DTYPE, &device)? };
         let model = BertModel::load(vb, &config)?;
+        spinner.finish_and_clear();
+        let info = ModelInfo::new(model_id, &config, Some(&tokenizer), &device);
 
         Ok(Self {
             model,
             tokenizer,
             device,
+            info,
+            query_instruction: query_instruction(model_id),
         })
     }
 
+    /// Caps the tokens embedded per text below the model's maximum, trading some
+    /// context for speed. `None`, or a cap above the model's, changes nothing.
+    pub fn with_max_seq_len(mut self, max_seq_len: Option<usize>) -> Result<Self> {
+        if let Some(max_seq_len) = max_seq_len {
+            self.info.max_seq_len =
+                limit_truncation(&mut self.tokenizer, max_seq_len, self.info.max_seq_len)?;
+        }
+        Ok(self)
+    }
+
+    pub fn info(&self) -> &ModelInfo {
+        &self.info
+    }
+
     pub fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
         if texts.is_empty() {
             return Ok(Vec::new());
@@ -94,14 +570,394 @@ impl EmbeddingEngine {
         let masked_output = output.broadcast_mul(&mask_expanded)?;
         let sum_emb = masked_output.sum(1)?; // [batch_size, hidden_size]
         
-        let sum_mask = mask_expanded.sum(1)?; // [batch_size, 1]
+        // At least 1, so a text with no tokens to attend to can't divide by zero.
+        let sum_mask = mask_expanded.sum(1)?.maximum(1.0)?; // [batch_size, 1]
         let mean_emb = sum_emb.broadcast_div(&sum_mask)?;
 
-        // L2 normalize: [batch_size, hidden_size]
-        let norm = mean_emb.sqr()?.sum_keepdim(1)?.sqrt()?;
-        let normalized = mean_emb.broadcast_div(&norm)?;
+        let normalized = l2_normalize(&mean_emb)?;
 
         let results_vec = normalized.to_vec2::<f32>()?;
         Ok(results_vec)
     }
 }
+
+/// Scales each row of `[batch_size, hidden_size]` to unit length. A row of zeros
+/// stays zeros rather than turning into NaNs.
+fn l2_normalize(embeddings: &Tensor) -> Result<Tensor> {
+    let norm = embeddings
+        .sqr()?
+        .sum_keepdim(1)?
+        .sqrt()?
+        .maximum(f32::MIN_POSITIVE as f64)?;
+    Ok(embeddings.broadcast_div(&norm)?)
+}
+
+/// Makes `tokenizer` truncate to `max_seq_len` tokens, never beyond `model_max`.
+/// Returns the limit now in effect.
+fn limit_truncation(
+    tokenizer: &mut Tokenizer,
+    max_seq_len: usize,
+    model_max: usize,
+) -> Result<usize> {
+    if max_seq_len == 0 {
+        anyhow::bail!("max_seq_len must be at least 1");
+    }
+    let max_length = max_seq_len.min(model_max);
+    let params = TruncationParams {
+        max_length,
+        ..tokenizer.get_truncation().cloned().unwrap_or_default()
+    };
+    tokenizer
+        .with_truncation(Some(params))
+        .map_err(|e| anyhow::anyhow!("Tokenizer error: {e}"))?;
+    Ok(max_length)
+}
+
+impl Embedder for EmbeddingEngine {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        EmbeddingEngine::embed(self, texts)
+    }
+
+    /// BGE retrieves best with its instruction before the query.
+    fn embed_queries(&self, queries: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let texts = queries
+            .into_iter()
+            .map(|query| format!("{}{}", self.query_instruction, query))
+            .collect();
+        EmbeddingEngine::embed(self, texts)
+    }
+
+    fn token_limit(&self) -> Result<Option<TokenLimit>> {
+        TokenLimit::new(&self.tokenizer, self.info.max_seq_len).map(Some)
+    }
+}
+
+/// Deterministic bag-of-words embedder for tests: each word is hashed into one
+/// dimension, so texts sharing words are close and call counts can be asserted.
+#[cfg(test)]
+pub mod testing {
+    use super::Embedder;
+    use crate::db::VECTOR_DIM;
+    use anyhow::Result;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    pub struct MockEmbedder {
+        pub calls: AtomicUsize,
+        pub batches: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl MockEmbedder {
+        pub fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+
+        pub fn vector(text: &str) -> Vec<f32> {
+            let mut vec = vec![0.0; VECTOR_DIM];
+            for word in text.to_lowercase().split(|c: char| !c.is_alphanumeric()) {
+                if word.is_empty() {
+                    continue;
+                }
+                let hash = word.bytes().fold(0xcbf29ce484222325u64, |h, b| {
+                    (h ^ b as u64).wrapping_mul(0x100000001b3)
+                });
+                vec[(hash % VECTOR_DIM as u64) as usize] += 1.0;
+            }
+            let norm = vec.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm == 0.0 {
+                vec[0] = 1.0;
+            } else {
+                vec.iter_mut().for_each(|x| *x /= norm);
+            }
+            vec
+        }
+    }
+
+    impl Embedder for MockEmbedder {
+        fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.batches.lock().unwrap().push(texts.clone());
+            Ok(texts.iter().map(|t| Self::vector(t)).collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::MockEmbedder;
+    use super::*;
+    use crate::config::MODEL_ID;
+    use crate::db::Database;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_transient_download_failures_are_retried() -> Result<()> {
+        let retry = DownloadRetry {
+            retries: 3,
+            backoff: Duration::ZERO,
+        };
+        let reset = || ApiError::IoError(std::io::ErrorKind::ConnectionReset.into());
+
+        let mut calls = 0;
+        let path = with_retries(retry, "config.json", || {
+            calls += 1;
+            if calls < 3 {
+                Err(reset())
+            } else {
+                Ok("config.json")
+            }
+        })?;
+        assert_eq!((path, calls), ("config.json", 3));
+
+        // Out of retries.
+        let mut calls = 0;
+        let result: Result<()> = with_retries(retry, "config.json", || {
+            calls += 1;
+            Err(reset())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 4);
+
+        // A 404 won't fix itself, so it isn't retried.
+        let mut calls = 0;
+        let result: Result<()> = with_retries(retry, "config.json", || {
+            calls += 1;
+            let response = ureq::Response::new(404, "Not Found", "").unwrap();
+            Err(Box::new(ureq::Error::Status(404, response)).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_model_is_a_clear_error() {
+        let model_id = "obra-test/no-such-model";
+        let err = model_files(model_id, DownloadRetry::default(), true).unwrap_err();
+        assert_eq!(ObraError::find(&err), Some(ObraError::ModelUnavailable));
+        assert!(err.to_string().contains(model_id));
+        assert!(err.to_string().contains("HF_HUB_OFFLINE"));
+
+        // No connection at all is told apart from a refused download.
+        let retry = DownloadRetry {
+            retries: 0,
+            backoff: Duration::ZERO,
+        };
+        let offline = || {
+            let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+            Err(Box::new(ureq::Error::from(refused)).into())
+        };
+        let err = with_retries::<()>(retry, "config.json", offline).unwrap_err();
+        assert_eq!(ObraError::find(&err), Some(ObraError::ModelUnavailable));
+        let denied = || {
+            let response = ureq::Response::new(401, "Unauthorized", "").unwrap();
+            Err(Box::new(ureq::Error::Status(401, response)).into())
+        };
+        let err = with_retries::<()>(retry, "config.json", denied).unwrap_err();
+        assert_eq!(ObraError::find(&err), None);
+    }
+
+    #[test]
+    fn test_normalizing_a_zero_vector_keeps_it_finite() -> Result<()> {
+        let rows = Tensor::new(&[[3.0f32, 4.0], [0.0, 0.0]], &Device::Cpu)?;
+        let normalized = l2_normalize(&rows)?.to_vec2::<f32>()?;
+        assert_eq!(normalized, [[0.6, 0.8], [0.0, 0.0]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_bar_is_labelled_and_restarts_with_each_attempt() {
+        let mut progress = download_bar("BAAI/bge-small-en-v1.5");
+        progress.init(1000, "model.safetensors");
+        progress.update(400);
+        assert_eq!(progress.bar.position(), 400);
+        assert_eq!(progress.bar.length(), Some(1000));
+        assert_eq!(
+            progress.bar.message(),
+            "Downloading BAAI/bge-small-en-v1.5 model.safetensors"
+        );
+
+        // A retried download starts the bar over rather than adding to it.
+        progress.init(1000, "model.safetensors");
+        assert_eq!(progress.bar.position(), 0);
+        progress.finish();
+        assert!(progress.bar.is_finished());
+    }
+
+    #[test]
+    fn test_only_english_bge_models_get_the_query_instruction() {
+        assert_eq!(query_instruction(MODEL_ID), QUERY_INSTRUCTION);
+        assert_eq!(
+            query_instruction("BAAI/bge-base-en-v1.5"),
+            QUERY_INSTRUCTION
+        );
+        assert_eq!(query_instruction("BAAI/bge-m3"), "");
+        assert_eq!(
+            query_instruction("sentence-transformers/all-MiniLM-L6-v2"),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_device_names() {
+        assert!(matches!(device_named("CPU"), Ok(Device::Cpu)));
+        let err = device_named("tpu").unwrap_err();
+        assert!(err.to_string().contains("cpu, cuda or metal"));
+        if !cfg!(feature = "cuda") {
+            assert!(device_named("cuda").is_err());
+        }
+    }
+
+    #[test]
+    fn test_model_info_dimension_matches_index() -> Result<()> {
+        // Trimmed from BAAI/bge-small-en-v1.5's config.json.
+        let config: BertConfig = serde_json::from_str(
+            r#"{
+                "vocab_size": 30522,
+                "hidden_size": 384,
+                "num_hidden_layers": 12,
+                "num_attention_heads": 12,
+                "intermediate_size": 1536,
+                "hidden_act": "gelu",
+                "hidden_dropout_prob": 0.1,
+                "max_position_embeddings": 512,
+                "type_vocab_size": 2,
+                "initializer_range": 0.02,
+                "layer_norm_eps": 1e-12,
+                "pad_token_id": 0,
+                "classifier_dropout": null,
+                "model_type": "bert"
+            }"#,
+        )?;
+        let info = ModelInfo::new(MODEL_ID, &config, None, &Device::Cpu);
+
+        let tmp = tempdir()?;
+        let db = Database::open(tmp.path())?;
+        assert_eq!(info.dimension, db.index.dimensions());
+        assert_eq!(info.max_seq_len, 512);
+        assert_eq!(info.device, "CPU");
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_seq_len_truncates_input() -> Result<()> {
+        use tokenizers::models::wordlevel::WordLevel;
+        use tokenizers::pre_tokenizers::whitespace::Whitespace;
+
+        let vocab: HashMap<String, u32> = [("[UNK]", 0), ("word", 1)]
+            .into_iter()
+            .map(|(token, id)| (token.to_string(), id))
+            .collect();
+        let model = WordLevel::builder()
+            .vocab(vocab.into_iter().collect())
+            .unk_token("[UNK]".into())
+            .build()
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace {}));
+        let text = "word ".repeat(50);
+        let encode = |t: &Tokenizer| t.encode(text.as_str(), true).unwrap().get_ids().len();
+        assert_eq!(encode(&tokenizer), 50);
+
+        assert_eq!(limit_truncation(&mut tokenizer, 16, 512)?, 16);
+        assert_eq!(encode(&tokenizer), 16);
+
+        // Never beyond what the model supports.
+        assert_eq!(limit_truncation(&mut tokenizer, 4096, 32)?, 32);
+        assert_eq!(encode(&tokenizer), 32);
+        assert!(limit_truncation(&mut tokenizer, 0, 32).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_similarity_of_text_pairs() -> Result<()> {
+        let engine = MockEmbedder::default();
+        let same = engine.similarity("sourdough starter", "sourdough starter")?;
+        assert!((same - 1.0).abs() < 1e-5);
+
+        let related = engine.similarity("sourdough starter", "sourdough bread")?;
+        let unrelated = engine.similarity("sourdough starter", "tax return")?;
+        assert!(related < same);
+        assert!(unrelated < related);
+        assert_eq!(engine.calls(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_embedding_differs_from_passage() -> Result<()> {
+        let engine = EmbeddingEngine::load(MODEL_ID, DownloadRetry::default())?;
+        let text = "how to keep a sourdough starter alive";
+        let query = engine.embed_query(text)?;
+        let passage = Embedder::embed(&engine, vec![text.to_string()])?.remove(0);
+        assert_ne!(query, passage);
+        // Still the same meaning to the model.
+        assert!(cosine_similarity(&query, &passage) > 0.7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_cache_hits_on_repeat() -> Result<()> {
+        let engine = QueryCachedEmbedder::new(MockEmbedder::default(), 2);
+
+        let first = engine.embed_query("sourdough starter")?;
+        let second = engine.embed_query("sourdough starter")?;
+        assert_eq!(first, second);
+        assert_eq!(engine.inner.calls(), 1);
+
+        // Bounded: older entries are evicted once capacity is exceeded.
+        engine.embed_query("rye")?;
+        engine.embed_query("spelt")?;
+        engine.embed_query("sourdough starter")?;
+        assert_eq!(engine.inner.calls(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_cache_evicts_least_recently_used() -> Result<()> {
+        let engine = QueryCachedEmbedder::new(MockEmbedder::default(), 2);
+        engine.embed_query("rye")?;
+        engine.embed_query("spelt")?;
+        // Using rye again makes spelt the one to go.
+        engine.embed_query("  rye ")?;
+        engine.embed_query("emmer")?;
+        assert_eq!(engine.inner.calls(), 3);
+        engine.embed_query("rye")?;
+        assert_eq!(engine.inner.calls(), 3);
+        engine.embed_query("spelt")?;
+        assert_eq!(engine.inner.calls(), 4);
+
+        // A batch embeds only what isn't cached, and keeps the order.
+        let queries = vec!["spelt".to_string(), "einkorn".to_string()];
+        let vectors = engine.embed_queries(queries)?;
+        assert_eq!(engine.inner.calls(), 5);
+        assert_eq!(
+            engine.inner.batches.lock().unwrap().last().unwrap(),
+            &vec!["einkorn".to_string()]
+        );
+        assert_eq!(
+            vectors,
+            vec![
+                MockEmbedder::vector("spelt"),
+                MockEmbedder::vector("einkorn")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_preloaded_queries_are_cached() -> Result<()> {
+        let engine = QueryCachedEmbedder::new(MockEmbedder::default(), 8);
+        let queries = vec!["meeting notes".to_string(), "reading list".to_string()];
+        engine.preload(&queries)?;
+        assert_eq!(engine.inner.calls(), 1);
+
+        assert_eq!(
+            engine.embed_query("reading list")?,
+            MockEmbedder::vector("reading list")
+        );
+        engine.embed_query("meeting notes")?;
+        assert_eq!(engine.inner.calls(), 1);
+        Ok(())
+    }
+}
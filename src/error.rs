@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Conditions callers need to tell apart, whichever transport reports them.
+///
+/// Everything else stays an ad-hoc `anyhow` error. These travel inside `anyhow`
+/// too and are recovered with `ObraError::find`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObraError {
+    /// No config file: `obra init` hasn't been run.
+    NotInitialized,
+    /// The index has no chunks to search.
+    EmptyIndex,
+    /// No daemon is listening on the IPC socket.
+    DaemonUnavailable,
+    /// The index was built with a different embedding model than the configured one.
+    ModelMismatch,
+    /// The daemon and the CLI speak different versions of the IPC protocol.
+    ProtocolMismatch,
+    /// The embedding model isn't in the local cache and can't be downloaded.
+    ModelUnavailable,
+    /// The search query is empty or only whitespace.
+    EmptyQuery,
+    /// The index stores vectors too coarse to convert to the configured precision.
+    PrecisionMismatch,
+    /// `obra verify` found the index and chunk metadata disagree.
+    IndexInconsistent,
+    /// One of `obra doctor`'s checks failed.
+    CheckFailed,
+}
+
+impl ObraError {
+    /// Process exit code for the CLI. 1 is left for generic failures.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ObraError::NotInitialized => 3,
+            ObraError::EmptyIndex => 4,
+            ObraError::DaemonUnavailable => 5,
+            ObraError::ModelMismatch => 6,
+            ObraError::ProtocolMismatch => 7,
+            ObraError::ModelUnavailable => 8,
+            ObraError::EmptyQuery => 9,
+            ObraError::PrecisionMismatch => 10,
+            ObraError::IndexInconsistent => 11,
+            ObraError::CheckFailed => 12,
+        }
+    }
+
+    pub fn http_status(self) -> u16 {
+        match self {
+            ObraError::NotInitialized => 404,
+            ObraError::EmptyIndex => 409,
+            ObraError::DaemonUnavailable => 503,
+            ObraError::ModelMismatch => 409,
+            ObraError::ProtocolMismatch => 400,
+            ObraError::ModelUnavailable => 503,
+            ObraError::EmptyQuery => 400,
+            ObraError::PrecisionMismatch => 409,
+            ObraError::IndexInconsistent => 409,
+            ObraError::CheckFailed => 500,
+        }
+    }
+
+    /// Looks for an `ObraError` anywhere in `err`, whether it was the source or
+    /// attached as context.
+    pub fn find(err: &anyhow::Error) -> Option<ObraError> {
+        err.downcast_ref::<ObraError>()
+            .or_else(|| err.chain().find_map(|e| e.downcast_ref::<ObraError>()))
+            .copied()
+    }
+}
+
+impl fmt::Display for ObraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObraError::NotInitialized => write!(
+                f,
+                "Configuration not found. Please run `obra init <VAULT_PATH>` first."
+            ),
+            ObraError::EmptyIndex => write!(
+                f,
+                "The index is empty. Run `obra --index` to index your vault."
+            ),
+            ObraError::DaemonUnavailable => write!(f, "The obra daemon is not running."),
+            ObraError::ModelMismatch => write!(f, "Model changed, run `obra --force` to re-index."),
+            ObraError::ProtocolMismatch => write!(
+                f,
+                "The obra daemon is from a different version of obra. Quit it from the tray and run `obra daemon` again."
+            ),
+            ObraError::ModelUnavailable => write!(
+                f,
+                "The embedding model isn't downloaded and can't be fetched. Connect to the internet once to download it."
+            ),
+            ObraError::EmptyQuery => write!(f, "The search query is empty."),
+            ObraError::PrecisionMismatch => write!(
+                f,
+                "The index's vectors are too coarse for the configured precision, run `obra --force` to re-embed them."
+            ),
+            ObraError::IndexInconsistent => write!(
+                f,
+                "The index and chunk metadata disagree. Run `obra verify --fix` to reconcile."
+            ),
+            ObraError::CheckFailed => write!(f, "Some checks failed; see above for how to fix them."),
+        }
+    }
+}
+
+impl std::error::Error for ObraError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{load_config, AppPaths};
+    use crate::ipc::{into_results, SearchResponse};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_not_initialized_across_transports() -> anyhow::Result<()> {
+        let config_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let paths = AppPaths::new(
+            config_dir.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+        )?;
+
+        // CLI
+        let err = load_config(&paths).unwrap_err();
+        let kind = ObraError::find(&err);
+        assert_eq!(kind, Some(ObraError::NotInitialized));
+        assert_eq!(kind.unwrap().exit_code(), 3);
+
+        // IPC: the error survives the JSON round trip as the same variant.
+        let resp = SearchResponse::from_result(Err(err));
+        let wire = serde_json::to_string(&resp)?;
+        assert!(wire.contains("not_initialized"));
+        let err = into_results(serde_json::from_str(&wire)?).unwrap_err();
+        assert_eq!(ObraError::find(&err), Some(ObraError::NotInitialized));
+
+        // HTTP
+        assert_eq!(ObraError::NotInitialized.http_status(), 404);
+        Ok(())
+    }
+}
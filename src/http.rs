@@ -0,0 +1,259 @@
+use crate::index::SyncManager;
+use crate::ipc::{serving_search, SearchResponse};
+use crate::search::run_search;
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Threads answering HTTP requests; more requests wait their turn.
+const HTTP_WORKERS: usize = 4;
+
+/// Serves `GET /search?q=...&limit=...&offset=...` on `bind:port`, answering with the JSON of
+/// a `SearchResponse` as the IPC server does. Returns the address it listens on.
+pub fn start_http_server(manager: Arc<SyncManager>, bind: &str, port: u16) -> Result<SocketAddr> {
+    let server = Server::http((bind, port))
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}:{}: {}", bind, port, e))?;
+    let addr = server
+        .server_addr()
+        .to_ip()
+        .ok_or_else(|| anyhow::anyhow!("{}:{} is not an IP address", bind, port))?;
+
+    println!("🌐 HTTP search listening on http://{}/search", addr);
+
+    let server = Arc::new(server);
+    for _ in 0..HTTP_WORKERS {
+        let server = server.clone();
+        let manager = manager.clone();
+        let bind = bind.to_string();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if let Err(e) = handle_request(request, &manager, &bind) {
+                    eprintln!("❌ Error handling HTTP request: {}", e);
+                }
+            }
+        });
+    }
+
+    Ok(addr)
+}
+
+fn handle_request(request: Request, manager: &Arc<SyncManager>, bind: &str) -> Result<()> {
+    let host = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Host"))
+        .map(|h| h.value.as_str());
+    let (status, resp) = if host_allowed(host, bind) {
+        respond(request.method(), request.url(), manager)
+    } else {
+        // A web page can point its own domain at 127.0.0.1 (DNS rebinding), but
+        // its requests still carry that domain as the Host.
+        let message = format!("Host {:?} isn't allowed; use localhost", host.unwrap_or(""));
+        (
+            403,
+            SearchResponse::from_result(Err(anyhow::anyhow!(message))),
+        )
+    };
+    let json = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+        .map_err(|()| anyhow::anyhow!("Invalid header"))?;
+    let body = serde_json::to_vec(&resp)?;
+    request.respond(
+        Response::from_data(body)
+            .with_status_code(status)
+            .with_header(json),
+    )?;
+
+    // After answering, so the search itself never waits on the sync.
+    if status == 200 {
+        manager.sync_if_stale();
+    }
+    Ok(())
+}
+
+/// The status code and body for a request to `url`. Failures carry their
+/// `ObraError` or message in the body, as they do over IPC.
+fn respond(method: &Method, url: &str, manager: &SyncManager) -> (u16, SearchResponse) {
+    let failure = |status, message: String| {
+        (
+            status,
+            SearchResponse::from_result(Err(anyhow::anyhow!(message))),
+        )
+    };
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    if path != "/search" {
+        return failure(404, format!("No endpoint at {}; use /search?q=...", path));
+    }
+    if *method != Method::Get {
+        return failure(405, "Only GET is supported".to_string());
+    }
+    let Some(q) = query_param(query, "q").filter(|q| !q.trim().is_empty()) else {
+        return failure(400, "Missing the query: /search?q=...".to_string());
+    };
+    let mut options = match manager.config.search_options(None) {
+        Ok(options) => options,
+        Err(e) => return failure(500, format!("{:#}", e)),
+    };
+    for (name, value) in [
+        ("limit", &mut options.limit),
+        ("offset", &mut options.offset),
+    ] {
+        if let Some(param) = query_param(query, name) {
+            match param.parse() {
+                Ok(parsed) => *value = parsed,
+                Err(_) => return failure(400, format!("Invalid {} '{}'", name, param)),
+            }
+        }
+    }
+
+    let _serving = serving_search();
+    let matches = match manager.db.lock() {
+        Ok(db) => run_search(&q, &db, manager.engine.as_ref(), &options),
+        Err(_) => Err(anyhow::anyhow!("DB Lock failed")),
+    };
+    let mut resp = SearchResponse::from_matches(matches, false);
+    resp.last_sync = *manager.last_sync_time.lock().unwrap();
+    let status = match (resp.error, &resp.message) {
+        (Some(error), _) => error.http_status(),
+        (None, Some(_)) => 500,
+        (None, None) => 200,
+    };
+    (status, resp)
+}
+
+/// Whether a request for `host` (the Host header, with or without a port) is for
+/// this server itself: `localhost`, `127.0.0.1` or the address it's bound to.
+fn host_allowed(host: Option<&str>, bind: &str) -> bool {
+    let Some(host) = host else {
+        return false;
+    };
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    let bind = bind.trim_start_matches('[').trim_end_matches(']');
+    name.eq_ignore_ascii_case("localhost") || name == "127.0.0.1" || name == bind
+}
+
+/// The decoded value of `name` in a URL query string like `q=garden+shed&limit=3`.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+/// Undoes URL encoding: `+` for spaces and `%XX` for bytes. Invalid escapes are
+/// kept as they are.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', None) => out.push(b' '),
+            (byte, None) => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::error::ObraError;
+    use crate::index::testing::test_manager_with;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("garden+shed%20plans"), "garden shed plans");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(query_param("limit=3&q=a%26b", "q").as_deref(), Some("a&b"));
+        assert_eq!(query_param("q", "q").as_deref(), Some(""));
+        assert_eq!(query_param("q=x", "limit"), None);
+    }
+
+    #[test]
+    fn test_host_allowed() {
+        assert!(host_allowed(Some("localhost:7700"), "127.0.0.1"));
+        assert!(host_allowed(Some("127.0.0.1"), "127.0.0.1"));
+        assert!(host_allowed(Some("192.168.1.5:7700"), "192.168.1.5"));
+        assert!(host_allowed(Some("[::1]:7700"), "::1"));
+        assert!(!host_allowed(Some("attacker.example:7700"), "127.0.0.1"));
+        assert!(!host_allowed(
+            Some("localhost.attacker.example"),
+            "127.0.0.1"
+        ));
+        assert!(!host_allowed(None, "127.0.0.1"));
+    }
+
+    #[test]
+    fn test_http_search_endpoint() -> anyhow::Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("shed.md"), "Garden shed plans")?;
+        fs::write(vault_dir.path().join("tax.md"), "Tax return checklist")?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            min_chunk_chars: 0,
+            ..Default::default()
+        };
+        let manager = Arc::new(test_manager_with(config, data_dir.path())?);
+        manager.full_index(true)?;
+
+        let addr = start_http_server(manager, "127.0.0.1", 0)?;
+        assert!(addr.ip().is_loopback());
+        let get = |path: &str| -> anyhow::Result<(u16, SearchResponse)> {
+            let url = format!("http://{}{}", addr, path);
+            let resp = match ureq::get(&url).call() {
+                Ok(resp) => resp,
+                Err(ureq::Error::Status(_, resp)) => resp,
+                Err(e) => return Err(e.into()),
+            };
+            Ok((resp.status(), serde_json::from_reader(resp.into_reader())?))
+        };
+
+        let (status, resp) = get("/search?q=garden+shed&limit=1")?;
+        assert!(host_allowed(Some(&addr.to_string()), "127.0.0.1"));
+        assert_eq!(status, 200);
+        assert_eq!(resp.results.len(), 1);
+        assert!(resp.results[0].path.ends_with("shed.md"));
+        assert!(resp.last_sync.is_some());
+
+        let (status, resp) = get("/search?limit=1")?;
+        assert_eq!(status, 400);
+        assert!(resp.message.is_some_and(|m| m.contains("q=")));
+        assert_eq!(get("/search?q=tax&limit=many")?.0, 400);
+        assert_eq!(get("/notes")?.0, 404);
+
+        // Requests for another host, as after DNS rebinding, are refused.
+        let url = format!("http://{}/search?q=garden", addr);
+        match ureq::get(&url).set("Host", "evil.example:8080").call() {
+            Err(ureq::Error::Status(status, _)) => assert_eq!(status, 403),
+            other => panic!("expected a 403, got {:?}", other.map(|r| r.status())),
+        }
+
+        // Typed failures keep their kind and map to a status.
+        let empty_dir = tempdir()?;
+        let empty = Arc::new(test_manager_with(Config::default(), empty_dir.path())?);
+        let (status, resp) = respond(&Method::Get, "/search?q=tax", &empty);
+        assert_eq!(resp.error, Some(ObraError::EmptyIndex));
+        assert_eq!(status, 409);
+        Ok(())
+    }
+}
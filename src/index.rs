@@ -1,15 +1,20 @@
-use crate::chunker::Chunker;
+use crate::cache::{content_hash, EmbeddingCache};
+use crate::chunker::{ChunkOverrides, TokenLimit};
 use crate::config::{Config, IGNORE_FOLDERS};
-use crate::db::{ChunkMeta, Database};
-use crate::embeddings::EmbeddingEngine;
+use crate::db::{ChunkMeta, CompactReport, Database, VerifyReport};
+use crate::embeddings::{mean_pool, Embedder};
+use crate::markdown::{self, FieldRole, ThinNotes};
 use anyhow::Result;
 use chrono::{DateTime, Utc, Local, Duration};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 use walkdir::WalkDir;
 
 #[derive(Serialize, Deserialize)]
@@ -17,38 +22,147 @@ pub struct Meta {
     pub last_sync: DateTime<Utc>,
 }
 
+/// How far along a running `full_index` is, shown in the tray while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexProgress {
+    pub files_done: usize,
+    pub files_total: usize,
+    #[serde(default)]
+    pub stage: IndexStage,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexStage {
+    #[default]
+    Embedding,
+    /// Writing the index to disk, or swapping in a rebuilt one.
+    Saving,
+    /// A stage added by a newer daemon, so its status still reads.
+    #[serde(other)]
+    Unknown,
+}
+
+impl IndexProgress {
+    fn embedding(files_done: usize, files_total: usize) -> Self {
+        Self {
+            files_done,
+            files_total,
+            stage: IndexStage::Embedding,
+        }
+    }
+
+    fn saving(self) -> Self {
+        Self {
+            stage: IndexStage::Saving,
+            ..self
+        }
+    }
+}
+
+/// What a `full_index` run did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexSummary {
+    /// Notes indexed, or indexed before the run was canceled.
+    pub files: usize,
+    pub canceled: bool,
+}
+
+/// What `obra status` reports about the index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexStatus {
+    pub vault_path: PathBuf,
+    pub files: usize,
+    pub chunks: usize,
+    pub last_sync: Option<DateTime<Utc>>,
+    /// Set while a sync is running.
+    pub progress: Option<IndexProgress>,
+    /// Notes skipped after failing repeatedly, which only a daemon tracks.
+    #[serde(default)]
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// A note `SyncManager::index_file` stopped trying until it changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    /// e.g. "failed 3 times: stream did not contain valid UTF-8"
+    pub reason: String,
+}
+
+impl IndexStatus {
+    /// The status of the index on disk, for when no daemon is running.
+    pub fn from_disk(config: &Config, data_dir: &Path) -> Result<Self> {
+        let db = Database::open(data_dir)?;
+        Ok(Self {
+            vault_path: config.vault_path.clone(),
+            files: db.indexed_files().len(),
+            chunks: db.chunks.len(),
+            last_sync: read_last_sync(data_dir),
+            progress: None,
+            skipped: Vec::new(),
+        })
+    }
+}
+
+/// When the index in `data_dir` last synced, from its `meta.json`.
+fn read_last_sync(data_dir: &Path) -> Option<DateTime<Utc>> {
+    let content = fs::read_to_string(data_dir.join("meta.json")).ok()?;
+    serde_json::from_str::<Meta>(&content)
+        .ok()
+        .map(|m| m.last_sync)
+}
+
+/// Consecutive indexing failures per note, with the mtime they happened at and
+/// the last error.
+type Failures = HashMap<PathBuf, (u32, Option<i64>, String)>;
+
 pub struct SyncManager {
     pub db: Arc<Mutex<Database>>,
-    pub engine: Arc<EmbeddingEngine>,
+    pub engine: Arc<dyn Embedder>,
+    pub config: Config,
     pub vault_path: PathBuf,
     pub data_dir: PathBuf,
     pub last_sync_time: Arc<Mutex<Option<DateTime<Utc>>>>,
+    progress: Mutex<Option<IndexProgress>>,
+    /// Paths the watcher touched while a forced re-index is being built, replayed
+    /// into the new index before it's swapped in. `None` when no rebuild is running.
+    rebuild_touched: Mutex<Option<HashSet<PathBuf>>>,
+    embed_cache: Mutex<EmbeddingCache>,
+    /// Set while a sync started by `sync_if_stale` is running.
+    background_sync: AtomicBool,
+    /// Asks the running `full_index` to stop after its current batch.
+    cancel_requested: AtomicBool,
+    /// Paths the watcher reported while watching is paused, reconciled on resume.
+    /// `None` when not paused.
+    paused_events: Mutex<Option<HashSet<PathBuf>>>,
+    failures: Mutex<Failures>,
     tray_handle: Mutex<Option<tauri::SystemTrayHandle>>,
 }
 
 impl SyncManager {
     pub fn new(
         db: Arc<Mutex<Database>>,
-        engine: Arc<EmbeddingEngine>,
-        vault_path: PathBuf,
+        engine: Arc<dyn Embedder>,
+        config: Config,
         data_dir: PathBuf,
     ) -> Self {
-        let meta_file = data_dir.join("meta.json");
-        let last_sync = if meta_file.exists() {
-            fs::read_to_string(&meta_file)
-                .ok()
-                .and_then(|content| serde_json::from_str::<Meta>(&content).ok())
-                .map(|m| m.last_sync)
-        } else {
-            None
-        };
+        let last_sync = read_last_sync(&data_dir);
 
         Self {
             db,
             engine,
-            vault_path,
+            vault_path: normalize_path(&config.vault_path),
+            embed_cache: Mutex::new(EmbeddingCache::load(&data_dir, &cache_id(&config))),
+            config,
             data_dir,
             last_sync_time: Arc::new(Mutex::new(last_sync)),
+            progress: Mutex::new(None),
+            rebuild_touched: Mutex::new(None),
+            background_sync: AtomicBool::new(false),
+            cancel_requested: AtomicBool::new(false),
+            paused_events: Mutex::new(None),
+            failures: Mutex::new(HashMap::new()),
             tray_handle: Mutex::new(None),
         }
     }
@@ -61,41 +175,335 @@ impl SyncManager {
         self.refresh_tray_status();
     }
 
-    fn update_status(&self) {
-        let now = Utc::now();
+    fn update_status(&self, synced: DateTime<Utc>) {
         {
             let mut last = self.last_sync_time.lock().unwrap();
-            *last = Some(now);
+            *last = Some(synced);
         }
         self.refresh_tray_status();
     }
 
-    pub fn refresh_tray_status(&self) {
+    fn set_progress(&self, progress: Option<IndexProgress>) {
+        *self.progress.lock().unwrap() = progress;
+        self.refresh_tray_status();
+    }
+
+    pub fn progress(&self) -> Option<IndexProgress> {
+        *self.progress.lock().unwrap()
+    }
+
+    fn report_progress(
+        &self,
+        progress: IndexProgress,
+        on_progress: Option<&dyn Fn(IndexProgress)>,
+    ) {
+        if let Some(on_progress) = on_progress {
+            on_progress(progress);
+        }
+        self.set_progress(Some(progress));
+    }
+
+    /// Asks the running index to stop once its current batch is done. Returns
+    /// whether one was running.
+    pub fn cancel_index(&self) -> bool {
+        let running = self.progress().is_some();
+        if running {
+            self.cancel_requested.store(true, Ordering::SeqCst);
+        }
+        running
+    }
+
+    fn cancel_pending(&self, files_done: usize, files_total: usize) -> bool {
+        let cancel = self.cancel_requested.swap(false, Ordering::SeqCst);
+        if cancel {
+            println!(
+                "⏹️  Indexing canceled after {}/{} files.",
+                files_done, files_total
+            );
+        }
+        cancel
+    }
+
+    /// What `full_index(force)` would do now; see `plan_index`. The vault is
+    /// scanned and chunked without holding the lock.
+    pub fn plan_index(&self, force: bool) -> Result<IndexPlan> {
+        let token_limit = self.engine.token_limit()?;
+        let (indexed, incomplete) = {
+            let db = self
+                .db
+                .lock()
+                .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+            (db.content_hashes(), db.is_incomplete())
+        };
+        plan_index(
+            &self.config,
+            &indexed,
+            incomplete,
+            &self.data_dir,
+            force,
+            token_limit.as_ref(),
+        )
+    }
+
+    pub fn index_status(&self) -> Result<IndexStatus> {
+        let db = self
+            .db
+            .lock()
+            .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+        Ok(IndexStatus {
+            vault_path: self.vault_path.clone(),
+            files: db.indexed_files().len(),
+            chunks: db.chunks.len(),
+            last_sync: *self.last_sync_time.lock().unwrap(),
+            progress: self.progress(),
+            skipped: self.skipped_files(),
+        })
+    }
+
+    pub fn status_text(&self) -> String {
+        if let Some(progress) = self.progress() {
+            return format!(
+                "Indexing {}/{} files...",
+                progress.files_done, progress.files_total
+            );
+        }
+        if let Some(pending) = self.paused_events.lock().unwrap().as_ref() {
+            return format!("Watching paused ({} changes pending)", pending.len());
+        }
+
         let last_sync = {
             let last = self.last_sync_time.lock().unwrap();
             *last
         };
+        let text = if let Some(last_sync) = last_sync {
+            let now = Utc::now();
+            let duration = now.signed_duration_since(last_sync);
+            let local_time: DateTime<Local> = DateTime::from(last_sync);
+            format!(
+                "Last indexed: {} ({})",
+                local_time.format("%H:%M:%S"),
+                humanize_duration(duration)
+            )
+        } else {
+            "Last indexed: Never".to_string()
+        };
+        match self.skipped_files().len() {
+            0 => text,
+            skipped => format!("{} · {} failing files skipped", text, skipped),
+        }
+    }
+
+    /// Notes that failed to index `max_index_failures` times in a row and are
+    /// skipped until they change.
+    pub fn skipped_files(&self) -> Vec<SkippedFile> {
+        let threshold = self.config.max_index_failures;
+        if threshold == 0 {
+            return Vec::new();
+        }
+        let mut skipped: Vec<SkippedFile> = self
+            .failures
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, (count, _, _))| *count >= threshold)
+            .map(|(path, (count, _, error))| SkippedFile {
+                path: path.clone(),
+                reason: format!("failed {} times: {}", count, error),
+            })
+            .collect();
+        skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        skipped
+    }
 
+    pub fn refresh_tray_status(&self) {
+        // The text is computed before taking the handle lock, and the handle lock
+        // serializes the periodic refresh with progress updates from indexing.
+        let status_text = self.status_text();
+        let pause_title = if self.is_paused() {
+            "Resume Watching"
+        } else {
+            "Pause Watching"
+        };
         let handle_lock = self.tray_handle.lock().unwrap();
         if let Some(ref handle) = *handle_lock {
-            let status_text = if let Some(last_sync) = last_sync {
-                let now = Utc::now();
-                let duration = now.signed_duration_since(last_sync);
-                let local_time: DateTime<Local> = DateTime::from(last_sync);
-                format!(
-                    "Last indexed: {} ({})",
-                    local_time.format("%H:%M:%S"),
-                    humanize_duration(duration)
-                )
-            } else {
-                "Last indexed: Never".to_string()
-            };
             let _ = handle.get_item("status").set_title(status_text);
+            let _ = handle.get_item("pause").set_title(pause_title);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_events.lock().unwrap().is_some()
+    }
+
+    /// Stops the watcher from indexing, e.g. during a bulk import or a rebase of the
+    /// vault. Events are only recorded until `resume`.
+    pub fn pause(&self) {
+        {
+            let mut paused = self.paused_events.lock().unwrap();
+            if paused.is_none() {
+                *paused = Some(HashSet::new());
+            }
+        }
+        self.refresh_tray_status();
+    }
+
+    /// Records `path` for reconciliation if watching is paused. Returns whether it was
+    /// deferred, in which case the watcher shouldn't index it now.
+    pub fn defer_if_paused(&self, path: &Path) -> bool {
+        {
+            let mut paused = self.paused_events.lock().unwrap();
+            let Some(pending) = paused.as_mut() else {
+                return false;
+            };
+            pending.insert(path.to_path_buf());
+        }
+        self.refresh_tray_status();
+        true
+    }
+
+    /// Resumes watching and reconciles in the background: the paths touched while
+    /// paused are indexed again or removed, then an incremental sync picks up
+    /// everything else that changed. Returns the sync thread, or `None` if watching
+    /// wasn't paused.
+    pub fn resume(self: &Arc<Self>) -> Option<JoinHandle<()>> {
+        let pending = self.paused_events.lock().unwrap().take()?;
+        self.refresh_tray_status();
+
+        let manager = self.clone();
+        Some(std::thread::spawn(move || {
+            // A note moved while paused keeps its mtime, so the sync alone would miss it.
+            for path in &pending {
+                let result = if path.is_dir() {
+                    if manager.ignores_folder(path) {
+                        continue;
+                    }
+                    manager.index_folder(path)
+                } else if path.exists() {
+                    manager.index_file(path)
+                } else if is_indexable(path, &manager.config) {
+                    manager.remove_file(path)
+                } else {
+                    manager.remove_folder(path)
+                };
+                if let Err(e) = result {
+                    eprintln!("❌ Failed to sync {:?}: {}", path, e);
+                }
+            }
+            if let Err(e) = manager.full_index(false) {
+                eprintln!("❌ Sync after resume failed: {}", e);
+            }
+        }))
+    }
+}
+
+/// Whether `rel_path`, a folder relative to the vault root, is or lies in a folder
+/// left out by `IGNORE_FOLDERS` or the config's `ignore_folders`. Patterns only
+/// match folders, so a note is checked by its parent.
+pub fn is_ignored(rel_path: &Path, config: &Config) -> bool {
+    let parts: Vec<String> = rel_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let patterns = IGNORE_FOLDERS
+        .iter()
+        .copied()
+        .chain(config.ignore_folders.iter().map(|p| p.trim_matches('/')));
+    patterns.into_iter().any(|pattern| {
+        if pattern.contains('/') {
+            (1..=parts.len()).any(|n| glob_match(pattern, &parts[..n].join("/")))
+        } else {
+            parts.iter().any(|part| glob_match(pattern, part))
         }
+    })
+}
+
+/// `*` matches any run of characters and `?` any one, neither crossing a `/`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut rest = pattern.chars();
+    let mut text_rest = text.chars();
+    match rest.next() {
+        None => text.is_empty(),
+        Some('*') => {
+            let pattern = rest.as_str();
+            loop {
+                if glob_match(pattern, text_rest.as_str()) {
+                    return true;
+                }
+                match text_rest.next() {
+                    Some(c) if c != '/' => {}
+                    _ => return false,
+                }
+            }
+        }
+        Some('?') => {
+            matches!(text_rest.next(), Some(c) if c != '/')
+                && glob_match(rest.as_str(), text_rest.as_str())
+        }
+        Some(c) => text_rest.next() == Some(c) && glob_match(rest.as_str(), text_rest.as_str()),
+    }
+}
+
+/// Whether `path` is a note, going by its extension and `indexed_extensions`.
+pub fn is_indexable(path: &Path, config: &Config) -> bool {
+    let Some(extension) = path.extension().and_then(OsStr::to_str) else {
+        return false;
+    };
+    config
+        .indexed_extensions
+        .iter()
+        .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+}
+
+/// The text of the note at `path` to index: a canvas's card text, or the file.
+fn read_note(path: &Path) -> Result<String> {
+    let content = fs::read_to_string(path)?;
+    let canvas = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("canvas"));
+    if canvas {
+        return markdown::canvas_text(&content);
+    }
+    Ok(content)
+}
+
+/// Canonicalizes `path` so that paths reported by the watcher and the configured
+/// vault root agree (symlinks, `..`, trailing separators). Paths that no longer
+/// exist (e.g. a just-deleted note) are resolved through their parent directory.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    if let Ok(canonical) = fs::canonicalize(path) {
+        return canonical;
     }
+    if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+        if let Ok(parent) = fs::canonicalize(parent) {
+            return parent.join(name);
+        }
+    }
+    path.components().collect()
+}
+
+/// Returns the path of a note relative to the vault root, as stored in `ChunkMeta.path`.
+/// Stored paths always use `/` so an index means the same thing on every OS.
+pub fn vault_relative_path(path: &Path, vault_root: &Path) -> Result<String> {
+    let root = normalize_path(vault_root);
+    let path = normalize_path(path);
+    let rel = path
+        .strip_prefix(&root)
+        .map_err(|_| anyhow::anyhow!("{:?} is not inside the vault {:?}", path, root))?;
+    Ok(with_forward_slashes(
+        &rel.to_string_lossy(),
+        std::path::MAIN_SEPARATOR,
+    ))
 }
 
-fn humanize_duration(duration: Duration) -> String {
+fn with_forward_slashes(rel_path: &str, separator: char) -> String {
+    rel_path
+        .split(separator)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn humanize_duration(duration: Duration) -> String {
     let secs = duration.num_seconds();
     if secs < 60 {
         return "just now".to_string();
@@ -113,97 +521,469 @@ fn humanize_duration(duration: Duration) -> String {
 }
 
 impl SyncManager {
-    pub fn full_index(&self, force: bool) -> Result<()> {
-        let meta_file = self.data_dir.join("meta.json");
+    /// Whether the watcher should leave the note at `path` alone: it's in an
+    /// ignored folder.
+    pub fn ignores(&self, path: &Path) -> bool {
+        path.parent()
+            .is_some_and(|folder| self.ignores_folder(folder))
+    }
 
-        let last_sync = if !force && meta_file.exists() {
-            let content = fs::read_to_string(&meta_file)?;
-            let meta: Meta = serde_json::from_str(&content)?;
-            Some(meta.last_sync)
+    /// Whether the folder at `path` is ignored or lies in an ignored folder.
+    pub fn ignores_folder(&self, path: &Path) -> bool {
+        let path = normalize_path(path);
+        path.strip_prefix(&self.vault_path)
+            .is_ok_and(|rel_path| is_ignored(rel_path, &self.config))
+    }
+
+    /// Saves a change the watcher reported: left to `flush` if there's a
+    /// `save_interval_secs`, otherwise right away.
+    fn save_later(&self, db: &Database, cache: &mut EmbeddingCache) {
+        if self.config.save_interval_secs == 0 {
+            save_or_warn(db, cache);
         } else {
-            None
+            db.mark_dirty();
+        }
+    }
+
+    /// Compacts the live index and saves it; see `Database::compact`.
+    pub fn compact(&self) -> Result<CompactReport> {
+        let mut db = self
+            .db
+            .lock()
+            .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+        let report = db.compact()?;
+        db.save_now()?;
+        Ok(report)
+    }
+
+    /// Cross-checks the live index against its chunk metadata and, if `fix`,
+    /// reconciles and saves it; see `obra verify`.
+    pub fn verify(&self, fix: bool) -> Result<VerifyReport> {
+        let mut db = self
+            .db
+            .lock()
+            .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+        let report = db.verify()?;
+        if fix && !report.is_consistent() {
+            db.remove_orphaned_vectors(&report)?;
+            let missing = db.restore_stored_vectors(&report.missing_vectors)?;
+            if !missing.is_empty() {
+                reembed_chunks(&mut db, &missing, &self.config, self.engine.as_ref())?;
+            }
+            db.save_now()?;
+        }
+        Ok(report)
+    }
+
+    /// Saves the index if it has changes `save_later` left unsaved.
+    pub fn flush(&self) {
+        let Ok(db) = self.db.lock() else {
+            return;
         };
+        if db.is_dirty() {
+            let mut cache = self.embed_cache.lock().unwrap();
+            save_or_warn(&db, &mut cache);
+        }
+    }
 
-        println!("🚀 Starting Indexing...");
+    /// Starts the daemon's thread that calls `flush` every `save_interval_secs`.
+    /// Returns `None` if changes are saved right away instead.
+    pub fn start_flusher(self: &Arc<Self>) -> Option<JoinHandle<()>> {
+        if self.config.save_interval_secs == 0 {
+            return None;
+        }
+        let interval = std::time::Duration::from_secs(self.config.save_interval_secs);
+        let manager = self.clone();
+        Some(std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            manager.flush();
+        }))
+    }
 
-        let mut paths_to_index = Vec::new();
+    /// Indexes the notes changed since the last sync, or all of them if `force`.
+    /// A canceled run keeps what it indexed but doesn't count as a sync, so the
+    /// next one picks up the rest.
+    pub fn full_index(&self, force: bool) -> Result<IndexSummary> {
+        self.full_index_reporting(force, None)
+    }
 
-        for entry in WalkDir::new(&self.vault_path)
-            .into_iter()
-            .filter_entry(|e| {
-                let name = e.file_name().to_string_lossy();
-                !IGNORE_FOLDERS.contains(&name.as_ref())
-            })
-        {
-            let entry = entry?;
-            if !entry.file_type().is_file() || entry.path().extension() != Some(OsStr::new("md")) {
-                continue;
-            }
-            let path = entry.path();
-            let metadata = fs::metadata(path)?;
-            let mtime: DateTime<Utc> = metadata.modified()?.into();
+    /// Like `full_index`, also passing each progress update to `on_progress`.
+    pub fn full_index_reporting(
+        &self,
+        force: bool,
+        on_progress: Option<&dyn Fn(IndexProgress)>,
+    ) -> Result<IndexSummary> {
+        let meta_file = self.data_dir.join("meta.json");
+        self.cancel_requested.store(false, Ordering::SeqCst);
 
-            if let Some(last) = last_sync {
-                if mtime <= last {
-                    continue;
-                }
+        println!("🚀 Starting Indexing...");
+
+        // Notes dropped to repair the index need indexing again whether changed or not.
+        let rescan = force || self.db.lock().unwrap().is_incomplete();
+        let scan = pending_changes(&self.vault_path, &self.config, &self.data_dir, rescan)?;
+        // A forced rebuild leaves deleted notes out when it's swapped in, and keeps
+        // serving them until then like the rest of the old index.
+        if !force || scan.changed.is_empty() {
+            let mut db = self
+                .db
+                .lock()
+                .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+            if purge_deleted(&mut db, &scan.present) > 0 {
+                let mut cache = self.embed_cache.lock().unwrap();
+                save_or_warn(&db, &mut cache);
             }
-            paths_to_index.push((path.to_path_buf(), mtime.timestamp()));
         }
+        let paths_to_index = scan.changed;
 
         if paths_to_index.is_empty() {
             println!("✅ No new files to index.");
-            return Ok(());
+            self.mark_complete();
+            self.record_sync(&meta_file)?;
+            return Ok(IndexSummary::default());
         }
 
         println!("📂 Found {} files to index. Processing in batches...", paths_to_index.len());
 
+        self.report_progress(
+            IndexProgress::embedding(0, paths_to_index.len()),
+            on_progress,
+        );
+        let result = if force {
+            self.rebuild(&paths_to_index, on_progress)
+        } else {
+            self.index_batches(&paths_to_index, on_progress)
+        };
+        let files_done = self.progress().map_or(0, |p| p.files_done);
+        self.set_progress(None);
+        if !result? {
+            return Ok(IndexSummary {
+                files: files_done,
+                canceled: true,
+            });
+        }
+
+        println!("✅ Indexed {} files.", paths_to_index.len());
+        self.mark_complete();
+        self.record_sync(&meta_file)?;
+        Ok(IndexSummary {
+            files: paths_to_index.len(),
+            canceled: false,
+        })
+    }
+
+    /// Clears `Database::is_incomplete` once a sync has indexed every note again.
+    fn mark_complete(&self) {
+        let mut db = self.db.lock().unwrap();
+        if db.is_incomplete() {
+            db.mark_complete();
+            let mut cache = self.embed_cache.lock().unwrap();
+            save_or_warn(&db, &mut cache);
+        }
+    }
+
+    fn record_sync(&self, meta_file: &Path) -> Result<()> {
+        let meta = Meta {
+            last_sync: Utc::now(),
+        };
+        {
+            // Held like for the index files, so two syncs never write it at once.
+            let _db = self
+                .db
+                .lock()
+                .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+            fs::write(meta_file, serde_json::to_string(&meta)?)?;
+        }
+        self.update_status(meta.last_sync);
+        Ok(())
+    }
+
+    /// Starts an incremental sync in the background if the last sync is older than
+    /// `auto_sync_hours`. The watcher misses changes made while the machine was
+    /// asleep, so a search is the daemon's cue to catch up. Returns the sync thread,
+    /// or `None` if the index is fresh or a sync is already running.
+    pub fn sync_if_stale(self: &Arc<Self>) -> Option<JoinHandle<()>> {
+        if self.config.auto_sync_hours <= 0 {
+            return None;
+        }
+        let last_sync = *self.last_sync_time.lock().unwrap();
+        let max_age = Duration::hours(self.config.auto_sync_hours);
+        let stale = last_sync.is_none_or(|last| Utc::now() - last > max_age)
+            || self.db.lock().unwrap().is_incomplete();
+        if !stale || self.background_sync.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let manager = self.clone();
+        Some(std::thread::spawn(move || {
+            if let Err(e) = manager.full_index(false) {
+                eprintln!("❌ Background sync failed: {}", e);
+            }
+            manager.background_sync.store(false, Ordering::SeqCst);
+        }))
+    }
+
+    /// Builds a fresh index off to the side and swaps it in at the end, so searches
+    /// keep being served from the old index until the new one is complete. If it's
+    /// canceled the old index stays; the embeddings computed so far stay cached.
+    /// Returns whether it finished.
+    fn rebuild(
+        &self,
+        paths_to_index: &[(PathBuf, i64)],
+        on_progress: Option<&dyn Fn(IndexProgress)>,
+    ) -> Result<bool> {
+        let mut replica = Database::in_memory()?;
+        replica.set_dimension(self.db.lock().unwrap().dimension())?;
+        replica.configure(&self.config)?;
+        *self.rebuild_touched.lock().unwrap() = Some(HashSet::new());
+
+        let file_batch_size = 100;
+        let mut files_done = 0;
+        for chunk in paths_to_index.chunks(file_batch_size) {
+            if self.cancel_pending(files_done, paths_to_index.len()) {
+                *self.rebuild_touched.lock().unwrap() = None;
+                return Ok(false);
+            }
+            // Only the cache is held while building; the live DB stays unlocked.
+            let mut cache = self.embed_cache.lock().unwrap();
+            let result = process_batch(
+                chunk,
+                &self.config,
+                &mut replica,
+                self.engine.as_ref(),
+                &mut cache,
+            );
+            drop(cache);
+            if let Err(e) = result {
+                *self.rebuild_touched.lock().unwrap() = None;
+                return Err(e);
+            }
+            files_done += chunk.len();
+            self.report_progress(
+                IndexProgress::embedding(files_done, paths_to_index.len()),
+                on_progress,
+            );
+        }
+
+        let progress = IndexProgress::embedding(files_done, paths_to_index.len());
+        self.report_progress(progress.saving(), on_progress);
+        let mut db = self
+            .db
+            .lock()
+            .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+        let mut cache = self.embed_cache.lock().unwrap();
+
+        // Taken under the DB lock: watcher events after this point wait for the
+        // lock and then apply to the swapped-in index directly.
+        let touched = self.rebuild_touched.lock().unwrap().take();
+        let mut changed = Vec::new();
+        for path in touched.unwrap_or_default() {
+            match fs::metadata(&path) {
+                // A folder renamed into place: its notes may be in the replica under
+                // the old name, or missing if they were read after the rename.
+                Ok(metadata) if metadata.is_dir() => {
+                    let scan = scan_vault(&path, &self.config, None)?;
+                    changed.extend(scan.changed.into_iter().filter(|(p, _)| !self.ignores(p)));
+                }
+                Ok(metadata) => {
+                    let mtime = DateTime::<Utc>::from(metadata.modified()?);
+                    changed.push((path, mtime.timestamp()));
+                }
+                Err(_) => {
+                    let rel_path = vault_relative_path(&path, &self.vault_path)?;
+                    replica.delete_by_path(&rel_path);
+                    replica.delete_folder(&rel_path);
+                }
+            }
+        }
+        process_batch(
+            &changed,
+            &self.config,
+            &mut replica,
+            self.engine.as_ref(),
+            &mut cache,
+        )?;
+
+        db.replace_with(replica);
+        save_or_warn(&db, &mut cache);
+        Ok(true)
+    }
+
+    fn note_touched(&self, path: &Path) {
+        if let Some(touched) = self.rebuild_touched.lock().unwrap().as_mut() {
+            touched.insert(path.to_path_buf());
+        }
+    }
+
+    /// Indexes into the live DB batch by batch, saving what's done if it's canceled.
+    /// Returns whether it finished.
+    fn index_batches(
+        &self,
+        paths_to_index: &[(PathBuf, i64)],
+        on_progress: Option<&dyn Fn(IndexProgress)>,
+    ) -> Result<bool> {
         let mut db = self.db.lock().map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
-        
+        let mut cache = self.embed_cache.lock().unwrap();
+
         let file_batch_size = 100;
+        let mut files_done = 0;
         for (i, chunk) in paths_to_index.chunks(file_batch_size).enumerate() {
-            println!("📦 Processing batch {}/{}...", i + 1, (paths_to_index.len() + file_batch_size - 1) / file_batch_size);
+            if self.cancel_pending(files_done, paths_to_index.len()) {
+                save_or_warn(&db, &mut cache);
+                return Ok(false);
+            }
+            println!(
+                "📦 Processing batch {}/{}...",
+                i + 1,
+                paths_to_index.len().div_ceil(file_batch_size)
+            );
             process_batch(
                 chunk,
-                &self.vault_path,
+                &self.config,
                 &mut db,
-                &self.engine,
+                self.engine.as_ref(),
+                &mut cache,
             )?;
+            files_done += chunk.len();
+            self.report_progress(
+                IndexProgress::embedding(files_done, paths_to_index.len()),
+                on_progress,
+            );
         }
 
-        db.save()?;
-        drop(db);
+        let progress = IndexProgress::embedding(files_done, paths_to_index.len());
+        self.report_progress(progress.saving(), on_progress);
+        save_or_warn(&db, &mut cache);
+        Ok(true)
+    }
 
-        let meta = Meta {
-            last_sync: Utc::now(),
-        };
-        fs::write(meta_file, serde_json::to_string(&meta)?)?;
+    /// Indexes one note the watcher reported. A note that keeps failing (e.g. a
+    /// corrupt file re-triggering the watcher) is skipped once it reaches
+    /// `max_index_failures`, until its mtime changes.
+    pub fn index_file(&self, path: &Path) -> Result<()> {
+        if !is_indexable(path, &self.config) {
+            return Ok(());
+        }
+        let mtime = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(|t| DateTime::<Utc>::from(t).timestamp());
+        let threshold = self.config.max_index_failures;
+        {
+            let mut failures = self.failures.lock().unwrap();
+            match failures.get(path) {
+                Some((count, at, _)) if *at == mtime && threshold > 0 && *count >= threshold => {
+                    return Ok(());
+                }
+                Some((_, at, _)) if *at != mtime => {
+                    failures.remove(path);
+                }
+                _ => {}
+            }
+        }
 
-        println!("✅ Indexed {} files.", paths_to_index.len());
-        self.update_status();
-        Ok(())
+        let result = self.index_file_now(path);
+        let mut failures = self.failures.lock().unwrap();
+        match &result {
+            Ok(()) => {
+                failures.remove(path);
+            }
+            Err(e) => {
+                let (count, at, error) =
+                    failures
+                        .entry(path.to_path_buf())
+                        .or_insert((0, mtime, String::new()));
+                *count += 1;
+                *at = mtime;
+                *error = e.to_string();
+                if *count == threshold {
+                    eprintln!(
+                        "⚠️  Skipping {:?} after {} failed attempts ({}); it will be retried when it changes.",
+                        path, count, e
+                    );
+                }
+            }
+        }
+        result
     }
 
-    pub fn index_file(&self, path: &Path) -> Result<()> {
+    fn index_file_now(&self, path: &Path) -> Result<()> {
+        self.note_touched(path);
         let metadata = fs::metadata(path)?;
         let mtime: DateTime<Utc> = metadata.modified()?.into();
         
         let mut db = self.db.lock().map_err(|_| anyhow::anyhow!("Failed to lock database"))?;
         
+        let mut cache = self.embed_cache.lock().unwrap();
+
         let paths = vec![(path.to_path_buf(), mtime.timestamp())];
-        process_batch(&paths, &self.vault_path, &mut db, &self.engine)?;
-        
-        db.save()?;
+        process_batch(
+            &paths,
+            &self.config,
+            &mut db,
+            self.engine.as_ref(),
+            &mut cache,
+        )?;
+
+        self.save_later(&db, &mut cache);
+        drop(cache);
         drop(db);
-        self.update_status();
+        self.update_status(Utc::now());
         Ok(())
     }
     
     pub fn remove_file(&self, path: &Path) -> Result<()> {
-        let rel_path = path.strip_prefix(&self.vault_path)?.to_string_lossy().to_string();
+        self.note_touched(path);
+        let rel_path = vault_relative_path(path, &self.vault_path)?;
         let mut db = self.db.lock().map_err(|_| anyhow::anyhow!("Failed to lock database"))?;
         db.delete_by_path(&rel_path);
-        db.save()?;
+        let mut cache = self.embed_cache.lock().unwrap();
+        self.save_later(&db, &mut cache);
+        Ok(())
+    }
+
+    /// Drops every note under a deleted folder, which the watcher reports as one
+    /// event rather than one per note.
+    pub fn remove_folder(&self, path: &Path) -> Result<()> {
+        self.note_touched(path);
+        let rel_path = vault_relative_path(path, &self.vault_path)?;
+        let mut db = self
+            .db
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Failed to lock database"))?;
+        if db.delete_folder(&rel_path) > 0 {
+            let mut cache = self.embed_cache.lock().unwrap();
+            self.save_later(&db, &mut cache);
+        }
+        Ok(())
+    }
+
+    /// Indexes every note under a folder that appeared as one event, e.g. one moved
+    /// out of an ignored folder.
+    pub fn index_folder(&self, path: &Path) -> Result<()> {
+        let scan = scan_vault(path, &self.config, None)?;
+        for (note, _) in scan.changed {
+            if !self.ignores(&note) {
+                self.index_file(&note)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Points the notes of a renamed folder at their new paths without re-embedding
+    /// them. Their `HOLDER_FOLDERS` context is refreshed when each note next changes.
+    pub fn rename_folder(&self, from: &Path, to: &Path) -> Result<()> {
+        self.note_touched(from);
+        self.note_touched(to);
+        let from = vault_relative_path(from, &self.vault_path)?;
+        let to = vault_relative_path(to, &self.vault_path)?;
+        let mut db = self
+            .db
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Failed to lock database"))?;
+        if db.rename_folder(&from, &to) > 0 {
+            let mut cache = self.embed_cache.lock().unwrap();
+            self.save_later(&db, &mut cache);
+        }
         Ok(())
     }
 }
@@ -211,48 +991,35 @@ impl SyncManager {
 pub fn run_index(
     config: &Config,
     db: &mut Database,
-    engine: &EmbeddingEngine,
+    engine: &dyn Embedder,
     data_dir: &Path,
     force: bool,
+    on_progress: Option<&dyn Fn(IndexProgress)>,
 ) -> Result<()> {
     let meta_file = data_dir.join("meta.json");
 
-    let last_sync = if !force && meta_file.exists() {
-        let content = fs::read_to_string(&meta_file)?;
-        let meta: Meta = serde_json::from_str(&content)?;
-        Some(meta.last_sync)
-    } else {
-        None
-    };
-
     println!("🚀 Starting Indexing...");
 
-    let mut paths_to_index = Vec::new();
-
-    for entry in WalkDir::new(&config.vault_path)
-        .into_iter()
-        .filter_entry(|e| {
-            let name = e.file_name().to_string_lossy();
-            !IGNORE_FOLDERS.contains(&name.as_ref())
-        })
-    {
-        let entry = entry?;
-        if !entry.file_type().is_file() || entry.path().extension() != Some(OsStr::new("md")) {
-            continue;
-        }
-        let path = entry.path();
-        let metadata = fs::metadata(path)?;
-        let mtime: DateTime<Utc> = metadata.modified()?.into();
-
-        if let Some(last) = last_sync {
-            if mtime <= last {
-                continue;
-            }
-        }
-        paths_to_index.push((path.to_path_buf(), mtime.timestamp()));
+    let rescan = force || db.is_incomplete();
+    let scan = pending_changes(&config.vault_path, config, data_dir, rescan)?;
+    let mut cache = EmbeddingCache::load(data_dir, &cache_id(config));
+    // Every note is chunked and embedded again, so none is left with the old
+    // settings; the cache still spares re-embedding text whose vector is current.
+    // Nothing is saved until the end, so a failed run leaves the old index on disk.
+    if force {
+        db.clear()?;
+        db.record_settings(config);
     }
+    let purged = purge_deleted(db, &scan.present);
+    // Only saved along with the notes, so a failed sync leaves it set.
+    let repaired = db.is_incomplete();
+    db.mark_complete();
+    let paths_to_index = scan.changed;
 
     if paths_to_index.is_empty() {
+        if purged > 0 || repaired {
+            save_or_warn(db, &mut cache);
+        }
         println!("✅ No new files to index.");
         return Ok(());
     }
@@ -260,12 +1027,25 @@ pub fn run_index(
     println!("📂 Found {} files to index. Processing in batches...", paths_to_index.len());
 
     let file_batch_size = 100;
+    let report = |progress| {
+        if let Some(on_progress) = on_progress {
+            on_progress(progress);
+        }
+    };
+    let mut files_done = 0;
     for (i, chunk) in paths_to_index.chunks(file_batch_size).enumerate() {
-        println!("📦 Processing batch {}/{}...", i + 1, (paths_to_index.len() + file_batch_size - 1) / file_batch_size);
-        process_batch(chunk, &config.vault_path, db, engine)?;
+        println!(
+            "📦 Processing batch {}/{}...",
+            i + 1,
+            paths_to_index.len().div_ceil(file_batch_size)
+        );
+        process_batch(chunk, config, db, engine, &mut cache)?;
+        files_done += chunk.len();
+        report(IndexProgress::embedding(files_done, paths_to_index.len()));
     }
 
-    db.save()?;
+    report(IndexProgress::embedding(files_done, paths_to_index.len()).saving());
+    save_or_warn(db, &mut cache);
 
     let meta = Meta {
         last_sync: Utc::now(),
@@ -276,9 +1056,594 @@ pub fn run_index(
     Ok(())
 }
 
+/// What a walk of the vault found.
+struct VaultScan {
+    /// Notes modified since the last sync (all of them without one), with their mtime.
+    changed: Vec<(PathBuf, i64)>,
+    /// Vault-relative paths of every note, changed or not.
+    present: HashSet<String>,
+}
+
+/// Walks the vault at `root` for notes, skipping ignored folders.
+fn scan_vault(root: &Path, config: &Config, last_sync: Option<DateTime<Utc>>) -> Result<VaultScan> {
+    let mut scan = VaultScan {
+        changed: Vec::new(),
+        present: HashSet::new(),
+    };
+    for entry in WalkDir::new(root).into_iter().filter_entry(|e| {
+        let rel_path = e.path().strip_prefix(root).unwrap_or(e.path());
+        !e.file_type().is_dir() || !is_ignored(rel_path, config)
+    }) {
+        let entry = entry?;
+        if !entry.file_type().is_file() || !is_indexable(entry.path(), config) {
+            continue;
+        }
+        let path = entry.path();
+        let rel_path = path.strip_prefix(root).unwrap_or(path);
+        scan.present.insert(with_forward_slashes(
+            &rel_path.to_string_lossy(),
+            std::path::MAIN_SEPARATOR,
+        ));
+
+        let metadata = fs::metadata(path)?;
+        let mtime: DateTime<Utc> = metadata.modified()?.into();
+        if last_sync.is_some_and(|last| mtime <= last) {
+            continue;
+        }
+        scan.changed.push((path.to_path_buf(), mtime.timestamp()));
+    }
+    Ok(scan)
+}
+
+/// The notes under `root` changed since the last sync recorded in `data_dir`, or
+/// all of them if `force` or there's no record.
+fn pending_changes(
+    root: &Path,
+    config: &Config,
+    data_dir: &Path,
+    force: bool,
+) -> Result<VaultScan> {
+    let meta_file = data_dir.join("meta.json");
+    let last_sync = if !force && meta_file.exists() {
+        let content = fs::read_to_string(&meta_file)?;
+        let meta: Meta = serde_json::from_str(&content)?;
+        Some(meta.last_sync)
+    } else {
+        None
+    };
+    scan_vault(root, config, last_sync)
+}
+
+/// What an index run would do, as `--dry-run` reports it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IndexPlan {
+    /// Notes that would be (re)indexed, vault-relative, with how many chunks each
+    /// would make.
+    pub files: Vec<(String, usize)>,
+    /// Indexed notes no longer in the vault, which would be removed.
+    pub deleted: usize,
+}
+
+impl IndexPlan {
+    pub fn chunks(&self) -> usize {
+        self.files.iter().map(|(_, chunks)| chunks).sum()
+    }
+}
+
+/// Works out what indexing would do without embedding or changing anything, given
+/// the indexed notes' content hashes (`Database::content_hashes`) and whether the
+/// index is incomplete. Without `token_limit` chunks are cut by characters alone,
+/// so the chunk counts are estimates.
+pub fn plan_index(
+    config: &Config,
+    indexed: &HashMap<String, u64>,
+    incomplete: bool,
+    data_dir: &Path,
+    force: bool,
+    token_limit: Option<&TokenLimit>,
+) -> Result<IndexPlan> {
+    let scan = pending_changes(&config.vault_path, config, data_dir, force || incomplete)?;
+    let files: Vec<Option<(String, usize)>> = scan
+        .changed
+        .par_iter()
+        .map(|(path, mtime)| {
+            let file = chunk_file(path, *mtime, config, token_limit)?;
+            // Only the mtime changed, so indexing just touches it.
+            if !force && indexed.get(&file.rel_path) == Some(&file.content_hash) {
+                return Ok(None);
+            }
+            Ok(Some((file.rel_path, file.chunks.len())))
+        })
+        .collect::<Result<_>>()?;
+    let mut files: Vec<(String, usize)> = files.into_iter().flatten().collect();
+    files.sort();
+
+    let deleted = indexed
+        .keys()
+        .filter(|path| !scan.present.contains(*path))
+        .count();
+    Ok(IndexPlan { files, deleted })
+}
+
+/// Deletes the notes that are no longer in the vault (not in `present`), e.g. ones
+/// deleted while nothing was watching. Returns how many there were.
+fn purge_deleted(db: &mut Database, present: &HashSet<String>) -> usize {
+    let deleted: HashSet<String> = db
+        .chunks
+        .iter()
+        .filter(|c| !present.contains(&c.path))
+        .map(|c| c.path.clone())
+        .collect();
+    for path in &deleted {
+        db.delete_by_path(path);
+    }
+    if !deleted.is_empty() {
+        println!(
+            "🗑️  Removed {} notes no longer in the vault.",
+            deleted.len()
+        );
+    }
+    deleted.len()
+}
+
+/// Writes `db` and then `cache`, warning instead of failing: the in-memory index is
+/// still good, so an unwritable data dir shouldn't throw away the work just done.
+fn save_or_warn(db: &Database, cache: &mut EmbeddingCache) {
+    if let Err(e) = db.save_now() {
+        eprintln!(
+            "⚠️  Could not save the index, keeping it in memory only: {:#}",
+            e
+        );
+        return;
+    }
+    if let Err(e) = cache.save_for(db) {
+        eprintln!("⚠️  Could not save the embedding cache: {:#}", e);
+    }
+}
+
+/// Ends the identity header that `process_batch` puts before a note's content.
+const CONTENT_MARKER: &str = "--- START OF CONTENT ---\n";
+
+/// The part of a chunk's text that comes from the note itself.
+pub fn strip_identity_header(chunk_text: &str) -> &str {
+    chunk_text
+        .split_once(CONTENT_MARKER)
+        .map_or(chunk_text, |(_, content)| content)
+}
+
+/// Identifies the embedding cache to use: vectors are only reusable for the same
+/// model, document prefix and sequence length cap.
+fn cache_id(config: &Config) -> String {
+    let mut id = config.model_id.clone();
+    if !config.document_prefix.is_empty() {
+        id += &format!("-{:016x}", content_hash(&config.document_prefix));
+    }
+    if let Some(max_seq_len) = config.max_seq_len {
+        id += &format!("-seq{}", max_seq_len);
+    }
+    id
+}
+
+/// Embeds the texts of `batches`, reusing the vectors of texts that are cached or
+/// already indexed in `db` and only running the model on chunks it hasn't seen
+/// before. A text repeated across the batches is embedded once.
+/// Each batch's misses go through the model in their own forward pass, all of them
+/// at once; the vectors come back per batch, in order.
+fn embed_with_cache(
+    batches: &[Vec<ChunkMeta>],
+    document_prefix: &str,
+    engine: &dyn Embedder,
+    cache: &mut EmbeddingCache,
+    db: &Database,
+) -> Result<Vec<Vec<Vec<f32>>>> {
+    let mut vectors: Vec<Vec<Option<Vec<f32>>>> = Vec::with_capacity(batches.len());
+    for batch in batches {
+        let mut slots = Vec::with_capacity(batch.len());
+        for meta in batch {
+            slots.push(match cache.get(&meta.text) {
+                Some(vector) => Some(vector.clone()),
+                None => db.vector_for_text(meta.text_hash)?,
+            });
+        }
+        vectors.push(slots);
+    }
+
+    // Each missing text once, in the batch it first appears in.
+    let mut queued = HashSet::new();
+    let misses: Vec<Vec<&ChunkMeta>> = batches
+        .iter()
+        .zip(&vectors)
+        .map(|(batch, slots)| {
+            batch
+                .iter()
+                .zip(slots)
+                .filter(|(m, v)| v.is_none() && queued.insert(m.text_hash))
+                .map(|(m, _)| m)
+                .collect()
+        })
+        .collect();
+
+    let fresh: Vec<Vec<Vec<f32>>> = misses
+        .par_iter()
+        .map(|batch| {
+            if batch.is_empty() {
+                Ok(Vec::new())
+            } else {
+                let texts = batch
+                    .iter()
+                    .map(|m| format!("{}{}", document_prefix, m.text))
+                    .collect();
+                engine.embed(texts)
+            }
+        })
+        .collect::<Result<_>>()?;
+
+    let mut embedded = HashMap::new();
+    for (batch, fresh) in misses.iter().zip(fresh) {
+        if fresh.len() < batch.len() {
+            anyhow::bail!("Embedding returned too few vectors");
+        }
+        for (meta, vector) in batch.iter().zip(fresh) {
+            cache.insert(&meta.text, vector.clone());
+            embedded.insert(meta.text_hash, vector);
+        }
+    }
+    for (batch, slots) in batches.iter().zip(&mut vectors) {
+        for (meta, slot) in batch.iter().zip(slots.iter_mut()) {
+            if slot.is_none() {
+                *slot = embedded.get(&meta.text_hash).cloned();
+            }
+        }
+    }
+
+    Ok(vectors
+        .into_iter()
+        .map(|slots| slots.into_iter().flatten().collect())
+        .collect())
+}
+
+/// A file that has been read and chunked, ready for embedding.
+struct ChunkedFile {
+    rel_path: String,
+    filename: String,
+    aliases: Vec<String>,
+    tags: Vec<String>,
+    links: Vec<String>,
+    word_count: usize,
+    content_hash: u64,
+    /// Chunk texts with their character range in the note's content.
+    chunks: Vec<(String, (usize, usize))>,
+    mtime: i64,
+}
+
+/// Reads a note and splits it into chunks, each starting from the identity header.
+/// With a `token_limit`, no chunk is longer than the model embeds.
+fn chunk_file(
+    path: &Path,
+    mtime: i64,
+    config: &Config,
+    token_limit: Option<&TokenLimit>,
+) -> Result<ChunkedFile> {
+    let rel_path = vault_relative_path(path, &config.vault_path)?;
+    let filename = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let raw = read_note(path)?;
+    let frontmatter = markdown::frontmatter(&raw);
+    let overrides = frontmatter.map(ChunkOverrides::from_frontmatter);
+    let roles = config.fields;
+    let stored = |role: FieldRole, values: Option<Vec<String>>| match role {
+        FieldRole::Ignored => Vec::new(),
+        _ => values.unwrap_or_default(),
+    };
+    let aliases = stored(roles.aliases, frontmatter.map(markdown::aliases));
+    // Frontmatter tags, then those written in the body, each once whatever its case.
+    let mut tags = frontmatter.map(markdown::tags).unwrap_or_default();
+    for tag in markdown::inline_tags(&raw) {
+        if !tags.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+            tags.push(tag);
+        }
+    }
+    let tags = stored(roles.tags, Some(tags));
+    let links = markdown::wikilinks(&raw);
+    let word_count = markdown::word_count(&raw);
+    let content_hash = content_hash(&raw);
+    // The frontmatter's useful fields go into the identity header instead of its
+    // raw YAML going into the chunks. Spans still count from the top of the note.
+    let preprocessed = markdown::preprocess(&raw, config.code_blocks);
+    let content = markdown::without_frontmatter(&preprocessed);
+    let offset = preprocessed.chars().count() - content.chars().count();
+    let thin = markdown::prose_words(content) < config.min_prose_words;
+    let title_only = thin && config.thin_notes == ThinNotes::TitleOnly;
+    let skip = thin && config.thin_notes == ThinNotes::Skip;
+    if skip || (content.trim().is_empty() && !title_only) {
+        return Ok(ChunkedFile {
+            rel_path,
+            filename,
+            aliases,
+            tags,
+            links,
+            word_count,
+            content_hash,
+            chunks: Vec::new(),
+            mtime,
+        });
+    }
+
+    // Context injection
+    let breadcrumb = match roles.folders {
+        FieldRole::EmbedContext => rel_path
+            .rsplit_once('/')
+            .map(|(folders, _)| folders.replace('/', " > "))
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+
+    let mut identity_header = format!(
+        "FILE_NAME: {}\nHOLDER_FOLDERS: {}\nDOCUMENT_SUBJECT: {}\n",
+        filename, breadcrumb, filename
+    );
+    for (label, role, values) in [
+        ("Also known as", roles.aliases, &aliases),
+        ("Tagged", roles.tags, &tags),
+    ] {
+        if role == FieldRole::EmbedContext && !values.is_empty() {
+            identity_header += &format!("{}: {}\n", label, values.join(", "));
+        }
+    }
+
+    // A thin note is indexed as one chunk of its title and what it links to.
+    if title_only {
+        let titles = markdown::link_titles(content).join("\n");
+        return Ok(ChunkedFile {
+            rel_path,
+            filename,
+            aliases,
+            tags,
+            links,
+            word_count,
+            content_hash,
+            chunks: vec![(identity_header + CONTENT_MARKER + &titles, (0, 0))],
+            mtime,
+        });
+    }
+
+    // Chunk
+    let mut chunker = config.chunker();
+    if let Some(limit) = token_limit {
+        chunker = chunker.with_token_limit(limit.reserve(&config.document_prefix));
+    }
+    if let Some(overrides) = overrides {
+        match overrides.apply(&chunker) {
+            Ok(overridden) => chunker = overridden,
+            Err(e) => eprintln!("⚠️  Ignoring chunk settings in {}: {}", rel_path, e),
+        }
+    }
+
+    // Every section chunk gets the header, with the headings it's under.
+    if config.split_on_headings {
+        let header = format!("{}SECTION: \n{}", identity_header, CONTENT_MARKER);
+        chunker.token_limit = chunker.token_limit.map(|limit| limit.reserve(&header));
+        let chunks = chunker
+            .chunk_markdown(content)
+            .into_iter()
+            .map(|chunk| {
+                let mut header = identity_header.clone();
+                if !chunk.breadcrumb.is_empty() {
+                    header += &format!("SECTION: {}\n", chunk.breadcrumb);
+                }
+                let (start, end) = chunk.span;
+                (
+                    header + CONTENT_MARKER + &markdown::link_display(&chunk.text),
+                    (start + offset, end + offset),
+                )
+            })
+            .collect();
+        return Ok(ChunkedFile {
+            rel_path,
+            filename,
+            aliases,
+            tags,
+            links,
+            word_count,
+            content_hash,
+            chunks,
+            mtime,
+        });
+    }
+
+    identity_header += CONTENT_MARKER;
+    let header_len = identity_header.chars().count();
+    let full_text = identity_header + content;
+    let spans = chunker.spans(&full_text).into_iter().map(|(start, end)| {
+        (
+            start.saturating_sub(header_len) + offset,
+            end.saturating_sub(header_len) + offset,
+        )
+    });
+    // Links are embedded as the text they show, so spans still count the brackets.
+    let chunks = chunker
+        .chunk(&full_text)
+        .iter()
+        .map(|text| markdown::link_display(text))
+        .zip(spans)
+        .collect();
+
+    Ok(ChunkedFile {
+        rel_path,
+        filename,
+        aliases,
+        tags,
+        links,
+        word_count,
+        content_hash,
+        chunks,
+        mtime,
+    })
+}
+
+/// Embeds a note that isn't in the index, chunked and pooled the same way as
+/// `Database::document_vector` sees an indexed one.
+pub fn embed_note(path: &Path, config: &Config, engine: &dyn Embedder) -> Result<Vec<f32>> {
+    let file = chunk_file(path, 0, config, engine.token_limit()?.as_ref())?;
+    let texts = file
+        .chunks
+        .into_iter()
+        .map(|(text, _)| format!("{}{}", config.document_prefix, text))
+        .collect();
+    mean_pool(&engine.embed(texts)?)
+        .ok_or_else(|| anyhow::anyhow!("{:?} has no content to embed", path))
+}
+
+/// Embeds chunks `ids` of `db` again, with the document prefix `process_batch`
+/// gives them, and puts their vectors back under the same ids.
+pub fn reembed_chunks(
+    db: &mut Database,
+    ids: &[u64],
+    config: &Config,
+    engine: &dyn Embedder,
+) -> Result<()> {
+    let texts: HashMap<u64, &str> = db.chunks.iter().map(|c| (c.id, c.text.as_str())).collect();
+    let texts = ids
+        .iter()
+        .filter_map(|id| texts.get(id))
+        .map(|text| format!("{}{}", config.document_prefix, text))
+        .collect();
+    let vectors = engine.embed(texts)?;
+    db.restore_vectors(ids, vectors)
+}
+
+pub fn process_batch(
+    paths: &[(PathBuf, i64)],
+    config: &Config,
+    db: &mut Database,
+    engine: &dyn Embedder,
+    cache: &mut EmbeddingCache,
+) -> Result<()> {
+    // 1. Parallel Chunking
+    let token_limit = engine.token_limit()?;
+    let file_results: Vec<Result<ChunkedFile>> = paths
+        .par_iter()
+        .map(|(path, mtime)| chunk_file(path, *mtime, config, token_limit.as_ref()))
+        .collect();
+
+    // 2. Collect chunks and remove old entries
+    let mut chunk_metas = Vec::new();
+
+    for res in file_results {
+        let file = res?;
+        // Only the mtime changed, e.g. after a `touch` or a sync tool rewriting it.
+        if db.touch_if_unchanged(&file.rel_path, file.content_hash, file.mtime) {
+            continue;
+        }
+
+        let metas: Vec<ChunkMeta> = file
+            .chunks
+            .into_iter()
+            .map(|(text, (start, end))| ChunkMeta {
+                id: 0, // assigned by db.insert_chunks
+                path: file.rel_path.clone(),
+                filename: file.filename.clone(),
+                text_hash: content_hash(&text),
+                text,
+                mtime: file.mtime,
+                start,
+                end,
+                aliases: file.aliases.clone(),
+                tags: file.tags.clone(),
+                links: file.links.clone(),
+                word_count: file.word_count,
+                content_hash: file.content_hash,
+            })
+            .collect();
+
+        // Delete old entries for this file, or all but its unchanged leading chunks
+        let kept = if config.reuse_unchanged_chunks {
+            db.keep_unchanged_prefix(&file.rel_path, &metas)
+        } else {
+            db.delete_by_path(&file.rel_path);
+            0
+        };
+        chunk_metas.extend(metas.into_iter().skip(kept));
+    }
+
+    if chunk_metas.is_empty() {
+        return Ok(());
+    }
+
+    // 3. Batched Embedding
+    // Up to `embed_concurrency` batches are embedded at once and inserted as soon
+    // as they're ready, in chunk order, so only that many batches of vectors are
+    // alive at a time, however many chunks the files produce.
+    let total = chunk_metas.len();
+    println!("🧠 Generating embeddings for {} chunks...", total);
+
+    let batch_size = config.embed_batch_size.max(1);
+    let concurrency = config.embed_concurrency.max(1);
+    let mut metas = chunk_metas.into_iter().peekable();
+    let mut done = 0;
+    let mut i = 0;
+    while metas.peek().is_some() {
+        let batches: Vec<Vec<ChunkMeta>> = (0..concurrency)
+            .map(|_| metas.by_ref().take(batch_size).collect::<Vec<_>>())
+            .filter(|batch| !batch.is_empty())
+            .collect();
+        let embeddings = embed_with_cache(&batches, &config.document_prefix, engine, cache, db)?;
+
+        // 4. Insert into DB
+        for (batch, vectors) in batches.into_iter().zip(embeddings) {
+            done += batch.len();
+            db.insert_chunks(batch, vectors)?;
+            if i % 10 == 0 {
+                println!("   ... {}/{}", done, total);
+            }
+            i += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// A `SyncManager` over a new database in `data_dir` that embeds with `MockEmbedder`.
+#[cfg(test)]
+pub mod testing {
+    use super::SyncManager;
+    use crate::config::Config;
+    use crate::db::Database;
+    use crate::embeddings::testing::MockEmbedder;
+    use anyhow::Result;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    pub fn test_manager(vault: &Path, data_dir: &Path) -> Result<SyncManager> {
+        let config = Config {
+            vault_path: vault.to_path_buf(),
+            ..Default::default()
+        };
+        test_manager_with(config, data_dir)
+    }
+
+    pub fn test_manager_with(config: Config, data_dir: &Path) -> Result<SyncManager> {
+        Ok(SyncManager::new(
+            Arc::new(Mutex::new(Database::open(data_dir)?)),
+            Arc::new(MockEmbedder::default()),
+            config,
+            data_dir.to_path_buf(),
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::testing::{test_manager, test_manager_with};
     use super::*;
+    use crate::db::MetaField;
+    use crate::embeddings::testing::MockEmbedder;
+    use crate::embeddings::{DownloadRetry, EmbeddingEngine};
+    use crate::search::{run_search, MetaFilter, SearchOptions};
     use tempfile::tempdir;
 
     #[test]
@@ -290,11 +1655,17 @@ mod tests {
         fs::write(&file_path, "# Hello\nThis is a test.")?;
         
         let db = Arc::new(Mutex::new(Database::open(data_dir.path())?));
-        let engine = Arc::new(EmbeddingEngine::new()?);
+        let engine = Arc::new(EmbeddingEngine::load(
+            crate::config::MODEL_ID,
+            DownloadRetry::default(),
+        )?);
         let manager = SyncManager::new(
             db.clone(),
             engine.clone(),
-            vault_dir.path().to_path_buf(),
+            Config {
+                vault_path: vault_dir.path().to_path_buf(),
+                ..Default::default()
+            },
             data_dir.path().to_path_buf(),
         );
         
@@ -327,99 +1698,1207 @@ mod tests {
         
         Ok(())
     }
-}
 
-pub fn process_batch(
-    paths: &[(PathBuf, i64)],
-    vault_root: &Path,
-    db: &mut Database,
-    engine: &EmbeddingEngine,
-) -> Result<()> {
-    // 1. Parallel Chunking
-    let file_results: Vec<Result<(String, String, Vec<String>, i64)>> = paths
-        .par_iter()
-        .map(|(path, mtime)| {
-            let rel_path = path.strip_prefix(vault_root)?.to_string_lossy().to_string();
-            let filename = path
-                .file_stem()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
-
-            let content = fs::read_to_string(path)?;
-            if content.trim().is_empty() {
-                return Ok((rel_path, filename, Vec::new(), *mtime));
-            }
+    #[test]
+    fn test_process_batch_reuses_cached_embeddings() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let path = vault_dir.path().join("note.md");
+        fs::write(&path, "some note text")?;
 
-            // Context injection
-            let breadcrumb = path
-                .parent()
-                .and_then(|p| p.strip_prefix(vault_root).ok())
-                .map(|p| p.to_string_lossy().replace("/", " > "))
-                .unwrap_or_default();
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
 
-            let identity_header = format!(
-                "FILE_NAME: {}\nHOLDER_FOLDERS: {}\nDOCUMENT_SUBJECT: {}\n--- START OF CONTENT ---\n",
-                filename, breadcrumb, filename
-            );
-            let full_text = identity_header + &content;
+        let paths = vec![(path, 0)];
+        process_batch(&paths, &config, &mut db, &engine, &mut cache)?;
+        process_batch(&paths, &config, &mut db, &engine, &mut cache)?;
 
-            // Chunk
-            let chunker = Chunker::default();
-            let chunks = chunker.chunk(&full_text);
+        assert_eq!(engine.calls(), 1);
+        assert_eq!(db.chunks.len(), 1);
+        Ok(())
+    }
 
-            Ok((rel_path, filename, chunks, *mtime))
-        })
-        .collect();
+    #[test]
+    fn test_touched_note_is_not_re_embedded() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let path = vault_dir.path().join("note.md");
+        fs::write(&path, "some note text")?;
 
-    // 2. Collect chunks and remove old entries
-    let mut all_chunks = Vec::new();
-    let mut chunk_metas = Vec::new();
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        // Never saved, so always empty.
+        let fresh_cache = || EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(
+            &[(path.clone(), 100)],
+            &config,
+            &mut db,
+            &engine,
+            &mut fresh_cache(),
+        )?;
+        let id = db.chunks[0].id;
 
-    for res in file_results {
-        let (rel_path, filename, chunks, mtime) = res?;
-        
-        // Delete old entries for this file
-        db.delete_by_path(&rel_path);
-        
-        for text in chunks {
-            all_chunks.push(text.clone());
-            chunk_metas.push(ChunkMeta {
-                id: 0, // assigned by db.insert_chunks
-                path: rel_path.clone(),
-                filename: filename.clone(),
-                text,
-                mtime,
-            });
-        }
+        // Only the content hash can spare the embedding.
+        process_batch(
+            &[(path.clone(), 200)],
+            &config,
+            &mut db,
+            &engine,
+            &mut fresh_cache(),
+        )?;
+        assert_eq!(engine.calls(), 1);
+        assert_eq!(db.chunks.len(), 1);
+        assert_eq!((db.chunks[0].id, db.chunks[0].mtime), (id, 200));
+
+        fs::write(&path, "some edited note text")?;
+        process_batch(
+            &[(path, 300)],
+            &config,
+            &mut db,
+            &engine,
+            &mut fresh_cache(),
+        )?;
+        assert_eq!(engine.calls(), 2);
+        assert_eq!(db.chunks.len(), 1);
+        Ok(())
     }
 
-    if all_chunks.is_empty() {
-        return Ok(());
+    #[test]
+    fn test_trailing_slash_vault_path() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::create_dir(vault_dir.path().join("Projects"))?;
+        let file_path = vault_dir.path().join("Projects").join("plan.md");
+        fs::write(&file_path, "plan")?;
+
+        let slashed_root = PathBuf::from(format!("{}/", vault_dir.path().display()));
+        let rel_path = vault_relative_path(&file_path, &slashed_root)?;
+        assert_eq!(rel_path, "Projects/plan.md");
+
+        let mut db = Database::open(data_dir.path())?;
+        let meta = ChunkMeta {
+            path: rel_path,
+            filename: "plan".into(),
+            text: "plan".into(),
+            ..Default::default()
+        };
+        db.insert_chunks(vec![meta], vec![vec![0.1; crate::db::VECTOR_DIM]])?;
+
+        // The watcher reports removals after the file is gone, with the
+        // un-slashed root; the relative path must still match.
+        fs::remove_file(&file_path)?;
+        let removed = vault_relative_path(&file_path, vault_dir.path())?;
+        db.delete_by_path(&removed);
+        assert!(db.chunks.is_empty());
+
+        Ok(())
     }
 
-    // 3. Batched Embedding
-    println!("🧠 Generating embeddings for {} chunks...", all_chunks.len());
-    
-    // We can process in smaller batches if needed, but the engine already batches.
-    // However, BERT has a limit on sequence length and GPU/CPU memory.
-    // Let's batch by 32 chunks at a time for safety and to show progress.
-    let batch_size = 32;
-    let mut all_embeddings = Vec::with_capacity(all_chunks.len());
-    
-    for i in (0..all_chunks.len()).step_by(batch_size) {
-        let end = (i + batch_size).min(all_chunks.len());
-        let batch = all_chunks[i..end].to_vec();
-        let embeddings = engine.embed(batch)?;
-        all_embeddings.extend(embeddings);
-        
-        if (i / batch_size) % 10 == 0 {
-             println!("   ... {}/{}", end, all_chunks.len());
+    #[test]
+    fn test_process_batch_inserts_per_embedding_batch() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+
+        let mut paths = Vec::new();
+        for i in 0..5 {
+            let path = vault_dir.path().join(format!("note{}.md", i));
+            let words: Vec<String> = (0..2000).map(|j| format!("w{}x{}", i, j)).collect();
+            fs::write(&path, words.join(" "))?;
+            paths.push((path, 0));
         }
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            embed_batch_size: 4,
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&paths, &config, &mut db, &engine, &mut cache)?;
+
+        let total = db.chunks.len();
+        assert!(total > 20);
+        // One embed + insert per batch; no batch larger than the configured size.
+        assert_eq!(engine.calls(), total.div_ceil(4));
+        assert!(engine.batches.lock().unwrap().iter().all(|b| b.len() <= 4));
+        assert_eq!(db.index.size(), total);
+
+        // Vectors stay aligned with their metadata across batch boundaries.
+        let last = db.chunks.last().unwrap();
+        let hits = db.search(&MockEmbedder::vector(&last.text), 1)?;
+        let hit = db.chunks.iter().find(|c| c.id == hits[0].0).unwrap();
+        assert_eq!(hit.path, last.path);
+
+        Ok(())
     }
 
-    // 4. Insert into DB
-    db.insert_chunks(chunk_metas, all_embeddings)?;
+    #[test]
+    fn test_concurrent_embedding_keeps_vectors_aligned() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let mut paths = Vec::new();
+        for i in 0..30 {
+            let path = vault_dir.path().join(format!("note{}.md", i));
+            fs::write(&path, format!("Note {} talks about topic{}.", i, i))?;
+            paths.push((path, 0));
+        }
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            embed_batch_size: 4,
+            embed_concurrency: 3,
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&paths, &config, &mut db, &engine, &mut cache)?;
 
-    Ok(())
+        assert_eq!(db.chunks.len(), 30);
+        assert_eq!(engine.calls(), 30_usize.div_ceil(4));
+        for chunk in &db.chunks {
+            assert_eq!(
+                db.vector(chunk.id)?.unwrap(),
+                MockEmbedder::vector(&chunk.text)
+            );
+        }
+        // Ids still follow chunk order.
+        let ids: Vec<u64> = db.chunks.iter().map(|c| c.id).collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_boilerplate_is_embedded_once() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let template = "Attendees, agenda, decisions and action items go below. ".repeat(12);
+        let mut paths = Vec::new();
+        for (name, topic) in [("standup-a.md", "pricing"), ("standup-b.md", "roadmap")] {
+            let path = vault_dir.path().join(name);
+            fs::write(&path, format!("Meeting about {}.\n\n{}", topic, template))?;
+            paths.push((path, 0));
+        }
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            chunk_size: 200,
+            chunk_overlap: 0,
+            min_chunk_chars: 0,
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let embedded = || -> usize { engine.batches.lock().unwrap().iter().map(Vec::len).sum() };
+
+        // Together in one batch.
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&paths, &config, &mut db, &engine, &mut cache)?;
+        let distinct: HashSet<u64> = db.chunks.iter().map(|c| c.text_hash).collect();
+        assert!(distinct.len() < db.chunks.len());
+        assert_eq!(embedded(), distinct.len());
+
+        // Separately, with no cache to fall back on: the index has the vectors.
+        db.clear()?;
+        engine.batches.lock().unwrap().clear();
+        let mut cold_cache = EmbeddingCache::load(vault_dir.path(), &cache_id(&config));
+        process_batch(&paths[..1], &config, &mut db, &engine, &mut cold_cache)?;
+        let mut cold_cache = EmbeddingCache::load(vault_dir.path(), &cache_id(&config));
+        process_batch(&paths[1..], &config, &mut db, &engine, &mut cold_cache)?;
+        assert_eq!(embedded(), distinct.len());
+        assert!(embedded() < db.chunks.len());
+
+        // Each chunk still has a vector of its own, so deleting a note spares the other's.
+        db.delete_by_path("standup-a.md");
+        for chunk in &db.chunks {
+            assert_eq!(
+                db.vector(chunk.id)?.unwrap(),
+                MockEmbedder::vector(&chunk.text)
+            );
+            // The boilerplate stays reusable through the other note's copy.
+            assert!(db.vector_for_text(chunk.text_hash)?.is_some());
+        }
+
+        // Vectors embedded with another document prefix aren't reused.
+        db.record_settings(&config);
+        assert!(db.vector_for_text(db.chunks[1].text_hash)?.is_some());
+        let prefixed = Config {
+            document_prefix: "passage: ".into(),
+            ..config.clone()
+        };
+        db.configure(&prefixed)?;
+        assert_eq!(db.vector_for_text(db.chunks[1].text_hash)?, None);
+        Ok(())
+    }
+
+    /// Needs the model. Run with
+    /// `cargo test --release bench_embed_concurrency -- --ignored --nocapture`
+    /// to compare full-index times of a 1000-note vault per `embed_concurrency`.
+    #[test]
+    #[ignore]
+    fn bench_embed_concurrency() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let mut paths = Vec::new();
+        for i in 0..1000 {
+            let path = vault_dir.path().join(format!("note{}.md", i));
+            let words: Vec<String> = (0..150)
+                .map(|j| format!("word{}", (i * 7 + j) % 500))
+                .collect();
+            fs::write(&path, words.join(" "))?;
+            paths.push((path, 0));
+        }
+        let engine = EmbeddingEngine::load(crate::config::MODEL_ID, DownloadRetry::default())?;
+
+        for concurrency in [1, 2, 4] {
+            let data_dir = tempdir()?;
+            let config = Config {
+                vault_path: vault_dir.path().to_path_buf(),
+                embed_concurrency: concurrency,
+                ..Default::default()
+            };
+            let mut db = Database::open(data_dir.path())?;
+            let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+            let start = std::time::Instant::now();
+            process_batch(&paths, &config, &mut db, &engine, &mut cache)?;
+            println!(
+                "embed_concurrency {}: {} chunks in {:?}",
+                concurrency,
+                db.chunks.len(),
+                start.elapsed()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_restores_notes_dropped_by_repair() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("a.md"), "Alpha notes.")?;
+        fs::write(vault_dir.path().join("b.md"), "Beta notes.")?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        run_index(&config, &mut db, &engine, data_dir.path(), false, None)?;
+
+        let id = db.chunks.iter().find(|c| c.path == "a.md").unwrap().id;
+        db.index.remove(id)?;
+        db.save_now()?;
+        drop(db);
+
+        // a.md hasn't changed since the last sync, but is indexed again.
+        let mut db = Database::open(data_dir.path())?;
+        assert_eq!(db.file_count(), 1);
+        run_index(&config, &mut db, &engine, data_dir.path(), false, None)?;
+        assert_eq!(db.file_count(), 2);
+        assert!(db.verify()?.is_consistent());
+        assert!(!Database::open(data_dir.path())?.is_incomplete());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_changes_nothing() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("a.md"), "Alpha notes.")?;
+        fs::write(vault_dir.path().join("b.md"), "Beta notes.")?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        run_index(&config, &mut db, &engine, data_dir.path(), false, None)?;
+        let calls = engine.calls();
+
+        fs::remove_file(vault_dir.path().join("a.md"))?;
+        fs::write(vault_dir.path().join("c.md"), "Gamma notes.")?;
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(vault_dir.path().join("c.md"))?
+            .set_modified(later)?;
+        // b.md is only touched, which indexing wouldn't re-embed.
+        fs::File::options()
+            .write(true)
+            .open(vault_dir.path().join("b.md"))?
+            .set_modified(later)?;
+        let indexed = db.content_hashes();
+        let plan = plan_index(&config, &indexed, false, data_dir.path(), false, None)?;
+        assert_eq!(plan.files, vec![("c.md".to_string(), 1)]);
+        assert_eq!(plan.deleted, 1);
+
+        let plan = plan_index(&config, &indexed, false, data_dir.path(), true, None)?;
+        assert_eq!(plan.files.len(), 2);
+        assert_eq!(plan.chunks(), 2);
+
+        assert_eq!(engine.calls(), calls);
+        assert_eq!(db.file_count(), 2);
+        Ok(())
+    }
+
+    /// Embeds like `MockEmbedder`, but first lets a test observe the manager
+    /// from inside an indexing run.
+    struct EmbedHook {
+        manager: std::sync::OnceLock<std::sync::Weak<SyncManager>>,
+        hook: Box<dyn Fn(&SyncManager) + Send + Sync>,
+    }
+
+    impl EmbedHook {
+        fn new(hook: impl Fn(&SyncManager) + Send + Sync + 'static) -> Arc<Self> {
+            Arc::new(Self {
+                manager: Default::default(),
+                hook: Box::new(hook),
+            })
+        }
+
+        fn manager(self: &Arc<Self>, db: Database, vault: &Path, data: &Path) -> Arc<SyncManager> {
+            let manager = Arc::new(SyncManager::new(
+                Arc::new(Mutex::new(db)),
+                self.clone(),
+                Config {
+                    vault_path: vault.to_path_buf(),
+                    ..Default::default()
+                },
+                data.to_path_buf(),
+            ));
+            self.manager.set(Arc::downgrade(&manager)).unwrap();
+            manager
+        }
+    }
+
+    impl Embedder for EmbedHook {
+        fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+            if let Some(manager) = self.manager.get().and_then(|m| m.upgrade()) {
+                (self.hook)(&manager);
+            }
+            MockEmbedder::default().embed(texts)
+        }
+    }
+
+    #[test]
+    fn test_full_index_reports_progress() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("a.md"), "first note")?;
+        fs::write(vault_dir.path().join("b.md"), "second note")?;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook = EmbedHook::new({
+            let seen = seen.clone();
+            move |m| seen.lock().unwrap().push(m.status_text())
+        });
+        let db = Database::open(data_dir.path())?;
+        let manager = hook.manager(db, vault_dir.path(), data_dir.path());
+
+        let reported = std::cell::RefCell::new(Vec::new());
+        let on_progress = |p: IndexProgress| reported.borrow_mut().push(p);
+        manager.full_index_reporting(true, Some(&on_progress))?;
+
+        assert_eq!(*seen.lock().unwrap(), ["Indexing 0/2 files..."]);
+        assert_eq!(
+            reported.into_inner(),
+            [
+                IndexProgress::embedding(0, 2),
+                IndexProgress::embedding(2, 2),
+                IndexProgress::embedding(2, 2).saving(),
+            ]
+        );
+        assert!(manager.progress().is_none());
+        assert!(manager.status_text().starts_with("Last indexed: "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_keeps_a_consistent_partial_index() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        for i in 0..150 {
+            fs::write(vault_dir.path().join(format!("note{}.md", i)), "a note")?;
+        }
+
+        let once = std::sync::Once::new();
+        let hook = EmbedHook::new(move |m| {
+            once.call_once(|| assert!(m.cancel_index()));
+        });
+        let db = Database::open(data_dir.path())?;
+        let manager = hook.manager(db, vault_dir.path(), data_dir.path());
+
+        manager.full_index(false)?;
+        assert!(manager.progress().is_none());
+        assert!(!manager.cancel_index());
+        // The first batch of 100 files was saved; the run didn't count as a sync.
+        assert!(manager.last_sync_time.lock().unwrap().is_none());
+        {
+            let db = manager.db.lock().unwrap();
+            assert_eq!(db.file_count(), 100);
+            assert!(db.verify()?.is_consistent());
+        }
+        drop(manager);
+        let saved = Database::open(data_dir.path())?;
+        assert_eq!(saved.file_count(), 100);
+        drop(saved);
+
+        // The next sync indexes the rest.
+        let db = Database::open(data_dir.path())?;
+        let hook = EmbedHook::new(|_| {});
+        let manager = hook.manager(db, vault_dir.path(), data_dir.path());
+        manager.full_index(false)?;
+        assert_eq!(manager.db.lock().unwrap().file_count(), 150);
+        Ok(())
+    }
+
+    #[test]
+    fn test_forced_reindex_serves_old_index_until_swap() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("new.md"), "fresh content")?;
+
+        // The live index only knows about a note that's since been deleted.
+        let mut db = Database::open(data_dir.path())?;
+        let old = ChunkMeta {
+            path: "old.md".into(),
+            filename: "old".into(),
+            text: "stale content".into(),
+            ..Default::default()
+        };
+        db.insert_chunks(vec![old], vec![MockEmbedder::vector("stale content")])?;
+
+        let searched = Arc::new(Mutex::new(Vec::new()));
+        let hook = EmbedHook::new({
+            let searched = searched.clone();
+            move |m| {
+                // Mid-build the live DB must be free and still the old one.
+                let db = m.db.try_lock().expect("search blocked during reindex");
+                let options = SearchOptions::default();
+                let results = run_search("stale content", &db, &MockEmbedder::default(), &options)
+                    .unwrap()
+                    .matches;
+                searched
+                    .lock()
+                    .unwrap()
+                    .extend(results.into_iter().map(|r| r.path));
+            }
+        });
+        let manager = hook.manager(db, vault_dir.path(), data_dir.path());
+
+        manager.full_index(true)?;
+
+        assert_eq!(*searched.lock().unwrap(), ["old.md"]);
+        let db = manager.db.lock().unwrap();
+        let paths: Vec<&str> = db.chunks.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["new.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_folder_renamed_during_rebuild_survives_the_swap() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::create_dir(vault_dir.path().join("Projects"))?;
+        fs::write(vault_dir.path().join("Projects/plan.md"), "launch plan")?;
+
+        let renamed = Arc::new(AtomicBool::new(false));
+        let hook = EmbedHook::new({
+            let renamed = renamed.clone();
+            move |m| {
+                if renamed.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                let (from, to) = (m.vault_path.join("Projects"), m.vault_path.join("Done"));
+                fs::rename(&from, &to).unwrap();
+                m.rename_folder(&from, &to).unwrap();
+            }
+        });
+        let manager = hook.manager(
+            Database::open(data_dir.path())?,
+            vault_dir.path(),
+            data_dir.path(),
+        );
+        manager.full_index(true)?;
+
+        assert!(renamed.load(Ordering::SeqCst));
+        let db = manager.db.lock().unwrap();
+        let paths: Vec<&str> = db.chunks.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["Done/plan.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_document_prefix_only_affects_embedded_text() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let path = vault_dir.path().join("note.md");
+        fs::write(&path, "some note text")?;
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            document_prefix: "passage: ".into(),
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&[(path, 0)], &config, &mut db, &engine, &mut cache)?;
+
+        let sent = engine.batches.lock().unwrap().concat();
+        assert!(sent[0].starts_with("passage: FILE_NAME: note"));
+        assert!(db.chunks[0].text.starts_with("FILE_NAME: note"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_fix_reembeds_with_document_prefix() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("note.md"), "some note text")?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            document_prefix: "passage: ".into(),
+            ..Default::default()
+        };
+        let manager = test_manager_with(config, data_dir.path())?;
+        manager.full_index(false)?;
+        let (id, text) = {
+            let db = manager.db.lock().unwrap();
+            db.index.remove(db.chunks[0].id)?;
+            (db.chunks[0].id, db.chunks[0].text.clone())
+        };
+
+        let report = manager.verify(true)?;
+        assert_eq!(report.missing_vectors, [id]);
+        let vector = manager.db.lock().unwrap().vector(id)?;
+        assert_eq!(
+            vector,
+            Some(MockEmbedder::vector(&format!("passage: {}", text)))
+        );
+        assert!(manager.verify(false)?.is_consistent());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_purges_notes_deleted_offline() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("kept.md"), "a note that stays")?;
+        fs::write(vault_dir.path().join("gone.md"), "a note that goes")?;
+
+        let manager = test_manager(vault_dir.path(), data_dir.path())?;
+        manager.full_index(false)?;
+        assert_eq!(manager.db.lock().unwrap().file_count(), 2);
+
+        // Deleted with nothing watching; the incremental sync finds nothing changed.
+        fs::remove_file(vault_dir.path().join("gone.md"))?;
+        manager.full_index(false)?;
+
+        let db = manager.db.lock().unwrap();
+        let paths: Vec<&str> = db.chunks.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["kept.md"]);
+        assert!(db.verify()?.is_consistent());
+        assert_eq!(db.index.size(), db.chunks.len());
+        drop(db);
+        assert_eq!(Database::open(data_dir.path())?.file_count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_extension_vault() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("note.md"), "a markdown note")?;
+        fs::write(vault_dir.path().join("todo.txt"), "a plain text list")?;
+        fs::write(vault_dir.path().join("agenda.org"), "* an org heading")?;
+        fs::write(vault_dir.path().join("scan.pdf"), "not a note")?;
+        fs::write(
+            vault_dir.path().join("board.canvas"),
+            r#"{"nodes": [{"id": "1", "type": "text", "text": "a canvas card"}]}"#,
+        )?;
+        fs::write(
+            vault_dir.path().join("Plan.CANVAS"),
+            r#"{"nodes": [{"id": "1", "type": "text", "text": "an upper-case canvas"}]}"#,
+        )?;
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            indexed_extensions: vec!["md".into(), ".txt".into(), "canvas".into()],
+            ..Default::default()
+        };
+        let manager = test_manager_with(config, data_dir.path())?;
+        manager.full_index(false)?;
+
+        let db = manager.db.lock().unwrap();
+        let mut paths: Vec<&str> = db.chunks.iter().map(|c| c.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            ["Plan.CANVAS", "board.canvas", "note.md", "todo.txt"]
+        );
+        let canvas = db.chunks.iter().find(|c| c.path == "board.canvas").unwrap();
+        assert_eq!(strip_identity_header(&canvas.text), "a canvas card");
+        let canvas = db.chunks.iter().find(|c| c.path == "Plan.CANVAS").unwrap();
+        assert_eq!(strip_identity_header(&canvas.text), "an upper-case canvas");
+        drop(db);
+
+        // The watcher's single-file path follows the same setting.
+        manager.index_file(&vault_dir.path().join("agenda.org"))?;
+        assert_eq!(manager.db.lock().unwrap().file_count(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignored_folders_match_names_and_globs() {
+        let config = Config {
+            ignore_folders: vec!["Archive".into(), "Daily/202?".into(), "_*/".into()],
+            ..Default::default()
+        };
+        let ignored = |rel_path: &str| is_ignored(Path::new(rel_path), &config);
+
+        // Plain names match a folder at any depth, built-in or configured.
+        assert!(ignored("templates"));
+        assert!(ignored("Archive"));
+        assert!(ignored("Projects/Archive/2023"));
+        assert!(!ignored("Archives"));
+        assert!(ignored("Projects/_scratch"));
+
+        // Patterns with a slash match from the vault root.
+        assert!(ignored("Daily/2024"));
+        assert!(!ignored("Daily"));
+        assert!(!ignored("Journal/Daily/2024"));
+    }
+
+    #[test]
+    fn test_ignored_folder_patterns_skip_notes_named_alike() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            ignore_folders: vec!["Archive*".into(), "_*".into()],
+            ..Default::default()
+        };
+        for rel_path in [
+            "Archive.md",
+            "_inbox.md",
+            "Archive/old.md",
+            "_drafts/idea.md",
+        ] {
+            let path = vault_dir.path().join(rel_path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, "a note")?;
+        }
+        let scan = scan_vault(vault_dir.path(), &config, None)?;
+        let mut present: Vec<String> = scan.present.into_iter().collect();
+        present.sort();
+        assert_eq!(present, ["Archive.md", "_inbox.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stored_paths_use_forward_slashes() {
+        assert_eq!(
+            with_forward_slashes(r"Projects\2024\plan.md", '\\'),
+            "Projects/2024/plan.md"
+        );
+        assert_eq!(
+            with_forward_slashes("Projects/plan.md", '/'),
+            "Projects/plan.md"
+        );
+        assert_eq!(with_forward_slashes("plan.md", '\\'), "plan.md");
+    }
+
+    #[test]
+    fn test_stale_index_triggers_background_sync() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("note.md"), "edited while asleep")?;
+
+        let stale = Utc::now() - Duration::hours(48);
+        let meta = Meta { last_sync: stale };
+        fs::write(
+            data_dir.path().join("meta.json"),
+            serde_json::to_string(&meta)?,
+        )?;
+
+        let manager = Arc::new(test_manager(vault_dir.path(), data_dir.path())?);
+
+        let sync = manager.sync_if_stale().expect("stale index should sync");
+        sync.join().unwrap();
+        assert_eq!(manager.db.lock().unwrap().chunks.len(), 1);
+
+        // Now fresh: the next search doesn't sync again.
+        assert!(manager.sync_if_stale().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_excluded_code_blocks_are_not_chunked() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let path = vault_dir.path().join("setup.md");
+        fs::write(
+            &path,
+            "How the build works.\n```sh\ncargo build --release\n```\n",
+        )?;
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            code_blocks: markdown::CodeBlocks::Exclude,
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&[(path, 0)], &config, &mut db, &engine, &mut cache)?;
+
+        assert!(db.chunks.iter().all(|c| !c.text.contains("cargo build")));
+        assert!(db.chunks[0].text.contains("How the build works.\n[code]"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_frontmatter_fields_replace_raw_yaml() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let path = vault_dir.path().join("loaf.md");
+        fs::write(
+            &path,
+            "---\ntags: [recipe, bread]\ncreated: 2024-01-01\n---\nFlour, water, salt.\n",
+        )?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            min_chunk_chars: 0,
+            ..Default::default()
+        };
+
+        let file = chunk_file(&path, 0, &config, None)?;
+        let (text, span) = &file.chunks[0];
+        assert!(text.contains("Tagged: recipe, bread\n"));
+        assert!(!text.contains("tags:"));
+        assert!(!text.contains("created"));
+        assert_eq!(strip_identity_header(text), "Flour, water, salt.\n");
+        assert_eq!(*span, (50, 70));
+        // The frontmatter isn't counted as words of the note.
+        assert_eq!(file.word_count, 3);
+
+        // Malformed YAML is still left out, with no fields taken from it.
+        fs::write(&path, "---\ntags: [recipe\n---\nFlour, water, salt.\n")?;
+        let file = chunk_file(&path, 0, &config, None)?;
+        assert!(!file.chunks[0].0.contains("recipe"));
+        assert!(file.tags.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wikilinks_are_stored_and_embedded_as_shown() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let path = vault_dir.path().join("dinner.md");
+        fs::write(
+            &path,
+            "Made [[Bread]] with [[Recipes/Pasta|fresh pasta]], as in [[bread#Starter]].\n",
+        )?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            min_chunk_chars: 0,
+            ..Default::default()
+        };
+
+        let file = chunk_file(&path, 0, &config, None)?;
+        assert_eq!(file.links, ["Bread", "Recipes/Pasta"]);
+        let (text, span) = &file.chunks[0];
+        assert_eq!(
+            strip_identity_header(text),
+            "Made Bread with fresh pasta, as in bread > Starter.\n"
+        );
+        // The span still covers the note as written.
+        assert_eq!(*span, (0, 76));
+        Ok(())
+    }
+
+    #[test]
+    fn test_thin_notes_follow_the_policy() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let moc = vault_dir.path().join("Cooking.md");
+        fs::write(&moc, "# Cooking\n- [[Bread]]\n- [[Pasta|Fresh pasta]]\n")?;
+        let chunks = |thin_notes| -> Result<Vec<String>> {
+            let config = Config {
+                vault_path: vault_dir.path().to_path_buf(),
+                thin_notes,
+                ..Default::default()
+            };
+            let file = chunk_file(&moc, 0, &config, None)?;
+            Ok(file.chunks.into_iter().map(|(text, _)| text).collect())
+        };
+
+        let embedded = chunks(ThinNotes::Embed)?;
+        assert_eq!(embedded.len(), 1);
+        assert!(embedded[0].contains("- Fresh pasta"));
+
+        let title_only = chunks(ThinNotes::TitleOnly)?;
+        assert_eq!(title_only.len(), 1);
+        assert!(title_only[0].starts_with("FILE_NAME: Cooking\n"));
+        assert_eq!(strip_identity_header(&title_only[0]), "Bread\nPasta");
+
+        assert!(chunks(ThinNotes::Skip)?.is_empty());
+
+        // Notes with prose are chunked as usual whatever the policy.
+        fs::write(&moc, "# Cooking\nI cook most weekends, mostly [[Bread]].\n")?;
+        assert!(chunks(ThinNotes::Skip)?[0].contains("most weekends"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_heading_chunks_carry_their_section() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let path = vault_dir.path().join("plan.md");
+        fs::write(&path, "# Project\nWhy.\n## Goals\nShip it.\n")?;
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            split_on_headings: true,
+            ..Default::default()
+        };
+        let file = chunk_file(&path, 0, &config, None)?;
+        assert_eq!(file.chunks.len(), 2);
+        let (text, span) = &file.chunks[1];
+        assert!(text.starts_with("FILE_NAME: plan\n"));
+        assert!(text.contains("SECTION: Project > Goals\n--- START OF CONTENT ---\n## Goals"));
+        assert_eq!(strip_identity_header(text), "## Goals\nShip it.\n");
+        assert_eq!(*span, (15, 33));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_only_fields_stay_out_of_embedded_text() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let path = vault_dir.path().join("bread.md");
+        fs::write(
+            &path,
+            "---\ntags: [recipe]\naliases: [Loaf]\n---\nFlour, water, salt.\n",
+        )?;
+
+        let mut config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            min_chunk_chars: 0,
+            ..Default::default()
+        };
+        config.fields.tags = FieldRole::FilterOnly;
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&[(path, 0)], &config, &mut db, &engine, &mut cache)?;
+
+        assert!(db.chunks[0].text.contains("Also known as: Loaf\n"));
+        assert!(!db.chunks[0].text.contains("recipe"));
+        assert_eq!(db.chunks[0].tags, ["recipe"]);
+
+        let filtered = |value: &str| -> Result<Vec<String>> {
+            let options = SearchOptions {
+                filters: vec![MetaFilter {
+                    field: MetaField::Tags,
+                    value: value.into(),
+                }],
+                ..Default::default()
+            };
+            let results = run_search("flour", &db, &engine, &options)?.matches;
+            Ok(results.into_iter().map(|r| r.path).collect())
+        };
+        assert_eq!(filtered("Recipe")?, ["bread.md"]);
+        assert!(filtered("journal")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_filter_uses_frontmatter_and_inline_tags() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let notes = [
+            (
+                "pasta.md",
+                "---\ntags: [Recipe]\n---\nQuick dinner, #weeknight too.\n",
+            ),
+            ("stew.md", "Slow dinner. #recipe #cooking/winter\n"),
+            ("diary.md", "Dinner with friends.\n"),
+        ];
+        let mut files = Vec::new();
+        for (name, content) in notes {
+            let path = vault_dir.path().join(name);
+            fs::write(&path, content)?;
+            files.push((path, 0));
+        }
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            min_chunk_chars: 0,
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&files, &config, &mut db, &engine, &mut cache)?;
+
+        let tagged = |tags: &[&str]| -> Result<Vec<String>> {
+            let options = SearchOptions {
+                filters: tags
+                    .iter()
+                    .map(|tag| MetaFilter {
+                        field: MetaField::Tags,
+                        value: tag.to_string(),
+                    })
+                    .collect(),
+                score_threshold: f32::MAX,
+                ..Default::default()
+            };
+            let results = run_search("dinner", &db, &engine, &options)?.matches;
+            let mut paths: Vec<String> = results.into_iter().map(|r| r.path).collect();
+            paths.sort();
+            Ok(paths)
+        };
+        assert_eq!(tagged(&[])?, ["diary.md", "pasta.md", "stew.md"]);
+        assert_eq!(tagged(&["RECIPE"])?, ["pasta.md", "stew.md"]);
+        assert_eq!(tagged(&["recipe", "weeknight"])?, ["pasta.md"]);
+        assert_eq!(tagged(&["cooking"])?, ["stew.md"]);
+        assert!(tagged(&["cook"])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_from_daemon_matches_disk() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        fs::write(vault_dir.path().join("a.md"), "First note")?;
+        fs::write(vault_dir.path().join("b.md"), "Second note")?;
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let empty = IndexStatus::from_disk(&config, data_dir.path())?;
+        assert_eq!((empty.files, empty.chunks, empty.last_sync), (0, 0, None));
+
+        let manager = test_manager_with(config.clone(), data_dir.path())?;
+        manager.full_index(false)?;
+        let live = manager.index_status()?;
+        assert_eq!((live.files, live.chunks, live.progress), (2, 2, None));
+        assert!(live.last_sync.is_some());
+        drop(manager);
+
+        let on_disk = IndexStatus::from_disk(&config, data_dir.path())?;
+        assert_eq!((on_disk.files, on_disk.chunks), (2, 2));
+        assert_eq!(on_disk.last_sync, live.last_sync);
+        Ok(())
+    }
+
+    #[test]
+    fn test_watcher_changes_are_saved_by_flush() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = test_manager(vault_dir.path(), data_dir.path())?;
+        let chunks_file = data_dir.path().join("chunks.json");
+        for name in ["a.md", "b.md"] {
+            let note = vault_dir.path().join(name);
+            fs::write(&note, "Some content")?;
+            manager.index_file(&note)?;
+        }
+        assert!(manager.db.lock().unwrap().is_dirty());
+        assert!(!chunks_file.exists());
+
+        manager.flush();
+        assert!(!manager.db.lock().unwrap().is_dirty());
+        assert_eq!(Database::open(data_dir.path())?.file_count(), 2);
+
+        // Nothing to write the next time round.
+        fs::remove_file(&chunks_file)?;
+        manager.flush();
+        assert!(!chunks_file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_saves_wait_for_each_other() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = Arc::new(test_manager_with(
+            Config {
+                vault_path: vault_dir.path().to_path_buf(),
+                save_interval_secs: 0,
+                ..Default::default()
+            },
+            data_dir.path(),
+        )?);
+        let note = vault_dir.path().join("note.md");
+        fs::write(&note, "Some content")?;
+        let chunks_file = data_dir.path().join("chunks.json");
+
+        // Stand in for a save in progress on another thread.
+        let saving = manager.db.lock().unwrap();
+        let watcher = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.index_file(&note))
+        };
+        let sync = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.full_index(false))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!chunks_file.exists());
+        assert!(!data_dir.path().join("meta.json").exists());
+
+        drop(saving);
+        watcher.join().unwrap()?;
+        sync.join().unwrap()?;
+        assert!(chunks_file.exists());
+        assert_eq!(Database::open(data_dir.path())?.chunks.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeatedly_failing_file_is_skipped_until_changed() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = test_manager_with(
+            Config {
+                vault_path: vault_dir.path().to_path_buf(),
+                max_index_failures: 2,
+                ..Default::default()
+            },
+            data_dir.path(),
+        )?;
+
+        // Not UTF-8, so reading it fails every time.
+        let path = vault_dir.path().join("corrupt.md");
+        fs::write(&path, [0xff, 0xfe, 0xfd])?;
+        assert!(manager.index_file(&path).is_err());
+        assert!(manager.skipped_files().is_empty());
+        assert!(manager.index_file(&path).is_err());
+        let skipped = manager.skipped_files();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].path, path);
+        assert!(skipped[0].reason.starts_with("failed 2 times: "));
+        assert_eq!(manager.index_status()?.skipped, skipped);
+        assert!(manager.status_text().ends_with("1 failing files skipped"));
+
+        // Skipped without trying again.
+        assert!(manager.index_file(&path).is_ok());
+        assert!(manager.db.lock().unwrap().chunks.is_empty());
+
+        // Fixing the file changes its mtime and lifts the skip.
+        fs::write(&path, "repaired note")?;
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(later)?;
+        manager.index_file(&path)?;
+        assert!(manager.skipped_files().is_empty());
+        assert_eq!(manager.db.lock().unwrap().chunks.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_reembeds_only_new_chunks() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let path = vault_dir.path().join("journal.md");
+        let mut journal = "Day one. ".repeat(300);
+        fs::write(&path, &journal)?;
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            reuse_unchanged_chunks: true,
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(&[(path.clone(), 0)], &config, &mut db, &engine, &mut cache)?;
+        let before: Vec<(u64, usize)> = db.chunks.iter().map(|c| (c.id, c.start)).collect();
+        let unchanged = before.len() - 1;
+        assert!(unchanged >= 2);
+
+        journal += &"Day two. ".repeat(300);
+        fs::write(&path, &journal)?;
+        // An empty cache, so the saving comes from the index alone.
+        let mut cold_cache = EmbeddingCache::load(vault_dir.path(), &cache_id(&config));
+        engine.batches.lock().unwrap().clear();
+        process_batch(&[(path, 1)], &config, &mut db, &engine, &mut cold_cache)?;
+
+        // The last old chunk ran to the old end of the note, so it changed too.
+        // Repeated windows of the new text are embedded once.
+        let kept: HashSet<&str> = db.chunks[..unchanged]
+            .iter()
+            .map(|c| c.text.as_str())
+            .collect();
+        let new: HashSet<&str> = db.chunks[unchanged..]
+            .iter()
+            .map(|c| c.text.as_str())
+            .filter(|text| !kept.contains(text))
+            .collect();
+        let embedded: usize = engine.batches.lock().unwrap().iter().map(Vec::len).sum();
+        assert_eq!(embedded, new.len());
+        assert!(new.len() < db.chunks.len() - unchanged);
+        assert_eq!(
+            db.chunks[..unchanged]
+                .iter()
+                .map(|c| (c.id, c.start))
+                .collect::<Vec<_>>(),
+            before[..unchanged]
+        );
+        assert!(db.chunks.iter().all(|c| c.mtime == 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_frontmatter_chunk_size_applies_to_that_note() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let body = "word ".repeat(200);
+        let custom = vault_dir.path().join("custom.md");
+        fs::write(
+            &custom,
+            format!(
+                "---\nobra_chunk_size: 300\nobra_chunk_overlap: 0\n---\n{}",
+                body
+            ),
+        )?;
+        let plain = vault_dir.path().join("plain.md");
+        fs::write(&plain, &body)?;
+
+        let config = Config {
+            vault_path: vault_dir.path().to_path_buf(),
+            min_chunk_chars: 0,
+            ..Default::default()
+        };
+        let mut db = Database::open(data_dir.path())?;
+        let engine = MockEmbedder::default();
+        let mut cache = EmbeddingCache::load(data_dir.path(), &cache_id(&config));
+        process_batch(
+            &[(custom, 0), (plain, 0)],
+            &config,
+            &mut db,
+            &engine,
+            &mut cache,
+        )?;
+
+        let chunks_of = |path: &str| {
+            db.chunks
+                .iter()
+                .filter(|c| c.path == path)
+                .map(|c| c.text.chars().count())
+                .collect::<Vec<_>>()
+        };
+        let custom_chunks = chunks_of("custom.md");
+        assert!(custom_chunks.len() > 3);
+        assert!(custom_chunks.iter().all(|&n| n <= 300));
+        assert!(chunks_of("plain.md").len() < custom_chunks.len());
+        Ok(())
+    }
 }
@@ -1,54 +1,445 @@
-use crate::index::SyncManager;
-use crate::search::{run_search, SearchResult};
+use crate::error::ObraError;
+use crate::index::{IndexProgress, IndexSummary, SyncManager};
+use crate::search::{
+    run_batch_search, run_search, FileMatch, SearchHits, SearchOptions, SearchResult,
+};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use interprocess::local_socket::{LocalSocketListener, LocalSocketStream};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::io::{BufRead, BufReader, Write};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, RwLock};
 
 #[derive(Serialize, Deserialize)]
 pub struct SearchRequest {
     pub query: String,
+    #[serde(default)]
+    pub options: SearchOptions,
+    /// Also return the matching chunks of each file, in `SearchResponse::matches`.
+    #[serde(default)]
+    pub grouped: bool,
+}
+
+/// Several searches in one call, e.g. for link suggestions across many notes.
+/// The queries are embedded together.
+#[derive(Serialize, Deserialize)]
+pub struct BatchSearchRequest {
+    pub queries: Vec<String>,
+    #[serde(default)]
+    pub options: SearchOptions,
+    #[serde(default)]
+    pub grouped: bool,
+}
+
+/// Version of the requests and responses in this module. Bump it when their shape changes,
+/// so a daemon and a CLI of different versions fail cleanly instead of misreading
+/// each other.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// A request as it goes over the socket, e.g.
+/// `{"protocol_version":2,"request":{"kind":"pause"}}`.
+#[derive(Serialize, Deserialize)]
+pub struct Envelope {
+    pub protocol_version: u32,
+    pub request: Request,
+}
+
+/// The version alone, read before the rest so a mismatch is reported as such.
+#[derive(Deserialize)]
+struct EnvelopeVersion {
+    protocol_version: Option<u32>,
+}
+
+/// What a client asks the daemon, tagged by `kind`. Commands other than searches
+/// are answered with a `ControlResponse` unless noted otherwise.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Request {
+    /// Answered with a `SearchResponse`.
+    Search(SearchRequest),
+    /// Answered with a `BatchSearchResponse`.
+    BatchSearch(BatchSearchRequest),
+    /// Stop indexing watcher events until `Resume`.
+    Pause,
+    /// Resume watching and sync what changed while paused.
+    Resume,
+    /// Report on the index, answered with an `IndexStatus`.
+    Status,
+    /// Stop the daemon once the searches in flight have been answered.
+    Shutdown,
+    /// Stop a running index after its current batch.
+    Cancel,
+    /// Report what indexing would do, answered with an `IndexPlan`.
+    DryRun { force: bool },
+    /// Index what changed, or everything if `force`, answered with a stream of
+    /// `ReindexFrame`s that ends with `ReindexFrame::Done`.
+    Reindex { force: bool },
+    /// Compact the index, answered with a `CompactReport`.
+    Compact,
+    /// Cross-check the index, reconciling it if `fix`, answered with a `VerifyReport`.
+    Verify { fix: bool },
+}
+
+/// The answer to a request the daemon couldn't take: one of another protocol
+/// version, or of a kind it doesn't know.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ObraError>,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn into_error(self) -> anyhow::Error {
+        match self.error {
+            Some(kind) => kind.into(),
+            None => anyhow::anyhow!(self.message),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub paused: bool,
+    /// Whether a `Cancel` found an index to stop.
+    #[serde(default)]
+    pub canceled: bool,
+}
+
+/// One line of the answer to a `Reindex`. Clients skip frames they don't know, so
+/// newer daemons can add kinds.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "frame", rename_all = "snake_case")]
+pub enum ReindexFrame {
+    Progress(IndexProgress),
+    /// The last frame.
+    Done(ReindexResponse),
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReindexResponse {
+    #[serde(flatten)]
+    pub summary: IndexSummary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ObraError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl ReindexResponse {
+    pub fn from_result(result: Result<IndexSummary>) -> Self {
+        match result {
+            Ok(summary) => Self {
+                summary,
+                error: None,
+                message: None,
+            },
+            Err(e) => Self {
+                summary: IndexSummary::default(),
+                error: ObraError::find(&e),
+                message: Some(e.to_string()),
+            },
+        }
+    }
+
+    pub fn into_summary(self) -> Result<IndexSummary> {
+        match (self.error, self.message) {
+            (Some(kind), _) => Err(kind.into()),
+            (None, Some(message)) => Err(anyhow::anyhow!(message)),
+            (None, None) => Ok(self.summary),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SearchResponse {
     pub results: Vec<SearchResult>,
+    /// The same files as `results` with their chunk hits, for grouped requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches: Option<Vec<FileMatch>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ObraError>,
+    /// Description of a failure that isn't one of the `ObraError` kinds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// When the daemon last synced, so clients can warn about a stale index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<DateTime<Utc>>,
+    /// How many notes matched, including any beyond the requested limit.
+    #[serde(default)]
+    pub total: usize,
+}
+
+impl SearchResponse {
+    pub fn from_result(result: Result<Vec<SearchResult>>) -> Self {
+        match result {
+            Ok(results) => Self {
+                total: results.len(),
+                results,
+                matches: None,
+                error: None,
+                message: None,
+                last_sync: None,
+            },
+            Err(e) => Self {
+                results: Vec::new(),
+                matches: None,
+                error: ObraError::find(&e),
+                message: Some(e.to_string()),
+                last_sync: None,
+                total: 0,
+            },
+        }
+    }
+
+    /// Builds the response for a search, keeping the chunk hits if `grouped`.
+    pub fn from_matches(result: Result<SearchHits>, grouped: bool) -> Self {
+        match result {
+            Ok(SearchHits { matches, total }) => Self {
+                results: matches.iter().map(SearchResult::from).collect(),
+                matches: grouped.then_some(matches),
+                error: None,
+                message: None,
+                last_sync: None,
+                total,
+            },
+            Err(e) => Self::from_result(Err(e)),
+        }
+    }
+}
+
+/// One `SearchResponse` per query of a `BatchSearchRequest`, in order. A failure
+/// of the whole batch is reported once, with `responses` left empty.
+#[derive(Serialize, Deserialize)]
+pub struct BatchSearchResponse {
+    pub responses: Vec<SearchResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ObraError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<DateTime<Utc>>,
+}
+
+impl BatchSearchResponse {
+    pub fn from_matches(result: Result<Vec<SearchHits>>, grouped: bool) -> Self {
+        match result {
+            Ok(sets) => Self {
+                responses: sets
+                    .into_iter()
+                    .map(|hits| SearchResponse::from_matches(Ok(hits), grouped))
+                    .collect(),
+                error: None,
+                message: None,
+                last_sync: None,
+            },
+            Err(e) => Self {
+                responses: Vec::new(),
+                error: ObraError::find(&e),
+                message: Some(e.to_string()),
+                last_sync: None,
+            },
+        }
+    }
+}
+
+/// Turns a daemon response back into the result the daemon computed.
+pub fn into_results(resp: SearchResponse) -> Result<Vec<SearchResult>> {
+    match (resp.error, resp.message) {
+        (Some(kind), _) => Err(kind.into()),
+        (None, Some(message)) => Err(anyhow::anyhow!(message)),
+        (None, None) => Ok(resp.results),
+    }
+}
+
+/// Like `into_results`, for the response to a grouped request.
+pub fn into_matches(mut resp: SearchResponse) -> Result<Vec<FileMatch>> {
+    let matches = resp.matches.take();
+    into_results(resp)?;
+    matches.ok_or_else(|| anyhow::anyhow!("The daemon didn't return grouped matches"))
+}
+
+/// The named vault whose daemon this process talks to or runs as; unset or `None`
+/// for the default vault.
+static VAULT: OnceLock<Option<String>> = OnceLock::new();
+
+/// Uses the daemon of vault `name` for the rest of the process.
+pub fn select_vault(name: Option<&str>) {
+    let _ = VAULT.set(name.map(str::to_string));
 }
 
 pub fn get_socket_path() -> String {
+    socket_path_for(VAULT.get().and_then(Option::as_deref))
+}
+
+/// Each vault's daemon listens on its own socket, so several can run at once.
+fn socket_path_for(vault: Option<&str>) -> String {
+    let name = match vault {
+        Some(vault) => format!("obra-{}", vault),
+        None => "obra".to_string(),
+    };
     if cfg!(windows) {
-        r"\.\pipe\obra".to_string()
+        format!(r"\.\pipe\{}", name)
     } else {
-        "/tmp/obra.sock".to_string()
+        format!("/tmp/{}.sock", name)
     }
 }
 
-pub fn send_request(query: String) -> Result<Vec<SearchResult>> {
-    let mut stream = LocalSocketStream::connect(get_socket_path())
-        .context("Could not connect to daemon socket")?;
+/// Removes the socket file, so clients stop finding a daemon that's gone.
+pub fn remove_socket_file() {
+    if !cfg!(windows) {
+        let _ = std::fs::remove_file(get_socket_path());
+    }
+}
+
+/// Removes the socket file if a panic ends the daemon. Release builds abort on
+/// panic (`panic = "abort"` in Cargo.toml), so this can't be left to a destructor.
+/// Where panics unwind, one in a request thread leaves the daemon serving, so only
+/// the main thread's takes the socket with it.
+pub fn remove_socket_on_panic() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if cfg!(panic = "abort") || std::thread::current().name() == Some("main") {
+            remove_socket_file();
+        }
+        previous(info);
+    }));
+}
 
-    let req = SearchRequest { query };
-    let mut payload = serde_json::to_vec(&req)?;
+/// Held for reading while a search is answered, and for writing by a shutdown so
+/// that it waits for those searches.
+static SEARCHES: RwLock<()> = RwLock::new(());
+
+/// Held while answering a search, over IPC or HTTP.
+pub fn serving_search() -> std::sync::RwLockReadGuard<'static, ()> {
+    SEARCHES.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Waits for the searches in flight to be answered; no new ones start until the
+/// returned guard is dropped.
+fn drain_searches() -> std::sync::RwLockWriteGuard<'static, ()> {
+    SEARCHES.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Sends `request` to the daemon, returning the stream to read the answer from.
+fn send_line(request: Request) -> Result<BufReader<LocalSocketStream>> {
+    let mut stream =
+        LocalSocketStream::connect(get_socket_path()).context(ObraError::DaemonUnavailable)?;
+
+    let envelope = Envelope {
+        protocol_version: PROTOCOL_VERSION,
+        request,
+    };
+    let mut payload = serde_json::to_vec(&envelope)?;
     payload.push(b'\n');
     stream.write_all(&payload)?;
     stream.flush()?;
+    Ok(BufReader::new(stream))
+}
 
-    let mut reader = BufReader::new(stream);
+/// Sends `request` to the daemon and reads its one-line response, e.g.
+/// `send::<IndexStatus>(Request::Status)`.
+pub fn send<T: DeserializeOwned>(request: Request) -> Result<T> {
+    let mut reader = send_line(request)?;
     let mut response_line = String::new();
     reader.read_line(&mut response_line)?;
+    parse_response(&response_line)
+}
+
+/// The response in `line`, or the error the daemon answered with instead.
+fn parse_response<T: DeserializeOwned>(line: &str) -> Result<T> {
+    let line = line.trim();
+    if line.is_empty() {
+        // Daemons from before the protocol was versioned hang up on requests they
+        // can't parse.
+        return Err(anyhow::Error::new(ObraError::ProtocolMismatch)
+            .context("The daemon closed the connection without answering"));
+    }
+    if let Ok(failure) = serde_json::from_str::<ErrorResponse>(line) {
+        return Err(failure.into_error());
+    }
+    Ok(serde_json::from_str(line)?)
+}
+
+/// Searches via the daemon. Results come back grouped, with each file's chunk hits,
+/// along with when the daemon last synced.
+pub fn send_search(
+    query: String,
+    options: SearchOptions,
+) -> Result<(SearchHits, Option<DateTime<Utc>>)> {
+    let resp: SearchResponse = send(Request::Search(SearchRequest {
+        query,
+        options,
+        grouped: true,
+    }))?;
+    let (last_sync, total) = (resp.last_sync, resp.total);
+    let matches = into_matches(resp)?;
+    Ok((SearchHits { matches, total }, last_sync))
+}
+
+/// Has the daemon index the vault, passing its progress updates to `on_progress`
+/// until it's done.
+pub fn send_reindex(force: bool, on_progress: impl FnMut(IndexProgress)) -> Result<IndexSummary> {
+    let reader = send_line(Request::Reindex { force })?;
+    read_reindex_frames(reader, on_progress)
+}
+
+fn read_reindex_frames(
+    reader: impl BufRead,
+    mut on_progress: impl FnMut(IndexProgress),
+) -> Result<IndexSummary> {
+    for line in reader.lines() {
+        let line = line?;
+        let frame = match serde_json::from_str(line.trim()) {
+            Ok(frame) => frame,
+            // A daemon that refused the request answers with an error instead.
+            Err(e) => match serde_json::from_str::<ErrorResponse>(line.trim()) {
+                Ok(failure) => return Err(failure.into_error()),
+                Err(_) => return Err(e.into()),
+            },
+        };
+        match frame {
+            ReindexFrame::Progress(progress) => on_progress(progress),
+            ReindexFrame::Done(resp) => return resp.into_summary(),
+            ReindexFrame::Unknown => {}
+        }
+    }
+    anyhow::bail!("The daemon stopped answering before indexing finished")
+}
 
-    let resp: SearchResponse = serde_json::from_str(response_line.trim())?;
-    Ok(resp.results)
+/// The request in `line`, or the error to answer with if this daemon can't serve it.
+fn parse_request(line: &str) -> std::result::Result<Request, ErrorResponse> {
+    let invalid = |e: serde_json::Error| ErrorResponse {
+        error: None,
+        message: format!("Invalid request: {}", e),
+    };
+    let version: EnvelopeVersion = serde_json::from_str(line).map_err(invalid)?;
+    if version.protocol_version != Some(PROTOCOL_VERSION) {
+        let theirs = version
+            .protocol_version
+            .map_or("none".to_string(), |v| v.to_string());
+        return Err(ErrorResponse {
+            error: Some(ObraError::ProtocolMismatch),
+            message: format!(
+                "The daemon speaks IPC protocol {}, the request protocol {}",
+                PROTOCOL_VERSION, theirs
+            ),
+        });
+    }
+    let envelope: Envelope = serde_json::from_str(line).map_err(invalid)?;
+    Ok(envelope.request)
 }
 
-pub fn start_server(manager: Arc<SyncManager>) -> Result<()> {
+/// Serves searches and control requests; a `Shutdown` exits the app through `app`.
+pub fn start_server(manager: Arc<SyncManager>, app: tauri::AppHandle) -> Result<()> {
     let socket_path = get_socket_path();
-    
+
     // Remove existing socket file on Unix
-    if !cfg!(windows) {
-        let _ = std::fs::remove_file(&socket_path);
-    }
+    remove_socket_file();
 
     let listener = LocalSocketListener::bind(socket_path)
         .context("Failed to bind local socket")?;
@@ -58,8 +449,9 @@ pub fn start_server(manager: Arc<SyncManager>) -> Result<()> {
     std::thread::spawn(move || {
         for stream in listener.incoming().filter_map(|s| s.ok()) {
             let manager = manager.clone();
+            let app = app.clone();
             std::thread::spawn(move || {
-                if let Err(e) = handle_client(stream, manager) {
+                if let Err(e) = handle_client(stream, manager, app) {
                     eprintln!("❌ Error handling IPC client: {}", e);
                 }
             });
@@ -69,25 +461,275 @@ pub fn start_server(manager: Arc<SyncManager>) -> Result<()> {
     Ok(())
 }
 
-fn handle_client(stream: LocalSocketStream, manager: Arc<SyncManager>) -> Result<()> {
+fn handle_client(
+    stream: LocalSocketStream,
+    manager: Arc<SyncManager>,
+    app: tauri::AppHandle,
+) -> Result<()> {
     let mut reader = BufReader::new(stream);
     let mut request_line = String::new();
     reader.read_line(&mut request_line)?;
 
-    let req: SearchRequest = serde_json::from_str(request_line.trim())?;
+    let request = match parse_request(request_line.trim()) {
+        Ok(request) => request,
+        Err(failure) => return write_response(reader.into_inner(), &failure),
+    };
+    let mut canceled = false;
+    match request {
+        Request::Search(req) => {
+            let _serving = serving_search();
+            let db = manager
+                .db
+                .lock()
+                .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+            let matches = run_search(&req.query, &db, manager.engine.as_ref(), &req.options);
+            drop(db);
 
-    let db = manager.db.lock().map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
-    let engine = &manager.engine;
+            let mut resp = SearchResponse::from_matches(matches, req.grouped);
+            resp.last_sync = *manager.last_sync_time.lock().unwrap();
+            write_response(reader.into_inner(), &resp)?;
 
-    let results = run_search(&req.query, &db, engine)?;
+            // After answering, so the search itself never waits on the sync.
+            manager.sync_if_stale();
+            return Ok(());
+        }
+        Request::BatchSearch(batch) => {
+            let _serving = serving_search();
+            let db = manager
+                .db
+                .lock()
+                .map_err(|_| anyhow::anyhow!("DB Lock failed"))?;
+            let sets =
+                run_batch_search(&batch.queries, &db, manager.engine.as_ref(), &batch.options);
+            drop(db);
 
-    let resp = SearchResponse { results };
-    let mut response_payload = serde_json::to_vec(&resp)?;
-    response_payload.push(b'\n');
+            let mut resp = BatchSearchResponse::from_matches(sets, batch.grouped);
+            resp.last_sync = *manager.last_sync_time.lock().unwrap();
+            write_response(reader.into_inner(), &resp)?;
+            manager.sync_if_stale();
+            return Ok(());
+        }
+        Request::Pause => manager.pause(),
+        Request::Resume => {
+            manager.resume();
+        }
+        Request::Status => {
+            return write_result(reader.into_inner(), manager.index_status());
+        }
+        Request::Shutdown => {
+            println!("🛑 Shutting down...");
+            remove_socket_file();
+            let _drained = drain_searches();
+            manager.flush();
+            let resp = ControlResponse {
+                paused: manager.is_paused(),
+                canceled: false,
+            };
+            write_response(reader.into_inner(), &resp)?;
+            app.exit(0);
+            return Ok(());
+        }
+        Request::Cancel => canceled = manager.cancel_index(),
+        Request::DryRun { force } => {
+            return write_result(reader.into_inner(), manager.plan_index(force));
+        }
+        Request::Compact => {
+            return write_result(reader.into_inner(), manager.compact());
+        }
+        Request::Verify { fix } => {
+            return write_result(reader.into_inner(), manager.verify(fix));
+        }
+        Request::Reindex { force } => {
+            let stream = RefCell::new(reader.into_inner());
+            // A client that stops listening doesn't stop the index.
+            let forward = |progress| {
+                let _ = write_line(&mut stream.borrow_mut(), &ReindexFrame::Progress(progress));
+            };
+            let resp =
+                ReindexResponse::from_result(manager.full_index_reporting(force, Some(&forward)));
+            return write_line(&mut stream.borrow_mut(), &ReindexFrame::Done(resp));
+        }
+    }
+    let resp = ControlResponse {
+        paused: manager.is_paused(),
+        canceled,
+    };
+    write_response(reader.into_inner(), &resp)
+}
 
-    let mut stream = reader.into_inner();
+/// Writes the value, or an `ErrorResponse` so the client learns why it failed.
+fn write_result(stream: LocalSocketStream, result: Result<impl Serialize>) -> Result<()> {
+    match result {
+        Ok(resp) => write_response(stream, &resp),
+        Err(e) => write_response(
+            stream,
+            &ErrorResponse {
+                error: ObraError::find(&e),
+                message: format!("{:#}", e),
+            },
+        ),
+    }
+}
+
+fn write_response(mut stream: LocalSocketStream, resp: &impl Serialize) -> Result<()> {
+    write_line(&mut stream, resp)
+}
+
+fn write_line(stream: &mut LocalSocketStream, resp: &impl Serialize) -> Result<()> {
+    let mut response_payload = serde_json::to_vec(resp)?;
+    response_payload.push(b'\n');
     stream.write_all(&response_payload)?;
     stream.flush()?;
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexStatus;
+
+    #[test]
+    fn test_response_carries_last_sync() -> Result<()> {
+        let synced = "2024-05-01T12:00:00Z".parse::<DateTime<Utc>>()?;
+        let mut resp = SearchResponse::from_matches(Ok(SearchHits::default()), true);
+        resp.last_sync = Some(synced);
+
+        let wire = serde_json::to_string(&resp)?;
+        assert!(wire.contains("\"last_sync\":\"2024-05-01T12:00:00Z\""));
+        let resp: SearchResponse = serde_json::from_str(&wire)?;
+        assert_eq!(resp.last_sync, Some(synced));
+
+        // Older daemons don't send it.
+        let resp: SearchResponse = serde_json::from_str(r#"{"results":[]}"#)?;
+        assert_eq!(resp.last_sync, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_searches_in_flight() -> Result<()> {
+        let request = parse_request(r#"{"protocol_version":2,"request":{"kind":"shutdown"}}"#);
+        assert!(matches!(request, Ok(Request::Shutdown)));
+
+        let serving = SEARCHES.read().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let shutdown = std::thread::spawn(move || {
+            let _drained = drain_searches();
+            tx.send(()).unwrap();
+        });
+        let wait = std::time::Duration::from_millis(100);
+        assert!(rx.recv_timeout(wait).is_err());
+
+        drop(serving);
+        assert!(rx.recv_timeout(wait * 10).is_ok());
+        shutdown.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_reindex_response_round_trips_errors() -> Result<()> {
+        let wire = serde_json::to_string(&Envelope {
+            protocol_version: PROTOCOL_VERSION,
+            request: Request::Reindex { force: true },
+        })?;
+        assert_eq!(
+            wire,
+            r#"{"protocol_version":2,"request":{"kind":"reindex","force":true}}"#
+        );
+        assert!(matches!(
+            parse_request(&wire),
+            Ok(Request::Reindex { force: true })
+        ));
+
+        let done = IndexSummary {
+            files: 3,
+            canceled: false,
+        };
+        let wire =
+            serde_json::to_string(&ReindexFrame::Done(ReindexResponse::from_result(Ok(done))))?;
+        assert_eq!(wire, r#"{"frame":"done","files":3,"canceled":false}"#);
+        assert_eq!(read_reindex_frames(wire.as_bytes(), |_| {})?, done);
+
+        let failed = ReindexFrame::Done(ReindexResponse::from_result(Err(
+            ObraError::ModelMismatch.into(),
+        )));
+        let wire = serde_json::to_string(&failed)?;
+        let err = read_reindex_frames(wire.as_bytes(), |_| {}).unwrap_err();
+        assert_eq!(ObraError::find(&err), Some(ObraError::ModelMismatch));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reindex_stream_skips_unknown_frames() -> Result<()> {
+        let wire = concat!(
+            r#"{"frame":"progress","files_done":100,"files_total":250,"stage":"embedding"}"#,
+            "\n",
+            r#"{"frame":"log","text":"from a newer daemon"}"#,
+            "\n",
+            r#"{"frame":"done","files":250,"canceled":false}"#,
+            "\n",
+        );
+        let mut seen = Vec::new();
+        let summary = read_reindex_frames(wire.as_bytes(), |p| seen.push(p.files_done))?;
+        assert_eq!(seen, vec![100]);
+        assert_eq!(summary.files, 250);
+
+        let cut_off = wire.lines().next().unwrap();
+        assert!(read_reindex_frames(cut_off.as_bytes(), |_| {}).is_err());
+        Ok(())
+    }
+
+    /// What a client gets back when the daemon refuses `line`.
+    fn refusal(line: &str) -> anyhow::Error {
+        let failure = parse_request(line).err().expect("the request is refused");
+        let wire = serde_json::to_string(&failure).unwrap();
+        parse_response::<IndexStatus>(&wire).unwrap_err()
+    }
+
+    #[test]
+    fn test_version_mismatch_is_a_clean_error() -> Result<()> {
+        let newer = r#"{"protocol_version":3,"request":{"kind":"status"}}"#;
+        assert_eq!(
+            ObraError::find(&refusal(newer)),
+            Some(ObraError::ProtocolMismatch)
+        );
+        // Clients from before the envelope send bare requests.
+        let unversioned = r#"{"query":"garden","grouped":true}"#;
+        assert_eq!(
+            ObraError::find(&refusal(unversioned)),
+            Some(ObraError::ProtocolMismatch)
+        );
+        // Daemons from before it hang up without answering.
+        let err = parse_response::<IndexStatus>("").unwrap_err();
+        assert_eq!(ObraError::find(&err), Some(ObraError::ProtocolMismatch));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_kind_is_a_clean_error() {
+        let err = refusal(r#"{"protocol_version":2,"request":{"kind":"defragment"}}"#);
+        assert_eq!(ObraError::find(&err), None);
+        assert!(err.to_string().contains("defragment"));
+        assert!(refusal("not json")
+            .to_string()
+            .starts_with("Invalid request"));
+    }
+
+    #[test]
+    fn test_each_vault_has_its_own_socket() {
+        assert_ne!(socket_path_for(None), socket_path_for(Some("work")));
+        assert!(socket_path_for(Some("work")).contains("obra-work"));
+    }
+
+    #[test]
+    fn test_limit_zero_response_keeps_total() -> Result<()> {
+        let hits = SearchHits {
+            matches: Vec::new(),
+            total: 42,
+        };
+        let wire = serde_json::to_string(&SearchResponse::from_matches(Ok(hits), true))?;
+        let resp: SearchResponse = serde_json::from_str(&wire)?;
+        assert_eq!(resp.total, 42);
+        assert!(into_matches(resp)?.is_empty());
+        Ok(())
+    }
+}
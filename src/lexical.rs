@@ -0,0 +1,152 @@
+use crate::db::ChunkMeta;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// BM25 term-frequency saturation.
+const K1: f32 = 1.2;
+/// BM25 document-length normalization.
+const B: f32 = 0.75;
+
+/// A BM25 index over chunk text, kept in step with the vector index so exact terms
+/// (error codes, names, identifiers) that embeddings blur can still be found.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LexicalIndex {
+    /// Term frequency of each term, per chunk id.
+    postings: HashMap<String, HashMap<u64, u32>>,
+    /// Length in terms of each chunk.
+    lengths: HashMap<u64, u32>,
+    total_length: u64,
+}
+
+/// `_` counts as part of a word so identifiers like `ERR_TIMEOUT` stay whole.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Lowercased words.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !is_word_char(c))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Byte ranges of the words `tokenize` would return, in order.
+pub fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (is_word_char(c), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                spans.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, text.len()));
+    }
+    spans
+}
+
+impl LexicalIndex {
+    pub fn build(chunks: &[ChunkMeta]) -> Self {
+        let mut index = Self::default();
+        for chunk in chunks {
+            index.add(chunk.id, &chunk.text);
+        }
+        index
+    }
+
+    /// Number of chunks indexed.
+    pub fn len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    pub fn add(&mut self, id: u64, text: &str) {
+        let mut length = 0;
+        for term in tokenize(text) {
+            *self
+                .postings
+                .entry(term)
+                .or_default()
+                .entry(id)
+                .or_default() += 1;
+            length += 1;
+        }
+        self.lengths.insert(id, length);
+        self.total_length += length as u64;
+    }
+
+    /// Forgets chunk `id`, which was added with `text`.
+    pub fn remove(&mut self, id: u64, text: &str) {
+        let Some(length) = self.lengths.remove(&id) else {
+            return;
+        };
+        self.total_length -= length as u64;
+        for term in tokenize(text) {
+            if let Some(docs) = self.postings.get_mut(&term) {
+                docs.remove(&id);
+                if docs.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// The `limit` chunks with the highest BM25 score for `query`, best first.
+    /// Chunks sharing no term with the query aren't returned.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(u64, f32)> {
+        let count = self.lengths.len() as f32;
+        if count == 0.0 {
+            return Vec::new();
+        }
+        let average_length = self.total_length as f32 / count;
+
+        let mut terms: Vec<String> = tokenize(query).collect();
+        terms.sort();
+        terms.dedup();
+
+        let mut scores: HashMap<u64, f32> = HashMap::new();
+        for term in &terms {
+            let Some(docs) = self.postings.get(term) else {
+                continue;
+            };
+            let df = docs.len() as f32;
+            let idf = ((count - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for (&id, &tf) in docs {
+                let tf = tf as f32;
+                let length = self.lengths[&id] as f32;
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(id).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut ranked: Vec<(u64, f32)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then(a.0.cmp(&b.0)));
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rare_terms_outscore_common_ones() {
+        let mut index = LexicalIndex::default();
+        index.add(0, "the build failed with ERR_4021 again");
+        index.add(1, "the build failed, the build failed, the build failed");
+        index.add(2, "notes on sourdough starters");
+
+        let hits = index.search("build ERR_4021", 10);
+        assert_eq!(hits.iter().map(|h| h.0).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(index.search("rye", 10).is_empty());
+
+        index.remove(0, "the build failed with ERR_4021 again");
+        assert_eq!(index.len(), 2);
+        assert!(index.search("err_4021", 10).is_empty());
+    }
+}
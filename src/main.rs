@@ -1,24 +1,48 @@
+mod cache;
 mod chunker;
 mod config;
 mod db;
+mod doctor;
 mod embeddings;
+mod error;
+mod http;
 mod index;
 mod ipc;
+mod lexical;
+mod markdown;
+mod query_log;
 mod search;
 mod watcher;
 
 use crate::config::{load_config, save_config, AppPaths, Config};
-use crate::db::Database;
-use crate::embeddings::EmbeddingEngine;
-use crate::index::{run_index, Meta, SyncManager};
-use crate::ipc::{send_request, start_server};
-use crate::search::run_search;
+use crate::db::{Database, MetaField, VerifyReport};
+use crate::embeddings::{
+    download_model, missing_model_files, DownloadRetry, Embedder, EmbeddingEngine,
+    QueryCachedEmbedder,
+};
+use crate::error::ObraError;
+use crate::http::start_http_server;
+use crate::index::{
+    embed_note, humanize_duration, plan_index, reembed_chunks, run_index, vault_relative_path,
+    IndexProgress, IndexStage, IndexStatus, Meta, SyncManager,
+};
+use crate::ipc::{
+    remove_socket_file, remove_socket_on_panic, select_vault, send, send_reindex, send_search,
+    start_server, ControlResponse, Request,
+};
+use crate::search::{
+    format_score, run_search, similarity_matrix, sort_results, FileMatch, MetaFilter, ResultOrder,
+    SearchHits, SearchMode, SearchOptions, SearchResult, RECENCY_BOOST,
+};
 use crate::watcher::watch_vault;
 use anyhow::{Context, Result};
-use chrono::{Duration, Utc};
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Duration, Local, Utc};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use tauri::{
     CustomMenuItem, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem,
@@ -36,6 +60,10 @@ struct Cli {
     /// Search query to find relevant notes
     query: Option<String>,
 
+    /// Read the search query from stdin instead, e.g. `echo "query" | obra --stdin`
+    #[arg(long, conflicts_with = "query")]
+    stdin: bool,
+
     /// Re-index the vault to pick up changes (manual sync)
     #[arg(short, long)]
     index: bool,
@@ -43,6 +71,89 @@ struct Cli {
     /// Force a full re-indexing of all files (bypasses incremental sync)
     #[arg(short, long)]
     force: bool,
+
+    /// With --index or --force, list the notes that would be indexed without indexing them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// How to order the results that pass the confidence filter
+    #[arg(long, value_enum, default_value_t = ResultOrder::Relevance)]
+    order: ResultOrder,
+
+    /// Which indexes to search [default: hybrid]
+    #[arg(long, value_enum)]
+    mode: Option<SearchMode>,
+
+    /// Fall back to fuzzy filename matching when nothing matches semantically
+    #[arg(long)]
+    fuzzy: bool,
+
+    /// Stable, tab-separated output for scripts: SCORE, RELPATH and SNIPPET per line
+    #[arg(long, conflicts_with = "format")]
+    porcelain: bool,
+
+    /// How to print results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+    format: OutputFormat,
+
+    /// Print `obsidian://open` links instead of paths; with `obra open`, open the note in Obsidian
+    #[arg(long, global = true)]
+    uri: bool,
+
+    /// Return at most N results. 0 prints only how many notes matched
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Skip the N best results, to see the ones after them
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    offset: usize,
+
+    /// Only return notes of at least N words
+    #[arg(long, value_name = "N")]
+    min_words: Option<usize>,
+
+    /// Don't warn when the daemon's index hasn't synced recently
+    #[arg(long)]
+    no_stale_warning: bool,
+
+    /// Rank recently modified notes higher (see `recency_boost` in the config)
+    #[arg(long)]
+    recency: bool,
+
+    /// Favour variety over near-duplicate notes in the results (see `mmr_lambda`)
+    #[arg(long)]
+    diverse: bool,
+
+    /// Only return notes under this vault folder; repeat for several
+    #[arg(long = "in", value_name = "FOLDER")]
+    in_folders: Vec<String>,
+
+    /// Only return notes with this tag (or one nested under it); repeat to require several
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+
+    /// Search with the settings of this profile from the config
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Keep the index and daemon log here instead of the default data directory
+    #[arg(long, global = true, value_name = "DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Use the vault registered under this name with `obra init --name`
+    #[arg(long, global = true, value_name = "NAME")]
+    vault: Option<String>,
+}
+
+/// `--format` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// Absolute paths, each with the matching snippet indented below it
+    Plain,
+    /// A JSON array of results with their path, score and snippet
+    Json,
+    /// Absolute path and score per line, separated by a tab
+    Paths,
 }
 
 #[derive(Subcommand)]
@@ -51,94 +162,998 @@ enum Commands {
     Init {
         #[arg(value_name = "VAULT_PATH")]
         vault_path: PathBuf,
+        /// Register it as a named vault with its own index, used with `--vault NAME`
+        #[arg(long, value_name = "NAME")]
+        name: Option<String>,
+        /// Don't download the embedding model now; the first search or index will
+        #[arg(long)]
+        no_download: bool,
     },
     /// Start the background daemon with a system tray icon
     Daemon {
         /// Run in the foreground instead of backgrounding
         #[arg(short, long)]
         foreground: bool,
+        /// Also answer `GET /search?q=...` over HTTP on this port (see `http_bind`)
+        #[arg(long, value_name = "PORT")]
+        http: Option<u16>,
+    },
+    /// Show the embedding model, its dimension and the device it runs on
+    ModelInfo,
+    /// Check that the vector index and chunk metadata agree
+    Verify {
+        /// Remove orphaned vectors and re-embed chunks that lost theirs
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Pause the daemon's file watcher, e.g. during a bulk import or rebase
+    Pause,
+    /// Resume the daemon's file watcher and sync what changed while paused
+    Resume,
+    /// Stop the daemon's running index after its current batch, keeping what's done
+    Cancel,
+    /// Show what's indexed and when it last synced, from the daemon if it's running
+    Status,
+    /// Check the config, vault, model download, daemon and index, with hints for fixing them
+    Doctor,
+    /// Rebuild the vector index without the space left by deleted chunks
+    Compact,
+    /// Sync the index through the daemon if it's running, otherwise like --index
+    Reindex {
+        /// Re-index every note, like --force
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Stop the background daemon once it has answered the searches in flight
+    Stop,
+    /// Print the pairwise cosine similarity of notes (averaged over their chunks)
+    Similarity {
+        /// Notes to compare, as paths or relative to the vault; un-indexed notes are embedded on the fly
+        #[arg(value_name = "NOTE", required = true, num_args = 2..)]
+        notes: Vec<PathBuf>,
+    },
+    /// Print the embedding of a text, or its similarity to another text
+    Embed {
+        #[arg(value_name = "TEXT")]
+        text: String,
+        /// Print the cosine similarity between TEXT and this text instead
+        #[arg(long, value_name = "OTHER")]
+        compare: Option<String>,
+    },
+    /// Delete the local query log (see `log_queries` in the config)
+    ClearQueryLog,
+    /// Open the best match for a search in its default app, or the `opener` from the config
+    Open {
+        #[arg(value_name = "QUERY")]
+        query: String,
+    },
+    /// Print a shell completion script, e.g. `obra completions zsh > ~/.zfunc/_obra`
+    #[command(hide = true)]
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
     },
+    /// List the indexed notes, sorted by path
+    List {
+        /// Also print each note's chunk count and when it was last indexed
+        #[arg(short, long)]
+        long: bool,
+        /// Print a JSON array of {path, chunks, mtime} instead
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:?}", e);
+        let code = ObraError::find(&e).map_or(1, ObraError::exit_code);
+        std::process::exit(code);
+    }
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let paths = AppPaths::from_env()?;
+fn run() -> Result<()> {
+    let mut cli = Cli::parse();
+    if cli.stdin {
+        cli.query = Some(read_query(std::io::stdin().lock())?);
+    }
+    if let Some(Commands::Completions { shell }) = cli.command {
+        write_completions(shell, &mut std::io::stdout());
+        return Ok(());
+    }
+    let vault = match &cli.command {
+        Some(Commands::Init {
+            name: Some(name), ..
+        }) => Some(name.clone()),
+        _ => cli.vault.clone(),
+    };
+    let paths = AppPaths::from_env(cli.data_dir.clone(), vault.as_deref())?;
+    select_vault(vault.as_deref());
 
     // Handle Init
-    if let Some(Commands::Init { vault_path }) = cli.command {
+    if let Some(Commands::Init {
+        vault_path,
+        no_download,
+        ..
+    }) = cli.command
+    {
         let abs_path = fs::canonicalize(vault_path).context("Could not find vault path")?;
         let config = Config {
             vault_path: abs_path,
+            ..Default::default()
         };
         save_config(&paths, &config)?;
         let meta_file = paths.data_dir.join("meta.json");
         if meta_file.exists() {
             fs::remove_file(&meta_file)?;
         }
-        println!("✅ Initialized with vault: {:?}", config.vault_path);
+        match &paths.vault {
+            Some(name) => println!(
+                "✅ Initialized vault '{}': {:?}. Use it with `obra --vault {}`.",
+                name, config.vault_path, name
+            ),
+            None => println!("✅ Initialized with vault: {:?}", config.vault_path),
+        }
+        if !no_download && !missing_model_files(&config.model_id).is_empty() {
+            println!("⬇️  Downloading the embedding model {}...", config.model_id);
+            match download_model(&config.model_id, download_retry(&config)) {
+                Ok(()) => println!("✅ Model downloaded."),
+                // The vault is set up either way; the download is retried when needed.
+                Err(e) => eprintln!(
+                    "⚠️  {:#}\n   It will be downloaded by the first search or index instead.",
+                    e
+                ),
+            }
+        }
         return Ok(());
     }
 
     // Handle Daemon
-    if let Some(Commands::Daemon { foreground }) = cli.command {
-        return run_daemon(paths, foreground);
+    if let Some(Commands::Daemon { foreground, http }) = cli.command {
+        return run_daemon(paths, foreground, http);
+    }
+
+    // Handle ModelInfo
+    if let Some(Commands::ModelInfo) = cli.command {
+        return run_model_info(&paths);
+    }
+
+    // Handle Verify
+    if let Some(Commands::Verify { fix }) = cli.command {
+        return run_verify(&paths, fix);
+    }
+
+    // Handle Pause / Resume
+    if let Some(Commands::Pause) = cli.command {
+        send::<ControlResponse>(Request::Pause)?;
+        println!("⏸️  Watching paused. Run `obra resume` when you're done.");
+        return Ok(());
+    }
+    if let Some(Commands::Resume) = cli.command {
+        send::<ControlResponse>(Request::Resume)?;
+        println!("▶️  Watching resumed; syncing changes made while paused.");
+        return Ok(());
+    }
+    if let Some(Commands::Cancel) = cli.command {
+        match send::<ControlResponse>(Request::Cancel) {
+            Ok(response) if response.canceled => {
+                println!("⏹️  Indexing will stop after the current batch.");
+            }
+            Ok(_) => println!("ℹ️  The daemon isn't indexing."),
+            Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => {
+                println!("ℹ️  Obra daemon isn't running, so there's nothing to cancel.");
+            }
+            Err(e) => return Err(e),
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Embed {
+        ref text,
+        ref compare,
+    }) = cli.command
+    {
+        return run_embed(&paths, text, compare.as_deref());
+    }
+
+    if let Some(Commands::ClearQueryLog) = cli.command {
+        if query_log::clear_log(&paths.data_dir)? {
+            println!("🧹 Query log cleared.");
+        } else {
+            println!("No query log to clear.");
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Stop) = cli.command {
+        match send::<ControlResponse>(Request::Shutdown) {
+            Ok(_) => println!("🛑 Obra daemon stopped."),
+            Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => {
+                println!("ℹ️  Obra daemon isn't running.");
+            }
+            Err(e) => return Err(e),
+        }
+        return Ok(());
+    }
+
+    if let Some(Commands::Reindex { force }) = cli.command {
+        let redraw = |progress: IndexProgress| eprint!("\r{}", progress_bar(&progress));
+        let result = send_reindex(force, redraw);
+        if result.is_ok() {
+            eprintln!();
+        }
+        match result {
+            Ok(summary) if summary.canceled => {
+                println!("⏹️  Indexing canceled after {} files.", summary.files);
+                return Ok(());
+            }
+            Ok(summary) => {
+                println!("✅ Indexed {} files (via daemon).", summary.files);
+                return Ok(());
+            }
+            // Index cold below.
+            Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => {
+                cli.index = true;
+                cli.force = force;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(Commands::Doctor) = cli.command {
+        return run_doctor(&paths);
+    }
+
+    if let Some(Commands::Compact) = cli.command {
+        return run_compact(&paths);
+    }
+
+    if let Some(Commands::Status) = cli.command {
+        return run_status(&paths);
+    }
+
+    if let Some(Commands::List { long, json }) = cli.command {
+        return run_list(&paths, long, json);
+    }
+
+    // Handle Similarity
+    if let Some(Commands::Similarity { ref notes }) = cli.command {
+        return run_similarity(&paths, notes);
+    }
+
+    // Searched like a query below; `print_results` opens the top result.
+    if let Some(Commands::Open { ref query }) = cli.command {
+        cli.query = Some(query.clone());
+    }
+
+    if cli.dry_run {
+        if !cli.index && !cli.force {
+            anyhow::bail!("--dry-run needs --index or --force");
+        }
+        return run_dry_run(&paths, cli.force);
     }
 
     // Handle search - Try IPC first if daemon is running
     if let Some(ref query) = cli.query {
-        if let Ok(results) = send_request(query.clone()) {
-            let config = load_config(&paths)?;
-            if results.is_empty() {
-                eprintln!("No confident results found for '{}' (via daemon)", query);
-            } else {
-                for res in results {
-                    println!("{}", config.vault_path.join(&res.path).display());
+        let config = load_config(&paths)?;
+        let options = search_options(&cli, &config)?;
+        let limit = options.limit;
+        match send_search(query.clone(), options) {
+            Ok((results, last_sync)) => {
+                if !cli.no_stale_warning && !cli.porcelain {
+                    if let Some(warning) =
+                        stale_warning(last_sync, Utc::now(), config.auto_sync_hours)
+                    {
+                        eprintln!("{}", warning);
+                    }
                 }
+                print_results(
+                    &paths,
+                    &config,
+                    results,
+                    limit,
+                    query,
+                    &cli,
+                    " (via daemon)",
+                )?;
+                return Ok(());
+            }
+            Err(e) if ObraError::find(&e) == Some(ObraError::ProtocolMismatch) => {
+                eprintln!("⚠️  {} Searching without it.", ObraError::ProtocolMismatch);
+            }
+            // The daemon answered with a definite error; don't retry cold.
+            Err(e) if ObraError::find(&e).is_some_and(|k| k != ObraError::DaemonUnavailable) => {
+                return Err(e);
             }
-            return Ok(());
+            Err(_) => {}
         }
     }
 
     // Legacy CLI behavior (Cold Start)
     let config = load_config(&paths)?;
     let mut db = Database::open(&paths.data_dir)?;
-    let engine = EmbeddingEngine::new()?;
+    // The old model's vectors can't share an index with the new one's, nor can
+    // i8 ones be turned back into f32, and a forced re-index replaces them all.
+    match db.configure(&config) {
+        Err(e)
+            if cli.force
+                && matches!(
+                    ObraError::find(&e),
+                    Some(ObraError::ModelMismatch | ObraError::PrecisionMismatch)
+                ) =>
+        {
+            db.clear()?;
+            db.configure(&config)?;
+        }
+        result => result?,
+    }
+    let engine = load_engine(&config)?;
+    let dimension = engine.info().dimension;
+    if cli.force && db.dimension() != dimension {
+        db.clear()?;
+    }
+    db.set_dimension(dimension)?;
 
     // Handle --index or auto-sync
     let meta_file = paths.data_dir.join("meta.json");
-    let needs_sync = if cli.index || cli.force {
+    let needs_sync = if cli.index || cli.force || db.is_incomplete() {
         true
     } else if meta_file.exists() {
         let content = fs::read_to_string(&meta_file)?;
         let meta: Meta = serde_json::from_str(&content)?;
-        Utc::now() - meta.last_sync > Duration::hours(24)
+        config.auto_sync_hours > 0
+            && Utc::now() - meta.last_sync > Duration::hours(config.auto_sync_hours)
     } else {
         true
     };
 
     if needs_sync {
         if !cli.index && !cli.force {
-            println!("🔔 Index is older than 24h, performing incremental sync...");
+            println!(
+                "🔔 Index is older than {}h, performing incremental sync...",
+                config.auto_sync_hours
+            );
         }
-        run_index(&config, &mut db, &engine, &paths.data_dir, cli.force)?;
+        let print_progress = |progress: IndexProgress| eprintln!("{}", progress_bar(&progress));
+        run_index(
+            &config,
+            &mut db,
+            &engine,
+            &paths.data_dir,
+            cli.force,
+            Some(&print_progress),
+        )?;
     }
 
     // Handle search
-    if let Some(query) = cli.query {
-        let results = run_search(&query, &db, &engine)?;
-        if results.is_empty() {
-            eprintln!("No confident results found for '{}'", query);
+    let options = search_options(&cli, &config)?;
+    if let Some(ref query) = cli.query {
+        let hits = run_search(query, &db, &engine, &options)?;
+        print_results(&paths, &config, hits, options.limit, query, &cli, "")?;
+    }
+
+    Ok(())
+}
+
+/// Writes the completion script for `shell`, generated from the same `Cli`
+/// definition that parses the arguments.
+fn write_completions(shell: Shell, out: &mut impl Write) {
+    // The generators offer hidden subcommands too, so they get a copy without them.
+    let cli = Cli::command();
+    let visible = cli
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .cloned();
+    let mut command = clap::Command::new("obra")
+        .version(env!("CARGO_PKG_VERSION"))
+        .args(cli.get_arguments().cloned())
+        .subcommands(visible);
+    clap_complete::generate(shell, &mut command, "obra", out);
+}
+
+/// Reads a `--stdin` query. Surrounding whitespace is dropped; line breaks inside
+/// it are kept, so multiline text can be searched as is.
+fn read_query(mut input: impl Read) -> Result<String> {
+    let mut query = String::new();
+    input
+        .read_to_string(&mut query)
+        .context("Could not read the query from stdin")?;
+    let query = query.trim();
+    if query.is_empty() {
+        anyhow::bail!("No query on stdin");
+    }
+    Ok(query.to_string())
+}
+
+/// The config's search settings, then the selected profile's, then flags.
+fn search_options(cli: &Cli, config: &Config) -> Result<SearchOptions> {
+    let mut options = config.search_options(cli.profile.as_deref())?;
+    options.limit = cli.limit.unwrap_or(options.limit);
+    options.offset = cli.offset;
+    options.min_words = cli.min_words.or(options.min_words);
+    options.mode = cli.mode.unwrap_or(options.mode);
+    options.fuzzy_fallback |= cli.fuzzy;
+    options.diverse |= cli.diverse;
+    options.filters = cli
+        .tags
+        .iter()
+        .map(|tag| MetaFilter {
+            field: MetaField::Tags,
+            value: tag.trim_start_matches('#').to_string(),
+        })
+        .collect();
+    options.folders = cli
+        .in_folders
+        .iter()
+        .map(|f| f.replace('\\', "/"))
+        .collect();
+    if cli.recency && options.recency_boost <= 0.0 {
+        options.recency_boost = RECENCY_BOOST;
+    }
+    Ok(options)
+}
+
+/// A failed log write shouldn't cost the user their results.
+fn log_query_or_warn(paths: &AppPaths, config: &Config, query: &str, chosen: Option<&str>) {
+    if let Err(e) = query_log::log_query(&paths.data_dir, config, query, chosen) {
+        eprintln!("⚠️  Could not write the query log: {}", e);
+    }
+}
+
+/// Logs the search (see `query_log`) and prints or opens its results.
+fn print_results(
+    paths: &AppPaths,
+    config: &Config,
+    hits: SearchHits,
+    limit: usize,
+    query: &str,
+    cli: &Cli,
+    source: &str,
+) -> Result<()> {
+    let SearchHits {
+        matches: mut results,
+        total,
+    } = hits;
+    if let Some(Commands::Open { .. }) = cli.command {
+        let top = results.first().filter(|res| !res.title_fallback);
+        log_query_or_warn(paths, config, query, top.map(|res| res.path.as_str()));
+        match top {
+            Some(top) => {
+                // The configured opener is for files, so links go to the system's.
+                let opener = config.opener.as_deref().filter(|_| !cli.uri);
+                return open_note(opener, &note_location(config, &top.path, cli.uri));
+            }
+            None => anyhow::bail!("No confident results found for '{}'{}", query, source),
+        }
+    }
+    log_query_or_warn(paths, config, query, None);
+    // Only the count was asked for, in whichever format.
+    if limit == 0 {
+        if cli.format == OutputFormat::Json {
+            let count = serde_json::json!({ "results": [], "total": total });
+            println!("{}", serde_json::to_string_pretty(&count)?);
+        } else if cli.porcelain {
+            println!("{}", total);
         } else {
-            for res in results {
-                println!("{}", config.vault_path.join(&res.path).display());
+            println!("{} matching notes{}", total, source);
+        }
+        return Ok(());
+    }
+    if cli.format == OutputFormat::Json {
+        sort_results(&mut results, cli.order);
+        let results: Vec<SearchResult> = results.iter().map(SearchResult::from).collect();
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+    if cli.porcelain {
+        sort_results(&mut results, cli.order);
+        for res in &results {
+            println!("{}", porcelain_line(res, config.score_precision));
+        }
+        return Ok(());
+    }
+    if results.is_empty() {
+        eprintln!("No confident results found for '{}'{}", query, source);
+        return Ok(());
+    }
+    if results[0].title_fallback {
+        eprintln!(
+            "No confident results found for '{}'{}; closest note titles:",
+            query, source
+        );
+    }
+    sort_results(&mut results, cli.order);
+    for res in results {
+        let location = note_location(config, &res.path, cli.uri);
+        match cli.format {
+            OutputFormat::Paths => println!(
+                "{}\t{}",
+                location.to_string_lossy(),
+                format_score(res.score, config.score_precision)
+            ),
+            _ => {
+                println!("{}", location.to_string_lossy());
+                if let Some(snippet) = res.snippet.as_deref() {
+                    let snippet: Vec<&str> = snippet.split_whitespace().collect();
+                    println!("    {}", snippet.join(" "));
+                }
             }
         }
     }
+    Ok(())
+}
+
+/// Where a result is shown or opened: its absolute path, or its `obsidian://open`
+/// link with `uri`.
+fn note_location(config: &Config, relpath: &str, uri: bool) -> OsString {
+    if uri {
+        obsidian_uri(&config.vault_path, relpath).into()
+    } else {
+        config.vault_path.join(relpath).into_os_string()
+    }
+}
+
+/// The link that opens `relpath` in Obsidian: the vault is named by its folder,
+/// and the file by its vault path without `.md`, both percent-encoded.
+fn obsidian_uri(vault_path: &Path, relpath: &str) -> String {
+    let vault = vault_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let relpath = relpath.replace('\\', "/");
+    let file = relpath.strip_suffix(".md").unwrap_or(&relpath);
+    format!(
+        "obsidian://open?vault={}&file={}",
+        uri_component(&vault),
+        uri_component(file)
+    )
+}
+
+/// Percent-encodes all but the characters JavaScript's `encodeURIComponent`
+/// leaves alone, as Obsidian decodes its links with the reverse.
+fn uri_component(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => out.push(byte as char),
+            b'-' | b'_' | b'.' | b'!' | b'~' | b'*' | b'\'' | b'(' | b')' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Opens `target`, a path or link, with `opener`, or the system's default app.
+fn open_note(opener: Option<&str>, target: &OsStr) -> Result<()> {
+    let mut command = opener_command(opener)?;
+    if cfg!(windows) && opener.is_none() {
+        // `cmd` would split an unquoted `&`, as in `?vault=X&file=Y`, into
+        // another command, and Rust only quotes arguments with spaces.
+        quote_for_cmd(&mut command, target);
+    } else {
+        command.arg(target);
+    }
+    let status = command
+        .status()
+        .with_context(|| format!("Could not run {:?}", command.get_program()))?;
+    if !status.success() {
+        anyhow::bail!(
+            "{:?} failed to open {}: {}",
+            command.get_program(),
+            target.to_string_lossy(),
+            status
+        );
+    }
+    Ok(())
+}
+
+/// Appends `target` in double quotes, as `cmd /C start` expects it.
+#[cfg(windows)]
+fn quote_for_cmd(command: &mut std::process::Command, target: &OsStr) {
+    use std::os::windows::process::CommandExt;
+    let mut quoted = std::ffi::OsString::from("\"");
+    quoted.push(target);
+    quoted.push("\"");
+    command.raw_arg(quoted);
+}
+
+#[cfg(not(windows))]
+fn quote_for_cmd(command: &mut std::process::Command, target: &OsStr) {
+    command.arg(target);
+}
+
+/// The command a note's path is appended to: `opener` split into words, each
+/// `$NAME` replaced by that environment variable, or else the platform's opener.
+fn opener_command(opener: Option<&str>) -> Result<std::process::Command> {
+    let Some(opener) = opener else {
+        return Ok(if cfg!(target_os = "macos") {
+            std::process::Command::new("open")
+        } else if cfg!(windows) {
+            let mut command = std::process::Command::new("cmd");
+            // `start` takes its first quoted argument as the window title.
+            command.args(["/C", "start", ""]);
+            command
+        } else {
+            std::process::Command::new("xdg-open")
+        });
+    };
+    let mut words = Vec::new();
+    for word in opener.split_whitespace() {
+        match word.strip_prefix('$') {
+            Some(name) => {
+                let value = std::env::var(name)
+                    .with_context(|| format!("The opener uses ${}, which isn't set", name))?;
+                words.extend(value.split_whitespace().map(str::to_string));
+            }
+            None => words.push(word.to_string()),
+        }
+    }
+    let Some((program, args)) = words.split_first() else {
+        anyhow::bail!("The opener in the config is empty");
+    };
+    let mut command = std::process::Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+/// A warning for results served from an index that last synced more than
+/// `stale_after_hours` ago, or `None` if it's fresh (or staleness isn't tracked).
+fn stale_warning(
+    last_sync: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    stale_after_hours: i64,
+) -> Option<String> {
+    let age = now - last_sync?;
+    (stale_after_hours > 0 && age > Duration::hours(stale_after_hours))
+        .then(|| format!("⚠️  Index last synced {}", humanize_duration(age)))
+}
+
+const PORCELAIN_SNIPPET_CHARS: usize = 200;
+
+/// One `--porcelain` line: `SCORE\tRELPATH\tSNIPPET`. This format is a contract
+/// with scripts, so it must not change. The score has `precision` decimals (the
+/// `score_precision` setting, 4 by default); the snippet is the start of the best
+/// chunk (empty for title matches) with backslash, tab, newline and carriage
+/// return escaped as `\\`, `\t`, `\n` and `\r`.
+fn porcelain_line(m: &FileMatch, precision: usize) -> String {
+    let snippet = m.chunk_prefix(PORCELAIN_SNIPPET_CHARS).unwrap_or_default();
+    format!(
+        "{}\t{}\t{}",
+        format_score(m.score, precision),
+        porcelain_escape(&m.path),
+        porcelain_escape(&snippet)
+    )
+}
+
+fn porcelain_escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn download_retry(config: &Config) -> DownloadRetry {
+    DownloadRetry {
+        retries: config.download_retries,
+        backoff: std::time::Duration::from_millis(config.download_backoff_ms),
+    }
+}
+
+/// Loads the model with the download retries and token cap from `config`.
+fn load_engine(config: &Config) -> Result<EmbeddingEngine> {
+    EmbeddingEngine::load(&config.model_id, download_retry(config))?
+        .with_max_seq_len(config.max_seq_len)
+}
+
+fn run_model_info(paths: &AppPaths) -> Result<()> {
+    let engine = load_engine(&load_config(paths).unwrap_or_default())?;
+    let info = engine.info();
+    let db = Database::open(&paths.data_dir)?;
+
+    println!("Model:           {}", info.model_id);
+    println!("Dimension:       {}", info.dimension);
+    println!("Device:          {}", info.device);
+    println!("Max seq length:  {} tokens", info.max_seq_len);
+    println!("Index dimension: {}", db.index.dimensions());
+
+    if info.dimension != db.index.dimensions() {
+        return Err(
+            anyhow::Error::new(ObraError::ModelMismatch).context(format!(
+                "Model dimension {} does not match the index ({})",
+                info.dimension,
+                db.index.dimensions()
+            )),
+        );
+    }
+    Ok(())
+}
+
+fn run_embed(paths: &AppPaths, text: &str, compare: Option<&str>) -> Result<()> {
+    let config = load_config(paths).unwrap_or_default();
+    let engine = load_engine(&config)?;
+    match compare {
+        Some(other) => {
+            let similarity = engine.similarity(text, other)?;
+            println!("{}", format_score(similarity, config.score_precision));
+        }
+        None => println!("{}", serde_json::to_string(&engine.embed_query(text)?)?),
+    }
+    Ok(())
+}
+
+fn run_verify(paths: &AppPaths, fix: bool) -> Result<()> {
+    // The daemon holds the index open, so it's the one to check and fix it.
+    let (report, source) = match send::<VerifyReport>(Request::Verify { fix }) {
+        Ok(report) => (report, " (via daemon)"),
+        Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => {
+            (verify_cold(paths, fix)?, "")
+        }
+        Err(e) => return Err(e),
+    };
+
+    println!(
+        "🔎 {} chunks, {} vectors{}",
+        report.chunks, report.vectors, source
+    );
+    if report.is_consistent() {
+        println!("✅ Index and chunk metadata are consistent.");
+        return Ok(());
+    }
+
+    println!(
+        "⚠️  {} orphaned vectors (no chunk), {} chunks without a vector",
+        report.orphaned_vectors.len(),
+        report.missing_vectors.len()
+    );
+    if !fix {
+        return Err(ObraError::IndexInconsistent.into());
+    }
+    println!("✅ Reconciled index and chunk metadata.");
+    Ok(())
+}
+
+/// `obra verify` without a daemon, on the index as saved rather than repaired.
+fn verify_cold(paths: &AppPaths, fix: bool) -> Result<VerifyReport> {
+    let mut db = Database::open_unrepaired(&paths.data_dir)?;
+    let report = db.verify()?;
+    if !fix || report.is_consistent() {
+        return Ok(report);
+    }
+
+    db.remove_orphaned_vectors(&report)?;
+    let missing = db.restore_stored_vectors(&report.missing_vectors)?;
+    if !missing.is_empty() {
+        let config = load_config(paths)?;
+        let engine = load_engine(&config)?;
+        db.set_dimension(engine.info().dimension)?;
+        reembed_chunks(&mut db, &missing, &config, &engine)?;
+    }
+    db.save_now()?;
+    Ok(report)
+}
+
+fn run_doctor(paths: &AppPaths) -> Result<()> {
+    let checks = doctor::run_checks(paths);
+    for check in &checks {
+        println!("{}", check.report());
+    }
+    if checks.iter().any(|c| c.failed()) {
+        return Err(ObraError::CheckFailed.into());
+    }
+    Ok(())
+}
+
+fn run_compact(paths: &AppPaths) -> Result<()> {
+    let (report, source) = match send(Request::Compact) {
+        Ok(report) => (report, " (via daemon)"),
+        Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => {
+            let mut db = Database::open(&paths.data_dir)?;
+            let report = db.compact()?;
+            db.save_now()?;
+            (report, "")
+        }
+        Err(e) => return Err(e),
+    };
+    println!(
+        "🗜️  Compacted {} chunks{}: vector index {} KB -> {} KB.",
+        report.chunks,
+        source,
+        report.bytes_before / 1024,
+        report.bytes_after / 1024
+    );
+    Ok(())
+}
+
+fn run_dry_run(paths: &AppPaths, force: bool) -> Result<()> {
+    let plan = match send(Request::DryRun { force }) {
+        Ok(plan) => plan,
+        // Without the daemon, chunks are split by size alone rather than loading
+        // the model just to count tokens, so the estimate can run slightly low.
+        Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => {
+            let config = load_config(paths)?;
+            let db = Database::open(&paths.data_dir)?;
+            plan_index(
+                &config,
+                &db.content_hashes(),
+                db.is_incomplete(),
+                &paths.data_dir,
+                force,
+                None,
+            )?
+        }
+        Err(e) => return Err(e),
+    };
+    for (path, chunks) in &plan.files {
+        println!("   {} ({} chunks)", path, chunks);
+    }
+    println!(
+        "🔍 Dry run: {} notes would be indexed (~{} chunks), {} deleted notes removed.",
+        plan.files.len(),
+        plan.chunks(),
+        plan.deleted
+    );
+    Ok(())
+}
+
+fn run_status(paths: &AppPaths) -> Result<()> {
+    let (status, source) = match send(Request::Status) {
+        Ok(status) => (status, "daemon"),
+        Err(e) if ObraError::find(&e) == Some(ObraError::DaemonUnavailable) => {
+            let config = load_config(paths)?;
+            let status = IndexStatus::from_disk(&config, &paths.data_dir)?;
+            (status, "index on disk; the daemon isn't running")
+        }
+        Err(e) => return Err(e),
+    };
+    print!("{}", status_report(&status, Utc::now()));
+    println!("Source:     {}", source);
+    Ok(())
+}
+
+/// e.g. `[######------------------------] 40/200 files, embedding`
+fn progress_bar(progress: &IndexProgress) -> String {
+    const WIDTH: usize = 30;
+    let filled = match progress.files_total {
+        0 => WIDTH,
+        total => WIDTH * progress.files_done.min(total) / total,
+    };
+    let stage = match progress.stage {
+        IndexStage::Embedding => "embedding",
+        IndexStage::Saving => "saving",
+        IndexStage::Unknown => "working",
+    };
+    format!(
+        "[{}{}] {}/{} files, {}",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        progress.files_done,
+        progress.files_total,
+        stage
+    )
+}
+
+/// How many skipped notes `obra status` lists before summing up the rest.
+const SKIPPED_SHOWN: usize = 10;
+
+fn status_report(status: &IndexStatus, now: DateTime<Utc>) -> String {
+    let last_sync = match status.last_sync {
+        Some(last_sync) => format!(
+            "{} ({})",
+            last_sync.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+            humanize_duration(now - last_sync)
+        ),
+        None => "never".to_string(),
+    };
+    let syncing = match status.progress {
+        Some(progress) => format!(
+            "yes ({}/{} files)",
+            progress.files_done, progress.files_total
+        ),
+        None => "no".to_string(),
+    };
+    let mut report = format!(
+        "Vault:      {}\nFiles:      {}\nChunks:     {}\nLast sync:  {}\nSyncing:    {}\n",
+        status.vault_path.display(),
+        status.files,
+        status.chunks,
+        last_sync,
+        syncing
+    );
+    if !status.skipped.is_empty() {
+        report += &format!(
+            "Skipped:    {} failing files, retried when they change\n",
+            status.skipped.len()
+        );
+        for file in status.skipped.iter().take(SKIPPED_SHOWN) {
+            let path = file
+                .path
+                .strip_prefix(&status.vault_path)
+                .unwrap_or(&file.path);
+            report += &format!("  {}: {}\n", path.display(), file.reason);
+        }
+        if status.skipped.len() > SKIPPED_SHOWN {
+            report += &format!("  ... and {} more\n", status.skipped.len() - SKIPPED_SHOWN);
+        }
+    }
+    report
+}
 
+fn run_list(paths: &AppPaths, long: bool, json: bool) -> Result<()> {
+    let db = Database::open(&paths.data_dir)?;
+    let files = db.indexed_files();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&files)?);
+        return Ok(());
+    }
+    for file in files {
+        if long {
+            let mtime = DateTime::from_timestamp(file.mtime, 0)
+                .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            println!("{:>5}  {}  {}", file.chunks, mtime, file.path);
+        } else {
+            println!("{}", file.path);
+        }
+    }
     Ok(())
 }
 
-fn run_daemon(paths: AppPaths, foreground: bool) -> Result<()> {
+fn run_similarity(paths: &AppPaths, notes: &[PathBuf]) -> Result<()> {
+    let config = load_config(paths)?;
+    let db = Database::open(&paths.data_dir)?;
+    let mut engine = None;
+
+    let mut vectors = Vec::with_capacity(notes.len());
+    let mut labels = Vec::with_capacity(notes.len());
+    for note in notes {
+        let path = if note.exists() {
+            note.clone()
+        } else {
+            config.vault_path.join(note)
+        };
+        let rel_path = vault_relative_path(&path, &config.vault_path)?;
+        let vector = match db.document_vector(&rel_path)? {
+            Some(vector) => vector,
+            None => {
+                if engine.is_none() {
+                    engine = Some(load_engine(&config)?);
+                }
+                embed_note(&path, &config, engine.as_ref().unwrap())
+                    .with_context(|| format!("Could not embed {:?}", path))?
+            }
+        };
+        vectors.push(vector);
+        labels.push(rel_path);
+    }
+
+    print!(
+        "{}",
+        similarity_table(
+            &labels,
+            &similarity_matrix(&vectors),
+            config.score_precision
+        )
+    );
+    Ok(())
+}
+
+/// A legend numbering the notes, then the matrix with numbered rows and columns.
+fn similarity_table(labels: &[String], matrix: &[Vec<f32>], precision: usize) -> String {
+    let mut out = String::new();
+    for (i, label) in labels.iter().enumerate() {
+        out += &format!("[{}] {}\n", i + 1, label);
+    }
+    let header: Vec<String> = (1..=labels.len()).map(|i| format!("[{}]", i)).collect();
+    out += &format!("\t{}\n", header.join("\t"));
+    for (i, row) in matrix.iter().enumerate() {
+        let cells: Vec<String> = row.iter().map(|s| format_score(*s, precision)).collect();
+        out += &format!("[{}]\t{}\n", i + 1, cells.join("\t"));
+    }
+    out
+}
+
+fn run_daemon(paths: AppPaths, foreground: bool, http_port: Option<u16>) -> Result<()> {
     use crate::ipc::get_socket_path;
     use interprocess::local_socket::LocalSocketStream;
 
@@ -158,40 +1173,79 @@ fn run_daemon(paths: AppPaths, foreground: bool) -> Result<()> {
 
         println!("🚀 Starting obra daemon in background...");
         
-        std::process::Command::new(std::env::current_exe()?)
+        let mut child = std::process::Command::new(std::env::current_exe()?);
+        child
             .arg("daemon")
             .arg("--foreground")
+            .arg("--data-dir")
+            .arg(&paths.data_dir);
+        if let Some(name) = &paths.vault {
+            child.arg("--vault").arg(name);
+        }
+        if let Some(port) = http_port {
+            child.arg("--http").arg(port.to_string());
+        }
+        let mut child = child
             .env("OBRA_DAEMON_CHILD", "1")
             .stdout(std::process::Stdio::from(log_file.try_clone()?))
             .stderr(std::process::Stdio::from(log_file))
             .spawn()
             .context("Failed to spawn background process")?;
-        
-        // Give it a moment to start and check if it failed immediately
-        std::thread::sleep(std::time::Duration::from_millis(500));
-        
+
+        let readiness = wait_for_daemon(
+            || LocalSocketStream::connect(get_socket_path()).is_ok(),
+            || matches!(child.try_wait(), Ok(Some(_))),
+            std::time::Duration::from_secs(30),
+        );
+        match readiness {
+            Readiness::Listening => println!("✅ Obra daemon is running."),
+            Readiness::StillStarting => println!(
+                "⏳ Obra daemon is still starting (loading the model can take a while). Logs: {:?}",
+                paths.log_file
+            ),
+            Readiness::Exited => {
+                eprintln!(
+                    "❌ Obra daemon exited during startup. Last lines of {:?}:",
+                    paths.log_file
+                );
+                eprintln!("{}", log_tail(&paths.log_file, 20));
+                anyhow::bail!("Daemon failed to start");
+            }
+        }
         return Ok(());
     }
 
     let config = load_config(&paths)?;
-    let db = Arc::new(Mutex::new(Database::open(&paths.data_dir)?));
-    let engine = Arc::new(EmbeddingEngine::new()?);
-    
+    let mut db = Database::open(&paths.data_dir)?;
+    db.configure(&config)?;
+    let db = Arc::new(Mutex::new(db));
+    let engine = load_engine(&config)?;
+    println!("🖥️  Embedding on {}", engine.info().device);
+    db.lock().unwrap().set_dimension(engine.info().dimension)?;
+    let engine = Arc::new(QueryCachedEmbedder::new(engine, config.query_cache_size));
+    if let Err(e) = engine.preload(&config.preload_queries) {
+        eprintln!("⚠️  Could not preload queries: {}", e);
+    }
+
     let manager = Arc::new(SyncManager::new(
         db.clone(),
         engine.clone(),
-        config.vault_path.clone(),
+        config,
         paths.data_dir.clone(),
     ));
 
     // System Tray Setup
     let quit = CustomMenuItem::new("quit".to_string(), "Exit Obra");
     let reindex = CustomMenuItem::new("reindex".to_string(), "Re-index All");
+    let cancel = CustomMenuItem::new("cancel".to_string(), "Cancel Re-index");
+    let pause = CustomMenuItem::new("pause".to_string(), "Pause Watching");
     let status = CustomMenuItem::new("status".to_string(), "Last indexed: Never").disabled();
     let tray_menu = SystemTrayMenu::new()
         .add_item(status)
         .add_native_item(SystemTrayMenuItem::Separator)
         .add_item(reindex)
+        .add_item(cancel)
+        .add_item(pause)
         .add_native_item(SystemTrayMenuItem::Separator)
         .add_item(quit);
 
@@ -209,10 +1263,15 @@ fn run_daemon(paths: AppPaths, foreground: bool) -> Result<()> {
                 manager.set_tray(tray_handle);
 
                 // Start IPC Server
-                start_server(manager.clone())?;
+                remove_socket_on_panic();
+                start_server(manager.clone(), app.handle())?;
+                if let Some(port) = http_port {
+                    start_http_server(manager.clone(), &manager.config.http_bind, port)?;
+                }
                 
-                // Start file watcher
+                // Start file watcher, whose changes are saved every few seconds
                 watch_vault(manager.clone())?;
+                manager.start_flusher();
 
                 // Refresh tray status periodically
                 let m = manager.clone();
@@ -227,6 +1286,8 @@ fn run_daemon(paths: AppPaths, foreground: bool) -> Result<()> {
         .on_system_tray_event(move |app, event| match event {
             SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
                 "quit" => {
+                    remove_socket_file();
+                    manager.flush();
                     app.exit(0);
                 }
                 "reindex" => {
@@ -237,6 +1298,16 @@ fn run_daemon(paths: AppPaths, foreground: bool) -> Result<()> {
                         }
                     });
                 }
+                "cancel" => {
+                    manager.cancel_index();
+                }
+                "pause" => {
+                    if manager.is_paused() {
+                        manager.resume();
+                    } else {
+                        manager.pause();
+                    }
+                }
                 _ => {}
             },
             _ => {}
@@ -246,3 +1317,368 @@ fn run_daemon(paths: AppPaths, foreground: bool) -> Result<()> {
 
     Ok(())
 }
+
+/// What a freshly spawned daemon did within the startup window.
+#[derive(Debug, PartialEq, Eq)]
+enum Readiness {
+    Listening,
+    Exited,
+    StillStarting,
+}
+
+/// Polls until the daemon accepts connections, its process exits, or `timeout`
+/// passes. The child only starts listening after loading the model, so a slow
+/// start isn't treated as a failure.
+fn wait_for_daemon(
+    mut is_listening: impl FnMut() -> bool,
+    mut has_exited: impl FnMut() -> bool,
+    timeout: std::time::Duration,
+) -> Readiness {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if is_listening() {
+            return Readiness::Listening;
+        }
+        if has_exited() {
+            return Readiness::Exited;
+        }
+        if std::time::Instant::now() >= deadline {
+            return Readiness::StillStarting;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// The last `lines` lines of the daemon log, for showing why startup failed.
+fn log_tail(path: &std::path::Path, lines: usize) -> String {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let all: Vec<&str> = content.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_daemon_readiness_check() -> Result<()> {
+        let timeout = Duration::from_millis(300);
+        assert_eq!(
+            wait_for_daemon(|| true, || false, timeout),
+            Readiness::Listening
+        );
+        assert_eq!(
+            wait_for_daemon(|| false, || false, timeout),
+            Readiness::StillStarting
+        );
+
+        // A child that dies after a couple of polls is reported as exited.
+        let mut polls = 0;
+        let exited = || {
+            polls += 1;
+            polls > 2
+        };
+        assert_eq!(
+            wait_for_daemon(|| false, exited, timeout),
+            Readiness::Exited
+        );
+
+        let tmp = tempfile::tempdir()?;
+        let log = tmp.path().join("daemon.log");
+        fs::write(&log, "starting\nloading model\npanicked at 'no model'\n")?;
+        assert_eq!(log_tail(&log, 2), "loading model\npanicked at 'no model'");
+        assert_eq!(log_tail(&tmp.path().join("missing.log"), 2), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_sets_search_options() -> Result<()> {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "vault_path": "/vault",
+                "max_candidates": 500,
+                "profiles": {
+                    "precise": {"limit": 10, "adaptive_cutoff": true, "max_candidates": 2000},
+                    "fast": {"max_candidates": 100}
+                }
+            }"#,
+        )?;
+        let options = |args: &[&str]| search_options(&Cli::try_parse_from(args).unwrap(), &config);
+
+        let default = options(&["obra", "query"])?;
+        assert_eq!((default.limit, default.max_candidates), (5, 500));
+        assert!(!default.adaptive_cutoff);
+
+        let precise = options(&["obra", "--profile", "precise", "query"])?;
+        assert_eq!((precise.limit, precise.max_candidates), (10, 2000));
+        assert!(precise.adaptive_cutoff);
+        // Flags still win over the profile.
+        let limited = options(&["obra", "--profile", "precise", "--limit", "3", "query"])?;
+        assert_eq!(limited.limit, 3);
+
+        let err = options(&["obra", "--profile", "thorough", "query"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No profile 'thorough'; the config defines fast, precise"
+        );
+
+        let config = Config {
+            default_profile: Some("fast".into()),
+            ..config
+        };
+        let cli = Cli::try_parse_from(["obra", "query"])?;
+        assert_eq!(search_options(&cli, &config)?.max_candidates, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_report_lines() {
+        use crate::index::{IndexProgress, SkippedFile};
+
+        let now = Utc::now();
+        let mut status = IndexStatus {
+            vault_path: PathBuf::from("/vault"),
+            files: 12,
+            chunks: 40,
+            last_sync: Some(now - chrono::Duration::hours(3)),
+            progress: None,
+            skipped: Vec::new(),
+        };
+        let report = status_report(&status, now);
+        assert!(report.contains("Files:      12\n"));
+        assert!(report.contains("Chunks:     40\n"));
+        assert!(report.contains("(3h ago)\n"));
+        assert!(report.ends_with("Syncing:    no\n"));
+
+        status.last_sync = None;
+        status.progress = Some(IndexProgress {
+            files_done: 2,
+            files_total: 5,
+            stage: IndexStage::Embedding,
+        });
+        let report = status_report(&status, now);
+        assert!(report.contains("Last sync:  never\n"));
+        assert!(report.contains("Syncing:    yes (2/5 files)\n"));
+
+        status.skipped = (0..SKIPPED_SHOWN + 2)
+            .map(|i| SkippedFile {
+                path: PathBuf::from(format!("/vault/broken {i}.md")),
+                reason: "failed 3 times: stream did not contain valid UTF-8".into(),
+            })
+            .collect();
+        let report = status_report(&status, now);
+        assert!(report.contains("Skipped:    12 failing files, retried when they change\n"));
+        assert!(
+            report.contains("  broken 0.md: failed 3 times: stream did not contain valid UTF-8\n")
+        );
+        assert!(report.ends_with("  ... and 2 more\n"));
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        let progress = IndexProgress {
+            files_done: 40,
+            files_total: 200,
+            stage: IndexStage::Embedding,
+        };
+        assert_eq!(
+            progress_bar(&progress),
+            "[######------------------------] 40/200 files, embedding"
+        );
+        let saving = IndexProgress {
+            files_done: 200,
+            stage: IndexStage::Saving,
+            ..progress
+        };
+        assert!(progress_bar(&saving).starts_with(&format!("[{}]", "#".repeat(30))));
+        assert!(progress_bar(&saving).ends_with("saving"));
+
+        // A stage from a newer daemon still shows as progress.
+        let newer: IndexProgress =
+            serde_json::from_str(r#"{"files_done": 3, "files_total": 5, "stage": "compacting"}"#)
+                .unwrap();
+        assert_eq!(newer.stage, IndexStage::Unknown);
+        assert!(progress_bar(&newer).ends_with("3/5 files, working"));
+    }
+
+    #[test]
+    fn test_stdin_query_matches_argument() -> Result<()> {
+        let piped = read_query("how to bake bread\n".as_bytes())?;
+        let arg = Cli::try_parse_from(["obra", "how to bake bread"])?;
+        assert_eq!(Some(piped), arg.query);
+
+        let multiline = read_query("  \"quoted\" $HOME\nsecond line\n".as_bytes())?;
+        assert_eq!(multiline, "\"quoted\" $HOME\nsecond line");
+
+        assert!(read_query(" \n".as_bytes()).is_err());
+        assert!(Cli::try_parse_from(["obra", "--stdin", "query"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bash_completions_cover_the_subcommands() {
+        let mut script = Vec::new();
+        write_completions(Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        let command = Cli::command();
+        for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+            assert!(
+                script.contains(&format!(" {} ", subcommand.get_name())),
+                "{} is missing",
+                subcommand.get_name()
+            );
+        }
+        assert!(!script.contains("completions"));
+        assert!(script.contains("--format"));
+    }
+
+    #[test]
+    fn test_obsidian_uri_encodes_the_vault_and_note() {
+        let vault = Path::new("/Users/me/My Vault");
+        assert_eq!(
+            obsidian_uri(vault, "Daily Notes/2024-01-05 Café & co.md"),
+            "obsidian://open?vault=My%20Vault&file=Daily%20Notes%2F2024-01-05%20Caf%C3%A9%20%26%20co"
+        );
+        // Only `.md` is implied by Obsidian; other files keep their extension.
+        assert_eq!(
+            obsidian_uri(vault, "Boards/plan.canvas"),
+            "obsidian://open?vault=My%20Vault&file=Boards%2Fplan.canvas"
+        );
+        let config = Config {
+            vault_path: vault.to_path_buf(),
+            ..Default::default()
+        };
+        assert_eq!(
+            note_location(&config, "a b.md", false),
+            vault.join("a b.md").into_os_string()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_open_runs_the_opener_on_the_top_result() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir()?;
+        let opened = tmp.path().join("opened.txt");
+        let opener = tmp.path().join("fake-opener");
+        fs::write(
+            &opener,
+            format!("#!/bin/sh\necho \"$@\" > '{}'\n", opened.display()),
+        )?;
+        fs::set_permissions(&opener, fs::Permissions::from_mode(0o755))?;
+        let config = Config {
+            vault_path: tmp.path().join("vault"),
+            opener: Some(format!("{} --wait", opener.display())),
+            log_queries: true,
+            ..Default::default()
+        };
+        let paths = AppPaths::new(tmp.path().to_path_buf(), tmp.path().to_path_buf())?;
+        let hit = |path: &str, title_fallback| FileMatch {
+            path: path.into(),
+            score: 0.2,
+            mtime: 0,
+            title_fallback,
+            word_count: 0,
+            chunks: Vec::new(),
+            snippet: None,
+        };
+        let hits = |matches| SearchHits { matches, total: 2 };
+        let cli = Cli::try_parse_from(["obra", "open", "shed"])?;
+
+        let found = hits(vec![hit("Garden/shed.md", false), hit("tax.md", false)]);
+        print_results(&paths, &config, found, 5, "shed", &cli, "")?;
+        let expected = config.vault_path.join("Garden/shed.md");
+        assert_eq!(
+            fs::read_to_string(&opened)?.trim(),
+            format!("--wait {}", expected.display())
+        );
+        // The query log records which note was opened.
+        let log = fs::read_to_string(query_log::log_path(&paths.data_dir))?;
+        let entry: query_log::QueryLogEntry = serde_json::from_str(log.trim())?;
+        assert_eq!(entry.chosen_path.as_deref(), Some("Garden/shed.md"));
+
+        // Nothing confident to open is an error rather than a guess.
+        fs::remove_file(&opened)?;
+        let err =
+            print_results(&paths, &config, hits(Vec::new()), 5, "shed", &cli, "").unwrap_err();
+        assert_eq!(err.to_string(), "No confident results found for 'shed'");
+        let fallback = hits(vec![hit("shed.md", true)]);
+        assert!(print_results(&paths, &config, fallback, 5, "shed", &cli, "").is_err());
+        assert!(!opened.exists());
+
+        std::env::remove_var("OBRA_TEST_UNSET_EDITOR");
+        assert!(opener_command(Some("$OBRA_TEST_UNSET_EDITOR")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_porcelain_line_fields() {
+        use crate::search::ChunkHit;
+
+        let m = FileMatch {
+            path: "Projects/plan.md".into(),
+            score: 0.123456,
+            mtime: 0,
+            title_fallback: false,
+            word_count: 0,
+            chunks: vec![ChunkHit {
+                text: "FILE_NAME: plan\n--- START OF CONTENT ---\nGoals:\n\t- ship\\done".into(),
+                score: 0.123456,
+                start: 0,
+                end: 0,
+            }],
+            snippet: None,
+        };
+        let line = porcelain_line(&m, 4);
+        assert!(!line.contains('\n'));
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        assert_eq!(
+            fields,
+            ["0.1235", "Projects/plan.md", "Goals:\\n\\t- ship\\\\done"]
+        );
+        assert!(fields[0].parse::<f32>().is_ok());
+    }
+
+    #[test]
+    fn test_scores_displayed_at_configured_precision() {
+        let m = FileMatch {
+            path: "a.md".into(),
+            score: 0.123456,
+            mtime: 0,
+            title_fallback: false,
+            word_count: 0,
+            chunks: Vec::new(),
+            snippet: None,
+        };
+        assert!(porcelain_line(&m, 2).starts_with("0.12\t"));
+        assert!(porcelain_line(&m, 0).starts_with("0\t"));
+
+        let labels = ["a.md".to_string(), "b.md".to_string()];
+        let matrix = vec![vec![1.0, 0.987654], vec![0.987654, 1.0]];
+        let table = similarity_table(&labels, &matrix, 2);
+        assert!(table.ends_with("[1]\t1.00\t0.99\n[2]\t0.99\t1.00\n"));
+
+        assert_eq!(format_score(-0.00001, 3), "0.000");
+    }
+
+    #[test]
+    fn test_stale_warning_formats_age() {
+        let now = Utc::now();
+        assert_eq!(
+            stale_warning(Some(now - chrono::Duration::days(3)), now, 24).as_deref(),
+            Some("⚠️  Index last synced 3d ago")
+        );
+        assert_eq!(
+            stale_warning(Some(now - chrono::Duration::hours(2)), now, 24),
+            None
+        );
+        assert_eq!(
+            stale_warning(Some(now - chrono::Duration::days(3)), now, 0),
+            None
+        );
+        assert_eq!(stale_warning(None, now, 24), None);
+    }
+}
@@ -0,0 +1,468 @@
+use serde::{Deserialize, Serialize};
+
+/// What to do with fenced code blocks before a note is chunked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CodeBlocks {
+    /// Embed code along with the prose.
+    #[default]
+    Include,
+    /// Replace each block with a `[code]` placeholder, so long snippets don't pull
+    /// a prose note's embedding toward code vocabulary.
+    Exclude,
+    /// Keep only the contents of code blocks.
+    CodeOnly,
+}
+
+/// What to do with a note that has almost no prose once links, headings and
+/// frontmatter are set aside, like a map of content that is only a list of links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThinNotes {
+    /// Embed whatever text it has, as for any other note.
+    #[default]
+    Embed,
+    /// Index one chunk of its title and link targets, so it's still findable.
+    TitleOnly,
+    /// Leave it out of the index.
+    Skip,
+}
+
+/// What a piece of note metadata is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldRole {
+    /// Written into the identity header, so it shapes the embedding, and stored.
+    EmbedContext,
+    /// Only stored, for filtering and exact matching.
+    FilterOnly,
+    /// Neither embedded nor stored.
+    Ignored,
+}
+
+/// The role of each metadata field, from the `fields` section of the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldRoles {
+    /// The note's folders, as the `HOLDER_FOLDERS` breadcrumb. Paths are always
+    /// stored, so `filter-only` and `ignored` both just leave it out of the header.
+    pub folders: FieldRole,
+    /// Frontmatter `aliases`.
+    pub aliases: FieldRole,
+    /// Frontmatter `tags`.
+    pub tags: FieldRole,
+}
+
+impl Default for FieldRoles {
+    fn default() -> Self {
+        Self {
+            folders: FieldRole::EmbedContext,
+            aliases: FieldRole::EmbedContext,
+            tags: FieldRole::EmbedContext,
+        }
+    }
+}
+
+/// Rewrites a note's markdown according to `code_blocks`. An unclosed fence runs
+/// to the end of the note, as it does when Obsidian renders it.
+pub fn preprocess(content: &str, code_blocks: CodeBlocks) -> String {
+    if code_blocks == CodeBlocks::Include {
+        return content.to_string();
+    }
+
+    let mut out = String::with_capacity(content.len());
+    let mut fence: Option<&str> = None;
+    for line in content.lines() {
+        match fence {
+            None => match opening_fence(line) {
+                Some(marker) => {
+                    fence = Some(marker);
+                    if code_blocks == CodeBlocks::Exclude {
+                        out.push_str("[code]\n");
+                    }
+                }
+                None if code_blocks == CodeBlocks::Exclude => {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+                None => {}
+            },
+            Some(marker) => {
+                if closes_fence(line, marker) {
+                    fence = None;
+                    if code_blocks == CodeBlocks::CodeOnly {
+                        out.push('\n');
+                    }
+                } else if code_blocks == CodeBlocks::CodeOnly {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The YAML between a note's opening `---` line and the next `---` line, if the
+/// note starts with frontmatter. A byte order mark or blank lines before it are
+/// allowed.
+pub fn frontmatter(content: &str) -> Option<&str> {
+    let start = content.trim_start_matches(['\u{feff}', ' ', '\t', '\r', '\n']);
+    let rest = start
+        .strip_prefix("---\n")
+        .or_else(|| start.strip_prefix("---\r\n"))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Some(&rest[..offset]);
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// The note without its frontmatter block, if it has one.
+pub fn without_frontmatter(content: &str) -> &str {
+    let Some(yaml) = frontmatter(content) else {
+        return content;
+    };
+    let yaml_end = yaml.as_ptr() as usize - content.as_ptr() as usize + yaml.len();
+    content[yaml_end..]
+        .split_once('\n')
+        .map_or("", |(_, rest)| rest)
+}
+
+/// The note's `aliases` (or `alias`) from its frontmatter YAML, given either as a
+/// list or a single string. Invalid YAML has no aliases.
+pub fn aliases(frontmatter: &str) -> Vec<String> {
+    string_list(frontmatter, &["aliases", "alias"])
+}
+
+/// The note's frontmatter `tags` (or `tag`), without any leading `#`.
+pub fn tags(frontmatter: &str) -> Vec<String> {
+    string_list(frontmatter, &["tags", "tag"])
+        .into_iter()
+        .map(|tag| tag.trim_start_matches('#').to_string())
+        .collect()
+}
+
+/// The `#tags` written in a note's body, like `#recipe` or `#project/acme`, in
+/// order. Headings, numbers like `#1` and anything in code blocks aren't tags.
+pub fn inline_tags(content: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    for line in prose_lines(content) {
+        for word in line.split_whitespace() {
+            let Some(rest) = word.strip_prefix('#') else {
+                continue;
+            };
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '/')))
+                .unwrap_or(rest.len());
+            let tag = rest[..end].trim_end_matches('/');
+            if tag.chars().any(|c| !c.is_ascii_digit()) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+    tags
+}
+
+/// The notes a note links to with `[[wikilinks]]` (or embeds with `![[...]]`),
+/// each once and without any `#heading` or `|alias`. Links in code blocks don't count.
+pub fn wikilinks(content: &str) -> Vec<String> {
+    let mut targets: Vec<String> = Vec::new();
+    for line in prose_lines(content) {
+        let mut rest = line;
+        while let Some(open) = rest.find("[[") {
+            let inner = &rest[open + 2..];
+            let Some(close) = inner.find("]]") else { break };
+            let target = inner[..close]
+                .split(['|', '#'])
+                .next()
+                .unwrap_or_default()
+                .trim();
+            if !target.is_empty() && !targets.iter().any(|t| t.eq_ignore_ascii_case(target)) {
+                targets.push(target.to_string());
+            }
+            rest = &inner[close + 2..];
+        }
+    }
+    targets
+}
+
+/// `text` with each `[[wikilink]]` replaced by the text Obsidian shows for it: the
+/// alias of `[[Note|alias]]`, or the note (and heading) it points to.
+pub fn link_display(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find("[[") {
+        let inner = &rest[open + 2..];
+        let Some(close) = inner.find("]]") else { break };
+        out.push_str(rest[..open].strip_suffix('!').unwrap_or(&rest[..open]));
+        let link = &inner[..close];
+        match link.split_once('|') {
+            Some((_, alias)) => out.push_str(alias),
+            None => {
+                let parts: Vec<&str> = link.split('#').filter(|p| !p.is_empty()).collect();
+                out.push_str(&parts.join(" > "));
+            }
+        }
+        rest = &inner[close + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The lines of a note's body outside fenced code blocks.
+fn prose_lines(content: &str) -> impl Iterator<Item = &str> {
+    let mut fence: Option<&str> = None;
+    without_frontmatter(content).lines().filter(move |line| {
+        if let Some(marker) = fence {
+            if closes_fence(line, marker) {
+                fence = None;
+            }
+            return false;
+        }
+        fence = opening_fence(line);
+        fence.is_none()
+    })
+}
+
+/// The first of `keys` present in the frontmatter, as a list of strings.
+fn string_list(frontmatter: &str, keys: &[&str]) -> Vec<String> {
+    let Ok(serde_yaml::Value::Mapping(map)) = serde_yaml::from_str(frontmatter) else {
+        return Vec::new();
+    };
+    let value = keys.iter().find_map(|key| map.get(*key));
+    match value {
+        Some(serde_yaml::Value::String(value)) => vec![value.clone()],
+        Some(serde_yaml::Value::Sequence(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The part of an Obsidian `.canvas` file (JSON Canvas) that holds writing.
+#[derive(Deserialize)]
+struct Canvas {
+    #[serde(default)]
+    nodes: Vec<CanvasNode>,
+}
+
+#[derive(Deserialize)]
+struct CanvasNode {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+/// The text cards of a canvas in file order, separated by blank lines. Cards that
+/// embed notes, links or groups carry no text of their own and are left out.
+pub fn canvas_text(json: &str) -> anyhow::Result<String> {
+    let canvas: Canvas = serde_json::from_str(json)?;
+    let texts: Vec<&str> = canvas
+        .nodes
+        .iter()
+        .filter(|node| node.kind == "text")
+        .map(|node| node.text.trim())
+        .filter(|text| !text.is_empty())
+        .collect();
+    Ok(texts.join("\n\n"))
+}
+
+/// Words in a note without its frontmatter, as a reader would count them.
+pub fn word_count(content: &str) -> usize {
+    without_frontmatter(content)
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count()
+}
+
+/// Words of prose in a note: what's left without its frontmatter, headings, links,
+/// tags and list markers.
+pub fn prose_words(content: &str) -> usize {
+    without_frontmatter(content)
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .map(without_links)
+        .map(|line| {
+            line.split_whitespace()
+                .filter(|word| !word.starts_with('#'))
+                .filter(|word| word.chars().any(char::is_alphanumeric))
+                .count()
+        })
+        .sum()
+}
+
+/// The targets of a note's `[[wikilinks]]` and the text of its `[markdown](links)`,
+/// in order.
+pub fn link_titles(content: &str) -> Vec<String> {
+    let mut titles = Vec::new();
+    let mut rest = content;
+    while let Some(open) = rest.find('[') {
+        rest = &rest[open..];
+        if let Some(inner) = rest.strip_prefix("[[") {
+            let Some(close) = inner.find("]]") else { break };
+            let target = inner[..close].split(['|', '#']).next().unwrap_or_default();
+            if !target.trim().is_empty() {
+                titles.push(target.trim().to_string());
+            }
+            rest = &inner[close + 2..];
+        } else {
+            let inner = &rest[1..];
+            match inner.find("](") {
+                Some(close) if !inner[..close].contains(['[', ']']) => {
+                    titles.push(inner[..close].trim().to_string());
+                    rest = &inner[close + 2..];
+                }
+                _ => rest = inner,
+            }
+        }
+    }
+    titles
+}
+
+/// `line` without its links, keeping nothing of them.
+fn without_links(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(open) = rest.find('[') {
+        out.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let end = if let Some(inner) = rest.strip_prefix("[[") {
+            inner.find("]]").map(|close| close + 4)
+        } else {
+            rest.find("](")
+                .and_then(|mid| rest[mid..].find(')').map(|close| mid + close + 1))
+        };
+        match end {
+            Some(end) => rest = &rest[end..],
+            None => {
+                out.push('[');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The fence run (e.g. "```" or "~~~~") if `line` opens a fenced code block.
+pub fn opening_fence(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let run = trimmed.len() - trimmed.trim_start_matches(fence_char).len();
+    (run >= 3).then(|| &trimmed[..run])
+}
+
+pub fn closes_fence(line: &str, marker: &str) -> bool {
+    let trimmed = line.trim();
+    let fence_char = marker.chars().next().unwrap_or('`');
+    trimmed.len() >= marker.len() && trimmed.chars().all(|c| c == fence_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canvas_text_keeps_text_cards() -> anyhow::Result<()> {
+        let canvas = r##"{
+            "nodes": [
+                {"id": "1", "type": "text", "text": "# Launch plan\nShip in May."},
+                {"id": "2", "type": "file", "file": "Roadmap.md"},
+                {"id": "3", "type": "group", "label": "Q2"},
+                {"id": "4", "type": "text", "text": "Hire a designer"}
+            ],
+            "edges": [{"id": "e", "fromNode": "1", "toNode": "4"}]
+        }"##;
+        assert_eq!(
+            canvas_text(canvas)?,
+            "# Launch plan\nShip in May.\n\nHire a designer"
+        );
+        assert_eq!(canvas_text("{}")?, "");
+        assert!(canvas_text("not json").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_block_modes() {
+        let note = "Intro prose.\n```rust\nfn main() {}\n```\nClosing prose.\n";
+
+        assert_eq!(preprocess(note, CodeBlocks::Include), note);
+        assert_eq!(
+            preprocess(note, CodeBlocks::Exclude),
+            "Intro prose.\n[code]\nClosing prose.\n"
+        );
+        assert_eq!(preprocess(note, CodeBlocks::CodeOnly), "fn main() {}\n\n");
+
+        // A shorter fence inside a longer one doesn't close it.
+        let nested = "````\n```\ninner\n```\n````\nafter\n";
+        assert_eq!(preprocess(nested, CodeBlocks::Exclude), "[code]\nafter\n");
+    }
+
+    #[test]
+    fn test_prose_words_ignore_links_and_headings() {
+        let moc = "---\ntags: [moc]\n---\n# Cooking\n- [[Bread]]\n- [[Pasta|Fresh pasta]]\n- [Stock](stock.md) #todo\n";
+        assert_eq!(prose_words(moc), 0);
+        assert_eq!(link_titles(moc), ["Bread", "Pasta", "Stock"]);
+
+        let note = "# Bread\nMix [[Flour]] and water, then wait.\n";
+        assert_eq!(prose_words(note), 5);
+    }
+
+    #[test]
+    fn test_frontmatter_is_stripped() {
+        let note = "---\ntags: [a]\n---\nBody.\n";
+        assert_eq!(without_frontmatter(note), "Body.\n");
+        assert_eq!(
+            without_frontmatter("No frontmatter.\n"),
+            "No frontmatter.\n"
+        );
+        // Unclosed, so it isn't frontmatter.
+        assert_eq!(without_frontmatter("---\ntags: [a]\n"), "---\ntags: [a]\n");
+
+        let late = "\u{feff}\n---\r\ntags: [a]\r\n---\r\nBody.\r\n";
+        assert_eq!(frontmatter(late), Some("tags: [a]\r\n"));
+        assert_eq!(without_frontmatter(late), "Body.\r\n");
+    }
+
+    #[test]
+    fn test_frontmatter_aliases() {
+        let note = "---\naliases:\n  - OKRs\n  - Goals 2024\ntags: [work]\n---\nBody\n";
+        assert_eq!(aliases(frontmatter(note).unwrap()), ["OKRs", "Goals 2024"]);
+        assert_eq!(aliases("alias: Mum\n"), ["Mum"]);
+        assert!(aliases("tags: [work]\n").is_empty());
+        assert!(aliases(": not yaml: [").is_empty());
+        assert_eq!(tags("tags: [recipe, \"#bread\"]\n"), ["recipe", "bread"]);
+    }
+
+    #[test]
+    fn test_inline_tags() {
+        let note = "---\ntags: [a]\n---\n# Dinner\nQuick #recipe for #Project/Acme/, issue #12.\n\
+                    ```sh\n#comment\n```\nSee [x](#anchor) and #dinner.\n";
+        assert_eq!(inline_tags(note), ["recipe", "Project/Acme", "dinner"]);
+    }
+
+    #[test]
+    fn test_wikilinks() {
+        let note = "See [[Bread]], [[Recipes/Pasta|fresh pasta]] and [[Bread#Starter]].\n\
+                    ![[oven.png]] [[#Local heading]] [[unclosed\n```\n[[Not a link]]\n```\n";
+        assert_eq!(wikilinks(note), ["Bread", "Recipes/Pasta", "oven.png"]);
+        assert_eq!(
+            link_display("See [[Bread]], [[Recipes/Pasta|fresh pasta]] and [[Bread#Starter]]."),
+            "See Bread, fresh pasta and Bread > Starter."
+        );
+        assert_eq!(
+            link_display("![[oven.png]] [[#Top]] [[open"),
+            "oven.png Top [[open"
+        );
+    }
+}
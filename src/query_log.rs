@@ -0,0 +1,96 @@
+use crate::config::Config;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One line of the query log, which is JSON Lines in `queries.jsonl` under the data
+/// dir. It never leaves the machine.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub query: String,
+    /// Vault-relative path of the result the user opened, if they opened one.
+    pub chosen_path: Option<String>,
+}
+
+pub fn log_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("queries.jsonl")
+}
+
+/// Appends an entry if `log_queries` is on in `config`; does nothing otherwise.
+pub fn log_query(
+    data_dir: &Path,
+    config: &Config,
+    query: &str,
+    chosen_path: Option<&str>,
+) -> Result<()> {
+    if !config.log_queries {
+        return Ok(());
+    }
+    let entry = QueryLogEntry {
+        timestamp: Utc::now(),
+        query: query.to_string(),
+        chosen_path: chosen_path.map(str::to_string),
+    };
+    let mut line = serde_json::to_vec(&entry)?;
+    line.push(b'\n');
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path(data_dir))?;
+    file.write_all(&line)?;
+    Ok(())
+}
+
+/// Deletes the query log. Returns whether there was one.
+pub fn clear_log(data_dir: &Path) -> Result<bool> {
+    match fs::remove_file(log_path(data_dir)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_logs_only_when_enabled() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut config = Config::default();
+
+        log_query(tmp.path(), &config, "private thoughts", None)?;
+        assert!(!log_path(tmp.path()).exists());
+
+        config.log_queries = true;
+        log_query(tmp.path(), &config, "bread recipe", None)?;
+        log_query(
+            tmp.path(),
+            &config,
+            "sourdough",
+            Some("Baking/Sourdough.md"),
+        )?;
+
+        let content = fs::read_to_string(log_path(tmp.path()))?;
+        let entries: Vec<QueryLogEntry> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<_>>()?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].query, "bread recipe");
+        assert_eq!(
+            entries[1].chosen_path.as_deref(),
+            Some("Baking/Sourdough.md")
+        );
+
+        assert!(clear_log(tmp.path())?);
+        assert!(!clear_log(tmp.path())?);
+        Ok(())
+    }
+}
@@ -1,90 +1,975 @@
-use crate::db::Database;
-use crate::embeddings::EmbeddingEngine;
+use crate::config::{
+    default_chunks_per_file, default_exact_title_boost, default_max_candidates,
+    default_max_results, default_mmr_lambda, default_recency_half_life_days,
+    default_score_threshold, default_search_limit,
+};
+use crate::db::{ChunkMeta, Database, MetaField, VectorPrecision};
+use crate::embeddings::{mean_pool, Embedder};
+use crate::error::ObraError;
+use crate::index::strip_identity_header;
+use crate::lexical::{tokenize, word_spans};
 use anyhow::Result;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use xxhash_rust::xxh3::xxh3_64;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SearchResult {
+    /// Identifies the note across searches (a hash of its path), so a client
+    /// paging through several requests can drop repeats. Paging isn't
+    /// transactional: if the index changes between requests, results can shift.
+    #[serde(default)]
+    pub id: String,
     pub path: String,
     pub score: f32,
+    #[serde(default)]
+    pub mtime: i64,
+    /// Set when the result came from the fuzzy filename fallback rather than
+    /// semantic search; `score` is then the negated fuzzy match score.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub title_fallback: bool,
+    #[serde(default)]
+    pub word_count: usize,
+    /// Minutes to read the note at `WORDS_PER_MINUTE`, rounded up.
+    #[serde(default)]
+    pub reading_minutes: usize,
+    /// The best-matching chunk around the first query term, with the terms in
+    /// `**bold**`; `None` for fuzzy filename matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
 }
 
+/// A result file together with the chunks that matched in it, for integrations
+/// that show previews. `score` is the best of the chunk scores.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileMatch {
+    pub path: String,
+    pub score: f32,
+    pub mtime: i64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub title_fallback: bool,
+    #[serde(default)]
+    pub word_count: usize,
+    /// Best-scoring chunks first, at most `SearchOptions::chunks_per_file`.
+    /// Empty for fuzzy filename matches.
+    pub chunks: Vec<ChunkHit>,
+    /// The best chunk with the query terms highlighted, see `highlight_snippet`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkHit {
+    pub text: String,
+    pub score: f32,
+    /// Character range of the chunk within the note's content.
+    pub start: usize,
+    pub end: usize,
+}
+
+impl From<&FileMatch> for SearchResult {
+    fn from(m: &FileMatch) -> Self {
+        SearchResult {
+            id: result_id(&m.path),
+            path: m.path.clone(),
+            score: m.score,
+            mtime: m.mtime,
+            title_fallback: m.title_fallback,
+            word_count: m.word_count,
+            reading_minutes: reading_minutes(m.word_count),
+            snippet: m.snippet.clone().or_else(|| m.chunk_prefix(SNIPPET_CHARS)),
+        }
+    }
+}
+
+/// Length of `SearchResult::snippet`, in characters.
+pub const SNIPPET_CHARS: usize = 200;
+
+impl FileMatch {
+    /// The first `max_chars` of the best chunk's note text, trimmed.
+    pub fn chunk_prefix(&self, max_chars: usize) -> Option<String> {
+        let hit = self.chunks.first()?;
+        Some(
+            strip_identity_header(&hit.text)
+                .trim()
+                .chars()
+                .take(max_chars)
+                .collect(),
+        )
+    }
+}
+
+/// About `max_chars` of `text` (a chunk), starting a little before the first word
+/// of `query` that appears in it, with every query word in the excerpt wrapped in
+/// `**`. Semantic matches may share no word with the query; those get the start
+/// of the chunk. Single letters aren't highlighted.
+pub fn highlight_snippet(text: &str, query: &str, max_chars: usize) -> String {
+    let text = strip_identity_header(text).trim();
+    let terms: HashSet<String> = tokenize(query).filter(|t| t.chars().count() > 1).collect();
+    let words = word_spans(text);
+    let matched: Vec<(usize, usize)> = words
+        .iter()
+        .copied()
+        .filter(|&(s, e)| terms.contains(&text[s..e].to_lowercase()))
+        .collect();
+    let Some(&(first, _)) = matched.first() else {
+        return text.chars().take(max_chars).collect();
+    };
+
+    // A quarter of the excerpt goes to context before the match, from a word start.
+    let lead = text[..first].chars().count().saturating_sub(max_chars / 4);
+    let start = text.char_indices().nth(lead).map_or(0, |(i, _)| i);
+    let start = match start {
+        0 => 0,
+        _ => words
+            .iter()
+            .map(|w| w.0)
+            .find(|&s| s >= start)
+            .unwrap_or(first),
+    };
+    let end = text[start..]
+        .char_indices()
+        .nth(max_chars)
+        .map_or(text.len(), |(i, _)| start + i);
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut at = start;
+    for (s, e) in matched.into_iter().filter(|&(s, e)| s >= start && e <= end) {
+        snippet.push_str(&text[at..s]);
+        snippet.push_str("**");
+        snippet.push_str(&text[s..e]);
+        snippet.push_str("**");
+        at = e;
+    }
+    snippet.push_str(&text[at..end]);
+    if end < text.len() {
+        snippet.truncate(snippet.trim_end().len());
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// A typical adult's silent reading speed.
+pub const WORDS_PER_MINUTE: usize = 200;
+
+pub fn reading_minutes(word_count: usize) -> usize {
+    word_count.div_ceil(WORDS_PER_MINUTE)
+}
+
+/// The stable id of the note at vault-relative `path`.
+pub fn result_id(path: &str) -> String {
+    format!("{:016x}", xxh3_64(path.as_bytes()))
+}
+
+/// Per-query knobs, sent along with the query when searching via the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    /// How many results to return at most. 0 returns none, only `SearchHits::total`.
+    pub limit: usize,
+    /// How many of the best results to skip, for fetching the next page.
+    pub offset: usize,
+    /// When nothing passes the confidence filter, fall back to fuzzy filename matching.
+    pub fuzzy_fallback: bool,
+    /// Upper bound on the number of chunks pulled from the vector index per query.
+    pub max_candidates: usize,
+    /// Lower bound on the same.
+    pub min_candidates: usize,
+    /// Results scoring this or worse are dropped.
+    pub score_threshold: f32,
+    /// Chunk hits kept per file in search results.
+    pub chunks_per_file: usize,
+    pub query_precision: VectorPrecision,
+    /// Subtracted from the score of notes whose title or an alias equals the query.
+    pub exact_title_boost: f32,
+    /// Subtracted from the score of a note modified just now, halving every
+    /// `recency_half_life_days` of age. 0 disables it.
+    pub recency_boost: f32,
+    pub recency_half_life_days: f32,
+    /// Subtracted from the score of a note that links to, or is linked from, one of
+    /// the best `limit` other results. 0 disables it.
+    pub link_boost: f32,
+    /// Also drop results after the largest drop in score (see `knee_cutoff`).
+    pub adaptive_cutoff: bool,
+    /// Pick results by maximal marginal relevance, so near-duplicate notes don't
+    /// crowd out other relevant ones (see `diversify`).
+    pub diverse: bool,
+    /// Weight of relevance against difference from the results already picked when
+    /// `diverse`: 1 ranks by relevance alone, 0 by difference alone.
+    pub mmr_lambda: f32,
+    /// Only notes matching every filter are returned.
+    pub filters: Vec<MetaFilter>,
+    /// Widen the vector search to make up for orphaned vectors among the candidates.
+    pub compensate_orphans: bool,
+    /// At most this many results from any one top-level folder.
+    pub max_per_folder: Option<usize>,
+    /// Only notes of at least this many words are returned.
+    pub min_words: Option<usize>,
+    pub mode: SearchMode,
+    /// Only notes under one of these vault-relative folders are returned; any note
+    /// is when it's empty.
+    pub folders: Vec<String>,
+}
+
+/// Which indexes a search consults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Embedding similarity only
+    Vector,
+    /// BM25 keyword matching only
+    Lexical,
+    /// Embedding similarity, boosted by BM25 keyword matches
+    #[default]
+    Hybrid,
+}
+
+/// Subtracted, scaled by a chunk's BM25 score relative to the best one, from its
+/// distance in hybrid search.
+const LEXICAL_BOOST: f32 = 0.5;
+
+/// Subtracted from the score of a note that contains a query word in its filename.
+const FILENAME_BOOST: f32 = 0.7;
+
+/// The `recency_boost` `--recency` turns on when the config doesn't set one. Less
+/// than `FILENAME_BOOST`, so it reorders close matches rather than overriding them.
+pub const RECENCY_BOOST: f32 = 0.3;
+
+/// `boost`, halved for every `half_life_days` that `mtime` lies before `now`.
+fn recency_boost(boost: f32, half_life_days: f32, mtime: i64, now: i64) -> f32 {
+    if boost <= 0.0 || half_life_days <= 0.0 {
+        return 0.0;
+    }
+    let age_days = (now - mtime).max(0) as f32 / 86_400.0;
+    boost * 0.5f32.powf(age_days / half_life_days)
+}
+
+/// Restricts a search to notes whose `field` contains `value`, ignoring case. A tag
+/// also matches the tags nested under it, so `project` matches `project/acme`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetaFilter {
+    pub field: MetaField,
+    pub value: String,
+}
+
+impl MetaFilter {
+    pub fn matches(&self, chunk: &ChunkMeta) -> bool {
+        let value = self.value.to_lowercase();
+        chunk.field(self.field).iter().any(|v| {
+            let v = v.to_lowercase();
+            v == value
+                || (self.field == MetaField::Tags
+                    && v.strip_prefix(&value)
+                        .is_some_and(|rest| rest.starts_with('/')))
+        })
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            limit: default_max_results(),
+            offset: 0,
+            fuzzy_fallback: false,
+            max_candidates: default_max_candidates(),
+            min_candidates: default_search_limit(),
+            score_threshold: default_score_threshold(),
+            chunks_per_file: default_chunks_per_file(),
+            query_precision: VectorPrecision::default(),
+            exact_title_boost: default_exact_title_boost(),
+            recency_boost: 0.0,
+            recency_half_life_days: default_recency_half_life_days(),
+            link_boost: 0.0,
+            adaptive_cutoff: false,
+            diverse: false,
+            mmr_lambda: default_mmr_lambda(),
+            filters: Vec::new(),
+            compensate_orphans: true,
+            max_per_folder: None,
+            min_words: None,
+            mode: SearchMode::Hybrid,
+            folders: Vec::new(),
+        }
+    }
+}
+
+/// How many chunks to fetch so that collapsing them per file still leaves about
+/// `wanted` distinct files, between `min` and `max`. Scales with the average chunks
+/// per file, since a long note can otherwise fill the whole candidate list on its own.
+pub fn candidate_limit(
+    wanted: usize,
+    chunk_count: usize,
+    file_count: usize,
+    min: usize,
+    max: usize,
+) -> usize {
+    let chunks_per_file = chunk_count.div_ceil(file_count.max(1)).max(1);
+    wanted
+        .saturating_mul(chunks_per_file)
+        .saturating_mul(2)
+        .clamp(min, max.max(min))
+}
+
+/// Final ordering applied to an already-filtered result set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultOrder {
+    /// Best match first (lowest score)
+    #[default]
+    Relevance,
+    /// Most recently modified first
+    Recency,
+    /// Alphabetical by vault-relative path
+    Path,
+}
+
+/// The fields `sort_results` orders by, shared by both result shapes.
+pub trait Ranked {
+    fn score(&self) -> f32;
+    fn mtime(&self) -> i64;
+    fn path(&self) -> &str;
+}
+
+impl Ranked for SearchResult {
+    fn score(&self) -> f32 {
+        self.score
+    }
+    fn mtime(&self) -> i64 {
+        self.mtime
+    }
+    fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Ranked for FileMatch {
+    fn score(&self) -> f32 {
+        self.score
+    }
+    fn mtime(&self) -> i64 {
+        self.mtime
+    }
+    fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+pub fn sort_results<R: Ranked>(results: &mut [R], order: ResultOrder) {
+    match order {
+        ResultOrder::Relevance => {
+            results.sort_by(|a, b| a.score().partial_cmp(&b.score()).unwrap())
+        }
+        ResultOrder::Recency => results.sort_by_key(|r| std::cmp::Reverse(r.mtime())),
+        ResultOrder::Path => results.sort_by(|a, b| a.path().cmp(b.path())),
+    }
+}
+
+/// The results of one search, with how many notes matched in all.
+#[derive(Debug, Default)]
+pub struct SearchHits {
+    /// At most `SearchOptions::limit` of the best matches, after skipping `offset`.
+    pub matches: Vec<FileMatch>,
+    /// Notes that passed the confidence filter among the candidates fetched from
+    /// the vector index, including those outside the page. The index only returns
+    /// its nearest chunks, as many as `offset + limit` notes need (within
+    /// `max_candidates`), so for a broad query this is a lower bound that can grow
+    /// as later pages are fetched. Fuzzy filename matches aren't counted.
+    pub total: usize,
+}
+
+/// Finds the files best matching `query`, each with its best-scoring chunks.
+/// With a `limit` of 0 this runs the whole pipeline and returns only the total,
+/// as a cheap health probe.
 pub fn run_search(
     query: &str,
     db: &Database,
-    engine: &EmbeddingEngine,
-) -> Result<Vec<SearchResult>> {
-    // Embed query
-    let query_vector = engine.embed(vec![query.to_string()])?[0].clone();
+    engine: &dyn Embedder,
+    options: &SearchOptions,
+) -> Result<SearchHits> {
+    if query.trim().is_empty() {
+        return Err(ObraError::EmptyQuery.into());
+    }
+    if db.chunks.is_empty() {
+        return Err(ObraError::EmptyIndex.into());
+    }
+
+    let query_vector = engine.embed_query(query)?;
+    search_vector(query, query_vector, db, options)
+}
+
+/// Runs several searches, embedding all the queries in one batch. Returns one
+/// result set per query, in order.
+pub fn run_batch_search(
+    queries: &[String],
+    db: &Database,
+    engine: &dyn Embedder,
+    options: &SearchOptions,
+) -> Result<Vec<SearchHits>> {
+    if queries.iter().any(|q| q.trim().is_empty()) {
+        return Err(ObraError::EmptyQuery.into());
+    }
+    if db.chunks.is_empty() {
+        return Err(ObraError::EmptyIndex.into());
+    }
+    if queries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let vectors = engine.embed_queries(queries.to_vec())?;
+    queries
+        .iter()
+        .zip(vectors)
+        .map(|(query, vector)| search_vector(query, vector, db, options))
+        .collect()
+}
+
+/// The search itself, given the embedded query.
+fn search_vector(
+    query: &str,
+    query_vector: Vec<f32>,
+    db: &Database,
+    options: &SearchOptions,
+) -> Result<SearchHits> {
+    let query_vector = options.query_precision.round(query_vector);
+    // Every result up to the end of the requested page is ranked. Both come from
+    // the HTTP query string, so they can be anything.
+    let wanted = options.offset.saturating_add(options.limit);
+
+    // Vector search. A search for no results only wants `total`, so it ranks
+    // every chunk rather than a pool sized for a page.
+    let limit = match wanted {
+        0 => db.chunks.len().max(1),
+        _ => candidate_limit(
+            wanted,
+            db.chunks.len(),
+            db.file_count(),
+            options.min_candidates,
+            options.max_candidates,
+        ),
+    };
+    let scoped = !options.folders.is_empty() || !options.filters.is_empty();
+    let scope: Option<HashSet<u64>> = scoped.then(|| {
+        db.chunks
+            .iter()
+            .filter(|c| in_folders(&c.path, &options.folders))
+            .filter(|c| options.filters.iter().all(|f| f.matches(c)))
+            .map(|c| c.id)
+            .collect()
+    });
+    let mut matches = match options.mode {
+        SearchMode::Lexical => Vec::new(),
+        _ => search_candidates(
+            db,
+            &query_vector,
+            limit,
+            options.compensate_orphans,
+            scope.as_ref(),
+        )?,
+    };
+
+    // BM25 scores relative to the best, so 1.0 is the best keyword match.
+    let mut lexical: HashMap<u64, f32> = HashMap::new();
+    if options.mode != SearchMode::Vector {
+        let hits = match &scope {
+            // Scored in full, since the best keyword matches may all be out of scope.
+            Some(scope) => {
+                let mut hits = db.lexical.search(query, usize::MAX);
+                hits.retain(|(id, _)| scope.contains(id));
+                hits.truncate(limit);
+                hits
+            }
+            None => db.lexical.search(query, limit),
+        };
+        let best = hits.first().map_or(1.0, |&(_, score)| score);
+        let candidate_ids: HashSet<u64> = matches.iter().map(|(key, _)| *key).collect();
+        for (id, score) in hits {
+            lexical.insert(id, score / best);
+            if candidate_ids.contains(&id) {
+                continue;
+            }
+            // In lexical mode every hit starts at the same distance, so the
+            // keyword score alone ranks them.
+            let distance = match options.mode {
+                SearchMode::Lexical => 1.0,
+                _ => match db.vector(id)? {
+                    Some(vector) => 1.0 - cosine_similarity(&query_vector, &vector),
+                    None => continue,
+                },
+            };
+            matches.push((id, distance));
+        }
+    }
+    let lexical_boost = match options.mode {
+        SearchMode::Lexical => 1.0,
+        _ => LEXICAL_BOOST,
+    };
 
-    // Vector search
-    let matches = db.search(&query_vector, 20)?;
+    // A note titled (or aliased) exactly as the query should win even when its
+    // chunks didn't make the candidate list, so score those directly.
+    let exact_paths = exact_title_paths(query, db, options.exact_title_boost);
+    let candidate_ids: HashSet<u64> = matches.iter().map(|(key, _)| *key).collect();
+    for chunk in db
+        .chunks
+        .iter()
+        .filter(|c| exact_paths.contains(c.path.as_str()))
+    {
+        if candidate_ids.contains(&chunk.id) {
+            continue;
+        }
+        if let Some(vector) = db.vector(chunk.id)? {
+            matches.push((chunk.id, 1.0 - cosine_similarity(&query_vector, &vector)));
+        }
+    }
 
-    let mut file_map: HashMap<String, SearchResult> = HashMap::new();
+    let now = chrono::Utc::now().timestamp();
+    let mut file_map: HashMap<String, FileMatch> = HashMap::new();
     let query_words: Vec<String> = query
         .to_lowercase()
         .split_whitespace()
         .map(|s| s.to_string())
         .collect();
 
+    let chunks: HashMap<u64, &ChunkMeta> = db.chunks.iter().map(|c| (c.id, c)).collect();
     for (key, distance) in matches {
-        let Some(meta) = db.chunks.iter().find(|c| c.id == key) else {
+        let Some(&meta) = chunks.get(&key) else {
             continue;
         };
+        if !options.filters.iter().all(|f| f.matches(meta)) {
+            continue;
+        }
+        if meta.word_count < options.min_words.unwrap_or(0) {
+            continue;
+        }
+        if !in_folders(&meta.path, &options.folders) {
+            continue;
+        }
 
         let filename = meta.filename.to_lowercase();
-        let mut score = distance;
+        let mut score = distance - lexical.get(&key).unwrap_or(&0.0) * lexical_boost;
 
         // Filename boost
         if query_words
             .iter()
             .any(|word| word.len() > 2 && filename.contains(word))
         {
-            score -= 0.7;
+            score -= FILENAME_BOOST;
         }
-
-        if !file_map.contains_key(&meta.path) || score < file_map[&meta.path].score {
-            file_map.insert(
-                meta.path.clone(),
-                SearchResult {
-                    path: meta.path.clone(),
-                    score,
-                },
-            );
+        if exact_paths.contains(meta.path.as_str()) {
+            score -= options.exact_title_boost;
         }
+        // Adds to the boosts above, so among notes that match alike the fresher wins.
+        score -= recency_boost(
+            options.recency_boost,
+            options.recency_half_life_days,
+            meta.mtime,
+            now,
+        );
+
+        // Matches arrive best first, so the first hit for a file sets its score
+        // and later ones only add chunks.
+        let file = file_map
+            .entry(meta.path.clone())
+            .or_insert_with(|| FileMatch {
+                path: meta.path.clone(),
+                score,
+                mtime: meta.mtime,
+                title_fallback: false,
+                word_count: meta.word_count,
+                chunks: Vec::new(),
+                snippet: None,
+            });
+        file.score = file.score.min(score);
+        file.chunks.push(ChunkHit {
+            text: meta.text.clone(),
+            score,
+            start: meta.start,
+            end: meta.end,
+        });
     }
 
-    let mut sorted: Vec<SearchResult> = file_map.into_values().collect();
-    sorted.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap());
+    let mut sorted: Vec<FileMatch> = file_map.into_values().collect();
+    // Equal scores rank by path.
+    sorted.sort_by(|a, b| a.path.cmp(&b.path));
+    sort_results(&mut sorted, ResultOrder::Relevance);
+    if options.link_boost > 0.0 {
+        boost_linked(&mut sorted, db, options.link_boost, wanted);
+        sort_results(&mut sorted, ResultOrder::Relevance);
+    }
+    if let Some(cap) = options.max_per_folder {
+        cap_per_folder(&mut sorted, cap);
+    }
 
     // Filter by confidence threshold
-    let results = sorted
-        .into_iter()
-        .filter(|r| r.score < 1.2)
-        .take(5)
+    sorted.retain(|r| r.score < options.score_threshold);
+    let total = sorted.len();
+    if options.diverse {
+        diversify(&mut sorted, db, options.mmr_lambda, wanted)?;
+    }
+    let mut results: Vec<FileMatch> = sorted.into_iter().take(wanted).collect();
+    if options.adaptive_cutoff {
+        let scores: Vec<f32> = results.iter().map(|r| r.score).collect();
+        results.truncate(knee_cutoff(&scores));
+    }
+    results.drain(..options.offset.min(results.len()));
+    for file in &mut results {
+        file.chunks
+            .sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap());
+        file.chunks.truncate(options.chunks_per_file);
+        file.snippet = file
+            .chunks
+            .first()
+            .map(|hit| highlight_snippet(&hit.text, query, SNIPPET_CHARS));
+    }
+
+    if total == 0 && options.fuzzy_fallback {
+        let mut matches = fuzzy_filename_matches(query, db, usize::MAX);
+        if let Some(scope) = &scope {
+            matches.retain(|m| {
+                db.chunks
+                    .iter()
+                    .any(|c| c.path == m.path && scope.contains(&c.id))
+            });
+        }
+        let matches = matches
+            .into_iter()
+            .skip(options.offset)
+            .take(options.limit)
+            .collect();
+        return Ok(SearchHits { matches, total });
+    }
+
+    Ok(SearchHits {
+        matches: results,
+        total,
+    })
+}
+
+/// Share of candidates that may be orphaned vectors (no chunk metadata) before a
+/// search warns that the index has drifted.
+const ORPHAN_WARN_RATIO: f32 = 0.1;
+
+/// Subtracts `boost` from the score of each note that links to, or is linked from,
+/// one of the `top` best other notes in `files`, which arrive best first.
+fn boost_linked(files: &mut [FileMatch], db: &Database, boost: f32, top: usize) {
+    let mut links: HashMap<&str, &[String]> = HashMap::new();
+    for chunk in &db.chunks {
+        links.entry(chunk.path.as_str()).or_insert(&chunk.links);
+    }
+    let links_to = |from: &str, to: &str| {
+        links
+            .get(from)
+            .is_some_and(|targets| targets.iter().any(|t| link_resolves_to(t, to)))
+    };
+    let best: Vec<String> = files.iter().take(top).map(|f| f.path.clone()).collect();
+    for file in files.iter_mut() {
+        let path = file.path.as_str();
+        if best
+            .iter()
+            .any(|b| b != path && (links_to(path, b) || links_to(b, path)))
+        {
+            file.score -= boost;
+        }
+    }
+}
+
+/// Reorders `files` (best first) by maximal marginal relevance: each of the first
+/// `picks` is the note scoring best on `lambda` times its relevance minus
+/// `1 - lambda` times its greatest similarity to a note picked before it, measured
+/// between their document vectors. The rest follow in their original order.
+fn diversify(files: &mut Vec<FileMatch>, db: &Database, lambda: f32, picks: usize) -> Result<()> {
+    let lambda = lambda.clamp(0.0, 1.0);
+    let index: HashMap<&str, usize> = files
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.path.as_str(), i))
+        .collect();
+    let mut chunk_vectors: Vec<Vec<Vec<f32>>> = vec![Vec::new(); files.len()];
+    for chunk in &db.chunks {
+        if let Some(&i) = index.get(chunk.path.as_str()) {
+            chunk_vectors[i].extend(db.vector(chunk.id)?);
+        }
+    }
+    let vectors: Vec<Option<Vec<f32>>> = chunk_vectors.iter().map(|v| mean_pool(v)).collect();
+
+    let mut remaining: Vec<usize> = (0..files.len()).collect();
+    let mut picked = Vec::new();
+    // Greatest similarity of each note to those picked so far.
+    let mut similarity = vec![0.0f32; files.len()];
+    while picked.len() < picks && !remaining.is_empty() {
+        // A lower score is more relevant. Ties go to the earlier, more relevant note.
+        let mmr = |i: usize| -lambda * files[i].score - (1.0 - lambda) * similarity[i];
+        let best = (1..remaining.len()).fold(0, |best, r| {
+            if mmr(remaining[r]) > mmr(remaining[best]) {
+                r
+            } else {
+                best
+            }
+        });
+        let chosen = remaining.remove(best);
+        if let Some(chosen_vector) = &vectors[chosen] {
+            for &i in &remaining {
+                if let Some(vector) = &vectors[i] {
+                    similarity[i] = similarity[i].max(cosine_similarity(chosen_vector, vector));
+                }
+            }
+        }
+        picked.push(chosen);
+    }
+
+    let mut slots: Vec<Option<FileMatch>> = files.drain(..).map(Some).collect();
+    files.extend(
+        picked
+            .into_iter()
+            .chain(remaining)
+            .filter_map(|i| slots[i].take()),
+    );
+    Ok(())
+}
+
+/// Whether a wikilink `target` points to vault-relative `path`: by the path, with
+/// or without its extension, or by the note's name alone as Obsidian allows.
+fn link_resolves_to(target: &str, path: &str) -> bool {
+    let stem = match path.rsplit_once('.') {
+        Some((stem, ext)) if !ext.contains('/') => stem,
+        _ => path,
+    };
+    let name = stem.rsplit('/').next().unwrap_or(stem);
+    [path, stem, name]
+        .iter()
+        .any(|candidate| candidate.eq_ignore_ascii_case(target))
+}
+
+/// Whether vault-relative `path` lies in one of `folders`, or there are none.
+fn in_folders(path: &str, folders: &[String]) -> bool {
+    folders.is_empty()
+        || folders.iter().any(|folder| {
+            let folder = folder.trim_matches('/');
+            folder.is_empty()
+                || path
+                    .strip_prefix(folder)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+/// The `limit` nearest chunks. Orphaned vectors among them would silently cost
+/// results, so with `compensate` the search widens until `limit` real chunks are
+/// found or the index runs out. With a `scope`, only the chunks in it are kept, and
+/// the search widens until `limit` of those are found.
+fn search_candidates(
+    db: &Database,
+    query_vector: &[f32],
+    limit: usize,
+    compensate: bool,
+    scope: Option<&HashSet<u64>>,
+) -> Result<Vec<(u64, f32)>> {
+    let known: HashSet<u64> = db.chunks.iter().map(|c| c.id).collect();
+    let orphans_in = |matches: &[(u64, f32)]| {
+        matches
+            .iter()
+            .filter(|(key, _)| !known.contains(key))
+            .count()
+    };
+
+    let mut k = limit;
+    let mut matches = db.search(query_vector, k)?;
+    let orphans = orphans_in(&matches);
+    if orphans as f32 > matches.len() as f32 * ORPHAN_WARN_RATIO {
+        eprintln!(
+            "⚠️  {} of {} search candidates have no chunk metadata; run `obra verify --fix`.",
+            orphans,
+            matches.len()
+        );
+    }
+
+    let total = db.index.size();
+    let mut orphans = orphans;
+    while compensate && orphans > 0 && matches.len() - orphans < limit && k < total {
+        k = (k + orphans).min(total);
+        matches = db.search(query_vector, k)?;
+        orphans = orphans_in(&matches);
+    }
+
+    let Some(scope) = scope else {
+        return Ok(matches);
+    };
+    let in_scope = |matches: &[(u64, f32)]| {
+        matches
+            .iter()
+            .filter(|(key, _)| scope.contains(key))
+            .count()
+    };
+    // Out-of-scope chunks can fill any number of the nearest places, so double
+    // rather than add the shortfall. A scope smaller than `limit` is done once
+    // all of it is found.
+    let wanted = limit.min(scope.len());
+    while in_scope(&matches) < wanted && k < total {
+        k = (k * 2).min(total);
+        matches = db.search(query_vector, k)?;
+    }
+    matches.retain(|(key, _)| scope.contains(key));
+    Ok(matches)
+}
+
+/// Keeps at most `cap` of the ranked `results` from each top-level folder, so
+/// lower-ranked notes from other folders move up. Notes at the vault root count
+/// as one folder.
+fn cap_per_folder(results: &mut Vec<FileMatch>, cap: usize) {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    results.retain(|r| {
+        let folder = r.path.split_once('/').map_or("", |(top, _)| top);
+        let count = counts.entry(folder.to_string()).or_default();
+        *count += 1;
+        *count <= cap
+    });
+}
+
+/// How many of `scores` (sorted best first) to keep: everything before the largest
+/// gap between neighbours, if that gap stands out at more than twice the average.
+/// Evenly spread scores have no knee and are all kept.
+pub fn knee_cutoff(scores: &[f32]) -> usize {
+    if scores.len() < 3 {
+        return scores.len();
+    }
+    let gaps: Vec<f32> = scores.windows(2).map(|w| w[1] - w[0]).collect();
+    let (knee, largest) =
+        gaps.iter().enumerate().fold(
+            (0, f32::MIN),
+            |best, (i, &gap)| if gap > best.1 { (i, gap) } else { best },
+        );
+    let mean = gaps.iter().sum::<f32>() / gaps.len() as f32;
+    if largest > 2.0 * mean {
+        knee + 1
+    } else {
+        scores.len()
+    }
+}
+
+/// Paths of notes whose filename or an alias equals `query`, ignoring case and
+/// spacing. Empty when the boost is disabled.
+fn exact_title_paths<'a>(query: &str, db: &'a Database, boost: f32) -> HashSet<&'a str> {
+    let normalize = |s: &str| {
+        s.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    let wanted = normalize(query);
+    if boost <= 0.0 || wanted.is_empty() {
+        return HashSet::new();
+    }
+    db.chunks
+        .iter()
+        .filter(|c| {
+            normalize(&c.filename) == wanted || c.aliases.iter().any(|a| normalize(a) == wanted)
+        })
+        .map(|c| c.path.as_str())
+        .collect()
+}
+
+/// Best fuzzy matches of `query` against every indexed filename, for when the
+/// user half-remembers a note's title but semantic search finds nothing.
+pub fn fuzzy_filename_matches(query: &str, db: &Database, limit: usize) -> Vec<FileMatch> {
+    let matcher = SkimMatcherV2::default();
+    let mut seen = HashSet::new();
+    let mut matches: Vec<FileMatch> = db
+        .chunks
+        .iter()
+        .filter(|c| seen.insert(c.path.as_str()))
+        .filter_map(|c| {
+            let score = matcher.fuzzy_match(&c.filename, query)?;
+            Some(FileMatch {
+                path: c.path.clone(),
+                score: -(score as f32),
+                mtime: c.mtime,
+                title_fallback: true,
+                word_count: c.word_count,
+                chunks: Vec::new(),
+                snippet: None,
+            })
+        })
         .collect();
 
-    Ok(results)
+    sort_results(&mut matches, ResultOrder::Relevance);
+    matches.truncate(limit);
+    matches
+}
+
+/// Formats a score for display with `precision` decimals. Only for output: scores
+/// are compared and sorted unrounded.
+pub fn format_score(score: f32, precision: usize) -> String {
+    let rounded = format!("{:.*}", precision, score);
+    // "-0.0000" and "0.0000" are the same score.
+    if rounded
+        .trim_start_matches('-')
+        .chars()
+        .all(|c| c == '0' || c == '.')
+    {
+        rounded.trim_start_matches('-').to_string()
+    } else {
+        rounded
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denom = norm(a) * norm(b);
+    if denom == 0.0 {
+        0.0
+    } else {
+        dot / denom
+    }
+}
+
+/// Pairwise cosine similarities; row `i`, column `j` compares `vectors[i]` and `vectors[j]`.
+pub fn similarity_matrix(vectors: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    vectors
+        .iter()
+        .map(|a| vectors.iter().map(|b| cosine_similarity(a, b)).collect())
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::{ChunkMeta, Database, VECTOR_DIM};
+    use crate::db::{Database, VECTOR_DIM};
+    use crate::embeddings::testing::MockEmbedder;
+    use std::path::Path;
     use tempfile::tempdir;
 
+    /// Adds a one-chunk note at `path`, titled after its file name and embedded as
+    /// `MockEmbedder` would.
+    fn insert_note(db: &mut Database, path: &str, text: &str) -> Result<()> {
+        let title = Path::new(path).file_stem().unwrap_or_default();
+        insert_titled(db, path, &title.to_string_lossy(), text)
+    }
+
+    /// `insert_note` with a title of its own, e.g. one no query matches.
+    fn insert_titled(db: &mut Database, path: &str, title: &str, text: &str) -> Result<()> {
+        let meta = ChunkMeta {
+            path: path.into(),
+            filename: title.into(),
+            text: text.into(),
+            ..Default::default()
+        };
+        db.insert_chunks(vec![meta], vec![MockEmbedder::vector(text)])
+    }
+
     #[test]
     fn test_search_ranking() -> Result<()> {
         let tmp = tempdir()?;
         let mut db = Database::open(tmp.path())?;
 
         let meta1 = ChunkMeta {
-            id: 0,
             path: "apple.md".into(),
             filename: "apple".into(),
             text: "all about apples".into(),
-            mtime: 0,
+            ..Default::default()
         };
         let vec1 = vec![0.1; VECTOR_DIM];
         db.insert_chunks(vec![meta1], vec![vec1.clone()])?;
@@ -93,4 +978,922 @@ mod tests {
         assert_eq!(matches.len(), 1);
         Ok(())
     }
+
+    #[test]
+    fn test_result_ordering() {
+        let result = |path: &str, score, mtime| SearchResult {
+            id: result_id(path),
+            path: path.into(),
+            score,
+            mtime,
+            title_fallback: false,
+            word_count: 0,
+            reading_minutes: 0,
+            snippet: None,
+        };
+        let fixture = || {
+            vec![
+                result("b.md", 0.2, 300),
+                result("c.md", 0.1, 100),
+                result("a.md", 0.3, 200),
+            ]
+        };
+        let order_of = |order| {
+            let mut results = fixture();
+            sort_results(&mut results, order);
+            results.into_iter().map(|r| r.path).collect::<Vec<_>>()
+        };
+
+        assert_eq!(order_of(ResultOrder::Relevance), ["c.md", "b.md", "a.md"]);
+        assert_eq!(order_of(ResultOrder::Recency), ["b.md", "a.md", "c.md"]);
+        assert_eq!(order_of(ResultOrder::Path), ["a.md", "b.md", "c.md"]);
+    }
+
+    #[test]
+    fn test_fuzzy_filename_fallback() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let engine = MockEmbedder::default();
+
+        // Every chunk points away from the query, so nothing passes the threshold.
+        let query = "qrtly plan";
+        let opposite: Vec<f32> = MockEmbedder::vector(query).iter().map(|x| -x).collect();
+        for path in ["Quarterly Planning.md", "recipes.md"] {
+            let meta = ChunkMeta {
+                path: path.into(),
+                filename: path.trim_end_matches(".md").into(),
+                text: "unrelated".into(),
+                ..Default::default()
+            };
+            db.insert_chunks(vec![meta], vec![opposite.clone()])?;
+        }
+
+        assert!(run_search(query, &db, &engine, &SearchOptions::default())?
+            .matches
+            .is_empty());
+
+        let options = SearchOptions {
+            fuzzy_fallback: true,
+            ..Default::default()
+        };
+        let results = run_search(query, &db, &engine, &options)?.matches;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "Quarterly Planning.md");
+        assert!(results[0].title_fallback);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_candidate_limit_scales_with_chunks_per_file() -> Result<()> {
+        assert_eq!(candidate_limit(5, 50, 50, 20, 1000), 20);
+        assert_eq!(candidate_limit(5, 3000, 10, 20, 1000), 1000);
+
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let query = "garden";
+        // The first note has 30 chunks that all match the query better than any
+        // other note, so a fixed top-20 would only ever see that one file.
+        for file in 0..5 {
+            let text = if file == 0 { "garden" } else { "garden shed" };
+            let metas = (0..30)
+                .map(|_| ChunkMeta {
+                    path: format!("note{}.md", file),
+                    filename: format!("note{}", file),
+                    text: text.into(),
+                    ..Default::default()
+                })
+                .collect();
+            let vectors = vec![MockEmbedder::vector(text); 30];
+            db.insert_chunks(metas, vectors)?;
+        }
+
+        let engine = MockEmbedder::default();
+        let results = run_search(query, &db, &engine, &SearchOptions::default())?.matches;
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].path, "note0.md");
+        Ok(())
+    }
+
+    #[test]
+    fn test_grouped_search_keeps_chunk_hits() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let chunk = |text: &str, start, end| ChunkMeta {
+            path: "garden.md".into(),
+            filename: "garden".into(),
+            text: text.into(),
+            start,
+            end,
+            ..Default::default()
+        };
+        db.insert_chunks(
+            vec![
+                chunk("tomato seedlings", 0, 16),
+                chunk("tomato harvest", 12, 26),
+            ],
+            vec![
+                MockEmbedder::vector("tomato seedlings"),
+                MockEmbedder::vector("tomato harvest"),
+            ],
+        )?;
+
+        let engine = MockEmbedder::default();
+        let matches = run_search("tomato", &db, &engine, &SearchOptions::default())?.matches;
+        assert_eq!(matches.len(), 1);
+        let hits = &matches[0].chunks;
+        assert_eq!(hits.len(), 2);
+        assert!(hits
+            .iter()
+            .any(|h| h.text == "tomato harvest" && (h.start, h.end) == (12, 26)));
+        assert_eq!(matches[0].score, hits[0].score);
+
+        let options = SearchOptions {
+            chunks_per_file: 1,
+            ..Default::default()
+        };
+        let matches = run_search("tomato", &db, &engine, &options)?.matches;
+        assert_eq!(matches[0].chunks.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snippet_is_the_start_of_the_best_chunk() {
+        let hit = |text: &str, score| ChunkHit {
+            text: text.into(),
+            score,
+            start: 0,
+            end: 0,
+        };
+        let mut m = FileMatch {
+            path: "garden.md".into(),
+            score: 0.2,
+            mtime: 0,
+            title_fallback: false,
+            word_count: 0,
+            chunks: vec![
+                hit(
+                    "FILE_NAME: garden\n--- START OF CONTENT ---\n  Tomato seedlings\n",
+                    0.2,
+                ),
+                hit("Tomato harvest", 0.4),
+            ],
+            snippet: None,
+        };
+        assert_eq!(m.chunk_prefix(6).as_deref(), Some("Tomato"));
+        let result = SearchResult::from(&m);
+        assert_eq!(result.snippet.as_deref(), Some("Tomato seedlings"));
+
+        m.chunks.clear();
+        assert_eq!(SearchResult::from(&m).snippet, None);
+    }
+
+    #[test]
+    fn test_snippet_highlights_query_terms() -> Result<()> {
+        let text = format!(
+            "FILE_NAME: garden\n--- START OF CONTENT ---\n{} Water the tomatoes at dawn; tomatoes hate wet leaves. Mulch in June.",
+            "Filler words here. ".repeat(10)
+        );
+        let snippet = highlight_snippet(&text, "Tomatoes watering", 60);
+        assert_eq!(
+            snippet,
+            "…Water the **tomatoes** at dawn; **tomatoes** hate wet leaves. Mulch…"
+        );
+
+        // Nothing to highlight in a purely semantic match.
+        assert_eq!(
+            highlight_snippet("Seedlings need light.", "plant care", 9),
+            "Seedlings"
+        );
+
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let meta = ChunkMeta {
+            path: "garden.md".into(),
+            filename: "garden".into(),
+            text: "Tomato harvest in August".into(),
+            ..Default::default()
+        };
+        db.insert_chunks(vec![meta], vec![MockEmbedder::vector("tomato harvest")])?;
+        let engine = MockEmbedder::default();
+        let hits = run_search("tomato", &db, &engine, &SearchOptions::default())?;
+        let result = SearchResult::from(&hits.matches[0]);
+        assert_eq!(
+            result.snippet.as_deref(),
+            Some("**Tomato** harvest in August")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_title_or_alias_ranks_first() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let query = "Reading List";
+        let note = |path: &str, aliases: &[&str], text: &str| ChunkMeta {
+            path: path.into(),
+            filename: path.trim_end_matches(".md").into(),
+            text: text.into(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        };
+        // The titled note's content points away from the query; the other
+        // notes match it semantically.
+        let opposite: Vec<f32> = MockEmbedder::vector(query).iter().map(|x| -x).collect();
+        db.insert_chunks(
+            vec![note("reading list.md", &[], "isbn")],
+            vec![opposite.clone()],
+        )?;
+        db.insert_chunks(
+            vec![note("books.md", &["Reading"], "reading list")],
+            vec![MockEmbedder::vector(query)],
+        )?;
+        db.insert_chunks(vec![note("misc.md", &["TBR"], "")], vec![opposite])?;
+
+        let engine = MockEmbedder::default();
+        let results =
+            run_search("  reading   LIST ", &db, &engine, &SearchOptions::default())?.matches;
+        assert_eq!(results[0].path, "reading list.md");
+
+        let results = run_search("tbr", &db, &engine, &SearchOptions::default())?.matches;
+        assert_eq!(results[0].path, "misc.md");
+
+        let options = SearchOptions {
+            exact_title_boost: 0.0,
+            ..Default::default()
+        };
+        let results = run_search(query, &db, &engine, &options)?.matches;
+        assert_eq!(results[0].path, "books.md");
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_search_matches_individual_searches() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        for text in ["sourdough starter", "tomato seedlings", "quarterly goals"] {
+            insert_note(&mut db, &format!("{}.md", text), text)?;
+        }
+
+        let queries: Vec<String> = ["bread", "garden tomato", "goals"]
+            .iter()
+            .map(|q| q.to_string())
+            .collect();
+        let engine = MockEmbedder::default();
+        let batch = run_batch_search(&queries, &db, &engine, &SearchOptions::default())?;
+        assert_eq!(
+            engine.batches.lock().unwrap().as_slice(),
+            std::slice::from_ref(&queries)
+        );
+
+        assert_eq!(batch.len(), queries.len());
+        for (query, hits) in queries.iter().zip(&batch) {
+            let results = &hits.matches;
+            let single = run_search(query, &db, &engine, &SearchOptions::default())?.matches;
+            // Unrelated notes tie, so compare regardless of their order.
+            let scored = |r: &[FileMatch]| {
+                let mut scored: Vec<_> = r.iter().map(|m| (m.path.clone(), m.score)).collect();
+                scored.sort_by(|a, b| a.0.cmp(&b.0));
+                scored
+            };
+            assert_eq!(scored(results), scored(&single));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_knee_cutoff() {
+        assert_eq!(knee_cutoff(&[0.20, 0.22, 0.25, 0.80, 0.83]), 3);
+        assert_eq!(knee_cutoff(&[0.1, 0.2, 0.3, 0.4]), 4);
+        assert_eq!(knee_cutoff(&[0.1, 0.9]), 2);
+    }
+
+    #[test]
+    fn test_adaptive_cutoff_trims_tail() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let texts = [
+            "rust borrow checker",
+            "rust borrow lifetimes",
+            "rust borrow traits",
+            "rust",
+        ];
+        for text in texts {
+            insert_titled(&mut db, &format!("{}.md", text), "note", text)?;
+        }
+
+        let engine = MockEmbedder::default();
+        let query = "rust borrow";
+        // Every note shares a word with the query, so keyword matching would only
+        // shift the scores; test the distances alone.
+        let vector_only = SearchOptions {
+            mode: SearchMode::Vector,
+            ..Default::default()
+        };
+        assert_eq!(
+            run_search(query, &db, &engine, &vector_only)?.matches.len(),
+            4
+        );
+
+        let options = SearchOptions {
+            adaptive_cutoff: true,
+            ..vector_only.clone()
+        };
+        let results = run_search(query, &db, &engine, &options)?.matches;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| r.path.starts_with("rust borrow")));
+
+        // A tighter fixed threshold drops it as well.
+        let options = SearchOptions {
+            score_threshold: 0.25,
+            ..vector_only
+        };
+        let results = run_search(query, &db, &engine, &options)?.matches;
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|r| r.path.starts_with("rust borrow")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_id_is_stable_across_searches() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        for text in ["sourdough starter", "sourdough discard"] {
+            insert_note(&mut db, &format!("Baking/{}.md", text), text)?;
+        }
+
+        let engine = MockEmbedder::default();
+        let ids = |query: &str, db: &Database| -> Result<HashMap<String, String>> {
+            let matches = run_search(query, db, &engine, &SearchOptions::default())?.matches;
+            Ok(matches
+                .iter()
+                .map(SearchResult::from)
+                .map(|r| (r.path, r.id))
+                .collect())
+        };
+        let first = ids("sourdough starter", &db)?;
+        // Re-indexing the note gives it new chunk ids but the same result id.
+        db.delete_by_path("Baking/sourdough starter.md");
+        let meta = ChunkMeta {
+            path: "Baking/sourdough starter.md".into(),
+            filename: "sourdough starter".into(),
+            text: "sourdough starter".into(),
+            mtime: 1,
+            ..Default::default()
+        };
+        db.insert_chunks(vec![meta], vec![MockEmbedder::vector("sourdough starter")])?;
+        let second = ids("sourdough discard", &db)?;
+
+        assert_eq!(first.len(), 2);
+        assert_eq!(first, second);
+        assert_ne!(
+            first["Baking/sourdough starter.md"],
+            first["Baking/sourdough discard.md"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_orphaned_candidates_are_compensated() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let chunk = |path: &str| ChunkMeta {
+            path: path.into(),
+            filename: path.trim_end_matches(".md").into(),
+            text: "garden".into(),
+            ..Default::default()
+        };
+        for i in 0..5 {
+            db.insert_chunks(
+                vec![chunk(&format!("plot{}.md", i))],
+                vec![MockEmbedder::vector("garden shed")],
+            )?;
+        }
+        // Vectors closer to the query than any real chunk, whose metadata is gone.
+        db.insert_chunks(
+            (0..25).map(|_| chunk("deleted.md")).collect(),
+            vec![MockEmbedder::vector("garden"); 25],
+        )?;
+        db.chunks.retain(|c| c.path != "deleted.md");
+
+        let engine = MockEmbedder::default();
+        let options = SearchOptions {
+            mode: SearchMode::Vector,
+            ..Default::default()
+        };
+        let results = run_search("garden", &db, &engine, &options)?.matches;
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(|r| r.path.starts_with("plot")));
+
+        let options = SearchOptions {
+            compensate_orphans: false,
+            ..options
+        };
+        assert!(run_search("garden", &db, &engine, &options)?
+            .matches
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_folder_cap_surfaces_other_folders() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let notes = [
+            ("Work/roadmap.md", "launch plan timeline"),
+            ("Work/standup.md", "launch plan notes"),
+            ("Work/retro.md", "launch plan review"),
+            ("Personal/trip.md", "trip plan"),
+            ("ideas.md", "side project plan"),
+        ];
+        for (path, text) in notes {
+            insert_titled(&mut db, path, "note", text)?;
+        }
+
+        let engine = MockEmbedder::default();
+        let query = "launch plan";
+        let paths = |options: &SearchOptions| -> Result<Vec<String>> {
+            let results = run_search(query, &db, &engine, options)?.matches;
+            Ok(results.into_iter().map(|r| r.path).collect())
+        };
+        let uncapped = paths(&SearchOptions::default())?;
+        assert!(uncapped[..3].iter().all(|p| p.starts_with("Work/")));
+
+        let capped = paths(&SearchOptions {
+            max_per_folder: Some(1),
+            ..Default::default()
+        })?;
+        assert_eq!(capped.len(), 3);
+        assert!(capped[0].starts_with("Work/"));
+        assert!(capped.contains(&"Personal/trip.md".to_string()));
+        assert!(capped.contains(&"ideas.md".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_link_boost_lifts_notes_linked_with_the_best_match() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let notes = [
+            ("sourdough.md", "sourdough starter", vec!["Flour"]),
+            ("almond.md", "starter flour", vec![]),
+            ("pantry/flour.md", "starter flour", vec![]),
+        ];
+        for (path, text, links) in notes {
+            let meta = ChunkMeta {
+                path: path.into(),
+                filename: "note".into(),
+                text: text.into(),
+                links: links.into_iter().map(String::from).collect(),
+                ..Default::default()
+            };
+            db.insert_chunks(vec![meta], vec![MockEmbedder::vector(text)])?;
+        }
+
+        let engine = MockEmbedder::default();
+        let paths = |link_boost: f32| -> Result<Vec<String>> {
+            let options = SearchOptions {
+                link_boost,
+                ..Default::default()
+            };
+            let results = run_search("sourdough starter", &db, &engine, &options)?.matches;
+            Ok(results.into_iter().map(|r| r.path).collect())
+        };
+        // The two notes match alike, so the path breaks the tie without the boost.
+        assert_eq!(
+            paths(0.0)?,
+            ["sourdough.md", "almond.md", "pantry/flour.md"]
+        );
+        // The best match links to `[[Flour]]`, which moves up.
+        assert_eq!(
+            paths(0.2)?,
+            ["sourdough.md", "pantry/flour.md", "almond.md"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_folders_restricts_results() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let notes = [
+            ("Projects/Acme/kickoff.md", "deadline for the launch"),
+            ("Projects/AcmeOld/kickoff.md", "deadline for the launch"),
+            ("Journal/monday.md", "missed a deadline"),
+            ("inbox.md", "deadline"),
+        ];
+        for (path, text) in notes {
+            insert_titled(&mut db, path, "kickoff", text)?;
+        }
+
+        let engine = MockEmbedder::default();
+        let paths = |folders: &[&str]| -> Result<Vec<String>> {
+            let options = SearchOptions {
+                folders: folders.iter().map(|f| f.to_string()).collect(),
+                ..Default::default()
+            };
+            let results = run_search("deadline", &db, &engine, &options)?.matches;
+            Ok(results.into_iter().map(|r| r.path).collect())
+        };
+        assert_eq!(paths(&["Projects/Acme/"])?, ["Projects/Acme/kickoff.md"]);
+        let mut both = paths(&["Projects/Acme", "Journal"])?;
+        both.sort();
+        assert_eq!(both, ["Journal/monday.md", "Projects/Acme/kickoff.md"]);
+        assert_eq!(paths(&[])?.len(), 4);
+
+        let options = SearchOptions {
+            folders: vec!["Archive".into()],
+            fuzzy_fallback: true,
+            ..Default::default()
+        };
+        let hits = run_search("deadline", &db, &engine, &options)?;
+        assert_eq!(hits.total, 0);
+        assert!(hits.matches.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_folders_backfills_past_closer_notes() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let chunk = |path: String| ChunkMeta {
+            path,
+            filename: "note".into(),
+            ..Default::default()
+        };
+        // Far more out-of-scope chunks closer to the query than the first fetch takes.
+        db.insert_chunks(
+            (0..40).map(|i| chunk(format!("Other/{}.md", i))).collect(),
+            vec![MockEmbedder::vector("soil"); 40],
+        )?;
+        db.insert_chunks(
+            vec![chunk("Garden/beds.md".into())],
+            vec![MockEmbedder::vector("soil ph")],
+        )?;
+
+        let engine = MockEmbedder::default();
+        let options = SearchOptions {
+            mode: SearchMode::Vector,
+            min_candidates: 5,
+            max_candidates: 5,
+            score_threshold: f32::MAX,
+            folders: vec!["Garden".into()],
+            ..Default::default()
+        };
+        let results = run_search("soil", &db, &engine, &options)?.matches;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "Garden/beds.md");
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_words_excludes_short_notes() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let note = |path: &str, word_count| ChunkMeta {
+            path: path.into(),
+            filename: path.trim_end_matches(".md").into(),
+            text: "compost".into(),
+            word_count,
+            ..Default::default()
+        };
+        db.insert_chunks(
+            vec![note("stub.md", 12), note("guide.md", 450)],
+            vec![MockEmbedder::vector("compost"); 2],
+        )?;
+
+        let engine = MockEmbedder::default();
+        let options = SearchOptions {
+            min_words: Some(200),
+            ..Default::default()
+        };
+        let results = run_search("compost", &db, &engine, &options)?.matches;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "guide.md");
+
+        let result = SearchResult::from(&results[0]);
+        assert_eq!(result.word_count, 450);
+        assert_eq!(result.reading_minutes, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rare_keyword_ranks_first_in_hybrid_mode() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let notes = [
+            ("incident.md", "E4021 during the Friday deploy window."),
+            ("chant.md", "Deploy, deploy, deploy!"),
+            ("checklist.md", "Deploy checklist."),
+            ("compost.md", "Compost bins."),
+            ("bread.md", "Sourdough bread."),
+        ];
+        let metas = notes
+            .iter()
+            .map(|(path, text)| ChunkMeta {
+                path: path.to_string(),
+                filename: path.trim_end_matches(".md").into(),
+                text: text.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let vectors = notes
+            .iter()
+            .map(|(_, text)| MockEmbedder::vector(text))
+            .collect();
+        db.insert_chunks(metas, vectors)?;
+
+        let engine = MockEmbedder::default();
+        let top = |mode| -> Result<String> {
+            let options = SearchOptions {
+                mode,
+                ..Default::default()
+            };
+            let hits = run_search("deploy E4021", &db, &engine, &options)?;
+            Ok(hits.matches[0].path.clone())
+        };
+        assert_eq!(top(SearchMode::Vector)?, "chant.md");
+        assert_eq!(top(SearchMode::Hybrid)?, "incident.md");
+        assert_eq!(top(SearchMode::Lexical)?, "incident.md");
+
+        // Only notes sharing a term with the query match lexically.
+        let options = SearchOptions {
+            mode: SearchMode::Lexical,
+            ..Default::default()
+        };
+        let hits = run_search("sourdough", &db, &engine, &options)?;
+        assert_eq!(hits.total, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recency_boost_prefers_the_newer_note() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let now = chrono::Utc::now().timestamp();
+        let three_years = 3 * 365 * 86_400;
+        let metas = [
+            ("archive.md", now - three_years),
+            ("today.md", now - 86_400),
+        ]
+        .iter()
+        .map(|(path, mtime)| ChunkMeta {
+            path: path.to_string(),
+            filename: path.trim_end_matches(".md").into(),
+            text: "What I was working on".into(),
+            mtime: *mtime,
+            ..Default::default()
+        })
+        .collect();
+        let vector = MockEmbedder::vector("What I was working on");
+        db.insert_chunks(metas, vec![vector.clone(), vector])?;
+
+        let engine = MockEmbedder::default();
+        let search = |recency_boost| {
+            let options = SearchOptions {
+                recency_boost,
+                ..Default::default()
+            };
+            run_search("working on", &db, &engine, &options)
+        };
+        // Equal scores rank by path when recency is off.
+        assert_eq!(search(0.0)?.matches[0].path, "archive.md");
+        let hits = search(RECENCY_BOOST)?;
+        assert_eq!(hits.matches[0].path, "today.md");
+        let gap = hits.matches[1].score - hits.matches[0].score;
+        assert!(gap > 0.2 && gap < RECENCY_BOOST);
+
+        assert_eq!(recency_boost(0.4, 30.0, now - 30 * 86_400, now), 0.2);
+        assert_eq!(recency_boost(0.0, 30.0, now, now), 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit_zero_returns_only_the_total() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        // The last note points away from the query, so three notes pass the threshold.
+        let away: Vec<f32> = MockEmbedder::vector("bread").iter().map(|x| -x).collect();
+        for text in ["bread recipe", "bread starter", "bread flour", "tax return"] {
+            let vector = if text == "tax return" {
+                away.clone()
+            } else {
+                MockEmbedder::vector(text)
+            };
+            let meta = ChunkMeta {
+                path: format!("{}.md", text),
+                filename: "note".into(),
+                text: text.into(),
+                ..Default::default()
+            };
+            db.insert_chunks(vec![meta], vec![vector])?;
+        }
+
+        let engine = MockEmbedder::default();
+        let options = SearchOptions {
+            limit: 2,
+            ..Default::default()
+        };
+        let hits = run_search("bread", &db, &engine, &options)?;
+        assert_eq!(hits.matches.len(), 2);
+        assert_eq!(hits.total, 3);
+
+        // The count doesn't depend on how many candidates a page would fetch.
+        let options = SearchOptions {
+            limit: 0,
+            fuzzy_fallback: true,
+            min_candidates: 1,
+            max_candidates: 1,
+            ..Default::default()
+        };
+        let hits = run_search("bread", &db, &engine, &options)?;
+        assert!(hits.matches.is_empty());
+        assert_eq!(hits.total, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diverse_surfaces_a_distinct_note_over_near_duplicates() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let query = MockEmbedder::vector("sourdough");
+        let q = query.iter().position(|&x| x > 0.0).unwrap();
+        // Unit vectors along the query's dimension and a few others.
+        let others: Vec<usize> = (0..VECTOR_DIM).filter(|&i| i != q).take(5).collect();
+        let unit = |parts: &[(usize, f32)]| {
+            let norm = parts.iter().map(|(_, x)| x * x).sum::<f32>().sqrt();
+            let mut v = vec![0.0; VECTOR_DIM];
+            for &(i, x) in parts {
+                v[i] = x / norm;
+            }
+            v
+        };
+        // Three copies of one note, closest to the query, and a different note
+        // that matches a little less well.
+        let notes = [
+            (
+                "copy a.md",
+                unit(&[(q, 0.9), (others[0], 0.43), (others[1], 0.02)]),
+            ),
+            (
+                "copy b.md",
+                unit(&[(q, 0.9), (others[0], 0.43), (others[2], 0.02)]),
+            ),
+            (
+                "copy c.md",
+                unit(&[(q, 0.9), (others[0], 0.43), (others[3], 0.02)]),
+            ),
+            ("other.md", unit(&[(q, 0.8), (others[4], 0.6)])),
+        ];
+        for (path, vector) in notes {
+            let meta = ChunkMeta {
+                path: path.into(),
+                filename: path.trim_end_matches(".md").into(),
+                text: "starter feeding schedule".into(),
+                ..Default::default()
+            };
+            db.insert_chunks(vec![meta], vec![vector])?;
+        }
+
+        let engine = MockEmbedder::default();
+        let mut options = SearchOptions {
+            limit: 3,
+            mode: SearchMode::Vector,
+            ..Default::default()
+        };
+        let paths = |hits: SearchHits| -> Vec<String> {
+            hits.matches.into_iter().map(|m| m.path).collect()
+        };
+        let plain = paths(run_search("sourdough", &db, &engine, &options)?);
+        assert_eq!(plain, ["copy a.md", "copy b.md", "copy c.md"]);
+
+        options.diverse = true;
+        let diverse = paths(run_search("sourdough", &db, &engine, &options)?);
+        assert_eq!(diverse, ["copy a.md", "other.md", "copy b.md"]);
+
+        // At lambda 1 it's relevance alone again.
+        options.mmr_lambda = 1.0;
+        assert_eq!(
+            paths(run_search("sourdough", &db, &engine, &options)?),
+            plain
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_and_punctuation_queries() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        insert_note(&mut db, "bread.md", "bread recipe")?;
+        let engine = MockEmbedder::default();
+        let options = SearchOptions {
+            fuzzy_fallback: true,
+            ..Default::default()
+        };
+
+        // Rejected before anything is embedded.
+        for query in ["", "  \n\t"] {
+            let err = run_search(query, &db, &engine, &options).unwrap_err();
+            assert_eq!(ObraError::find(&err), Some(ObraError::EmptyQuery));
+        }
+        let queries = ["bread".to_string(), " ".to_string()];
+        let err = run_batch_search(&queries, &db, &engine, &options).unwrap_err();
+        assert_eq!(ObraError::find(&err), Some(ObraError::EmptyQuery));
+        assert_eq!(engine.calls(), 0);
+
+        // Punctuation alone is a query, just one that matches nothing well.
+        let hits = run_search("?!... --", &db, &engine, &options)?;
+        assert!(hits.matches.iter().all(|m| m.score.is_finite()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_offset_pages_through_the_results() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let texts = [
+            "bread",
+            "bread flour",
+            "bread flour water",
+            "bread flour water salt",
+            "bread flour water salt yeast",
+            "bread flour water salt yeast oven",
+            "bread flour water salt yeast oven tin",
+        ];
+        for (i, text) in texts.iter().enumerate() {
+            insert_note(&mut db, &format!("{}.md", i), text)?;
+        }
+
+        let engine = MockEmbedder::default();
+        let search = |offset, limit| {
+            let options = SearchOptions {
+                offset,
+                limit,
+                mode: SearchMode::Vector,
+                ..Default::default()
+            };
+            run_search("bread", &db, &engine, &options)
+        };
+        let everything = search(0, 10)?;
+        let all: Vec<String> = everything.matches.iter().map(|m| m.path.clone()).collect();
+        assert_eq!(all.len(), 7);
+        assert_eq!(everything.total, 7);
+
+        let mut paged = Vec::new();
+        for offset in [0, 3, 6] {
+            let page = search(offset, 3)?;
+            assert_eq!(page.total, 7);
+            paged.extend(page.matches.into_iter().map(|m| m.path));
+        }
+        assert_eq!(paged, all);
+
+        // Past the end there's nothing more, but the total stays.
+        let past = search(9, 3)?;
+        assert!(past.matches.is_empty());
+        assert_eq!(past.total, 7);
+        // Out-of-range values from a query string don't overflow.
+        assert!(search(usize::MAX, usize::MAX)?.matches.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_similarity_matrix_is_symmetric() -> Result<()> {
+        let tmp = tempdir()?;
+        let mut db = Database::open(tmp.path())?;
+        let notes = [
+            ("bread.md", ["sourdough starter", "bake the loaf"]),
+            ("garden.md", ["tomato seedlings", "water the beds"]),
+            ("baking.md", ["sourdough loaf", "oven temperature"]),
+        ];
+        for (path, texts) in notes {
+            let metas = texts
+                .iter()
+                .map(|text| ChunkMeta {
+                    path: path.into(),
+                    filename: path.trim_end_matches(".md").into(),
+                    text: text.to_string(),
+                    ..Default::default()
+                })
+                .collect();
+            db.insert_chunks(
+                metas,
+                texts.iter().map(|t| MockEmbedder::vector(t)).collect(),
+            )?;
+        }
+
+        let vectors = notes
+            .iter()
+            .map(|(path, _)| db.document_vector(path).map(Option::unwrap))
+            .collect::<Result<Vec<_>>>()?;
+        let matrix = similarity_matrix(&vectors);
+        for (i, row) in matrix.iter().enumerate() {
+            assert!((row[i] - 1.0).abs() < 1e-5);
+            for (j, similarity) in row.iter().enumerate() {
+                assert!((similarity - matrix[j][i]).abs() < 1e-6);
+            }
+        }
+        assert!(matrix[0][2] > matrix[0][1]);
+        assert!(db.document_vector("missing.md")?.is_none());
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use crate::index::SyncManager;
+use crate::index::{is_indexable, SyncManager};
 use anyhow::Result;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use std::sync::Arc;
@@ -34,20 +34,76 @@ pub fn watch_vault(manager: Arc<SyncManager>) -> Result<()> {
 }
 
 fn handle_event(manager: &SyncManager, event: notify::Event) {
+    use notify::event::{ModifyKind, RemoveKind, RenameMode};
     use notify::EventKind;
 
+    // A renamed folder arrives as one event with its old and new path.
+    if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) =
+        (event.kind, event.paths.as_slice())
+    {
+        if to.is_dir() {
+            // Both sides, so resuming drops the old notes and indexes the new ones.
+            if manager.defer_if_paused(from) | manager.defer_if_paused(to) {
+                return;
+            }
+            // A folder moved into or out of an ignored one leaves or joins the index.
+            let result = match (manager.ignores_folder(from), manager.ignores_folder(to)) {
+                (true, true) => return,
+                (false, true) => manager.remove_folder(from),
+                (true, false) => manager.index_folder(to),
+                (false, false) => manager.rename_folder(from, to),
+            };
+            println!("📁 Folder renamed: {:?} -> {:?}", from, to);
+            if let Err(e) = result {
+                eprintln!("❌ Failed to rename folder {:?}: {}", from, e);
+            }
+            return;
+        }
+        println!("🔀 File renamed: {:?} -> {:?}", from, to);
+    }
+
+    // Renames are handled per path: the old one is gone, the new one exists. That
+    // also covers platforms that report each side as its own single-path event.
+    let renamed = matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)));
     for path in event.paths {
-        if path.extension().and_then(|s| s.to_str()) != Some("md") {
+        if manager.ignores(&path) {
+            continue;
+        }
+        if !is_indexable(&path, &manager.config) {
+            // A deleted folder is reported once, not per note inside it.
+            if (renamed || matches!(event.kind, EventKind::Remove(_)))
+                && !path.exists()
+                && !manager.defer_if_paused(&path)
+            {
+                if let Err(e) = manager.remove_folder(&path) {
+                    eprintln!("❌ Failed to remove folder {:?}: {}", path, e);
+                }
+            }
+            continue;
+        }
+        if manager.defer_if_paused(&path) {
             continue;
         }
 
         match event.kind {
+            _ if renamed && !path.exists() => {
+                if let Err(e) = manager.remove_file(&path) {
+                    eprintln!("❌ Failed to remove file {:?}: {}", path, e);
+                }
+            }
             EventKind::Modify(_) | EventKind::Create(_) => {
                 println!("📝 File changed: {:?}", path);
                 if let Err(e) = manager.index_file(&path) {
                     eprintln!("❌ Failed to index file {:?}: {}", path, e);
                 }
             }
+            // A folder named like a note, e.g. `Books.md/`, still holds notes.
+            EventKind::Remove(RemoveKind::Folder) => {
+                println!("🗑️ Folder removed: {:?}", path);
+                if let Err(e) = manager.remove_folder(&path) {
+                    eprintln!("❌ Failed to remove folder {:?}: {}", path, e);
+                }
+            }
             EventKind::Remove(_) => {
                 println!("🗑️ File removed: {:?}", path);
                 if let Err(e) = manager.remove_file(&path) {
@@ -58,3 +114,257 @@ fn handle_event(manager: &SyncManager, event: notify::Event) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::index::testing::{test_manager, test_manager_with};
+    use notify::event::{CreateKind, EventKind, ModifyKind, RemoveKind, RenameMode};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_removed_folder_purges_its_notes() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = test_manager(vault_dir.path(), data_dir.path())?;
+
+        let folder = vault_dir.path().join("Archive");
+        fs::create_dir_all(folder.join("2023"))?;
+        fs::create_dir_all(vault_dir.path().join("Archive Notes"))?;
+        for path in [
+            folder.join("old.md"),
+            folder.join("2023").join("older.md"),
+            vault_dir.path().join("Archive Notes").join("kept.md"),
+        ] {
+            fs::write(&path, "an archived note")?;
+            manager.index_file(&path)?;
+        }
+
+        fs::remove_dir_all(&folder)?;
+        handle_event(
+            &manager,
+            notify::Event::new(EventKind::Remove(RemoveKind::Folder)).add_path(folder),
+        );
+
+        let paths = |manager: &SyncManager| -> Vec<String> {
+            let db = manager.db.lock().unwrap();
+            db.chunks.iter().map(|c| c.path.clone()).collect()
+        };
+        assert_eq!(paths(&manager), ["Archive Notes/kept.md"]);
+
+        // A folder whose name passes the extension filter is still a folder.
+        let folder = vault_dir.path().join("Reading.md");
+        fs::create_dir(&folder)?;
+        fs::write(folder.join("dune.md"), "a book note")?;
+        manager.index_file(&folder.join("dune.md"))?;
+        fs::remove_dir_all(&folder)?;
+        handle_event(
+            &manager,
+            notify::Event::new(EventKind::Remove(RemoveKind::Folder)).add_path(folder),
+        );
+        assert_eq!(paths(&manager), ["Archive Notes/kept.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_renamed_folder_keeps_its_notes() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = test_manager(vault_dir.path(), data_dir.path())?;
+
+        let from = vault_dir.path().join("Drafts");
+        fs::create_dir(&from)?;
+        fs::write(from.join("idea.md"), "a draft idea")?;
+        manager.index_file(&from.join("idea.md"))?;
+
+        let to = vault_dir.path().join("Published");
+        fs::rename(&from, &to)?;
+        handle_event(
+            &manager,
+            notify::Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(from)
+                .add_path(to),
+        );
+
+        assert_eq!(
+            manager.db.lock().unwrap().chunks[0].path,
+            "Published/idea.md"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_renamed_note_moves_to_its_new_path() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = test_manager(vault_dir.path(), data_dir.path())?;
+        let paths = || -> Vec<String> {
+            let db = manager.db.lock().unwrap();
+            db.chunks.iter().map(|c| c.path.clone()).collect()
+        };
+
+        let a = vault_dir.path().join("a.md");
+        let b = vault_dir.path().join("b.md");
+        fs::write(&a, "a note about to be renamed")?;
+        manager.index_file(&a)?;
+        fs::rename(&a, &b)?;
+        handle_event(
+            &manager,
+            notify::Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(a.clone())
+                .add_path(b.clone()),
+        );
+        assert_eq!(paths(), ["b.md"]);
+
+        // Platforms that report each side of a rename separately.
+        fs::rename(&b, &a)?;
+        for (mode, path) in [(RenameMode::From, &b), (RenameMode::To, &a)] {
+            handle_event(
+                &manager,
+                notify::Event::new(EventKind::Modify(ModifyKind::Name(mode)))
+                    .add_path(path.clone()),
+            );
+        }
+        assert_eq!(paths(), ["a.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_edits_in_ignored_folders_are_skipped() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = test_manager_with(
+            Config {
+                vault_path: vault_dir.path().to_path_buf(),
+                ignore_folders: vec!["Attachments".into(), "Daily/*".into()],
+                ..Default::default()
+            },
+            data_dir.path(),
+        )?;
+
+        for rel_path in [
+            "Attachments/scan.md",
+            "Daily/2024/monday.md",
+            "Projects/plan.md",
+        ] {
+            let path = vault_dir.path().join(rel_path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, "a note")?;
+            handle_event(
+                &manager,
+                notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path),
+            );
+        }
+
+        let paths = || -> Vec<String> {
+            let db = manager.db.lock().unwrap();
+            let mut paths: Vec<String> = db.chunks.iter().map(|c| c.path.clone()).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(paths(), ["Projects/plan.md"]);
+
+        // Folders renamed into and out of an ignored folder leave and join the index.
+        let rename = |from: &str, to: &str| -> Result<()> {
+            let (from, to) = (vault_dir.path().join(from), vault_dir.path().join(to));
+            fs::rename(&from, &to)?;
+            handle_event(
+                &manager,
+                notify::Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                    .add_path(from)
+                    .add_path(to),
+            );
+            Ok(())
+        };
+        rename("Projects", "Attachments/Projects")?;
+        assert!(paths().is_empty());
+        rename("Daily/2024", "Journal")?;
+        assert_eq!(paths(), ["Journal/monday.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_paused_events_wait_for_resume() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = Arc::new(test_manager(vault_dir.path(), data_dir.path())?);
+
+        manager.pause();
+        let path = vault_dir.path().join("imported.md");
+        fs::write(&path, "bulk imported note")?;
+        handle_event(
+            &manager,
+            notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path),
+        );
+        assert!(manager.db.lock().unwrap().chunks.is_empty());
+        assert_eq!(manager.status_text(), "Watching paused (1 changes pending)");
+
+        manager.resume().expect("was paused").join().unwrap();
+        assert!(!manager.is_paused());
+        assert_eq!(manager.db.lock().unwrap().chunks.len(), 1);
+        assert!(manager.resume().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_note_renamed_while_paused_is_indexed_on_resume() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = Arc::new(test_manager(vault_dir.path(), data_dir.path())?);
+        let from = vault_dir.path().join("draft.md");
+        fs::write(&from, "a note that moves")?;
+        manager.full_index(false)?;
+
+        manager.pause();
+        let to = vault_dir.path().join("final.md");
+        fs::rename(&from, &to)?;
+        handle_event(
+            &manager,
+            notify::Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(from)
+                .add_path(to),
+        );
+        assert_eq!(manager.db.lock().unwrap().chunks[0].path, "draft.md");
+
+        manager.resume().expect("was paused").join().unwrap();
+        let db = manager.db.lock().unwrap();
+        let paths: Vec<&str> = db.chunks.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["final.md"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_folder_renamed_while_paused_waits_for_resume() -> Result<()> {
+        let vault_dir = tempdir()?;
+        let data_dir = tempdir()?;
+        let manager = Arc::new(test_manager(vault_dir.path(), data_dir.path())?);
+        fs::create_dir(vault_dir.path().join("Drafts"))?;
+        fs::write(vault_dir.path().join("Drafts/plan.md"), "a plan")?;
+        manager.full_index(false)?;
+        let paths = || -> Vec<String> {
+            let db = manager.db.lock().unwrap();
+            db.chunks.iter().map(|c| c.path.clone()).collect()
+        };
+
+        manager.pause();
+        let (from, to) = (
+            vault_dir.path().join("Drafts"),
+            vault_dir.path().join("Projects"),
+        );
+        fs::rename(&from, &to)?;
+        handle_event(
+            &manager,
+            notify::Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(from)
+                .add_path(to),
+        );
+        assert_eq!(paths(), ["Drafts/plan.md"]);
+        assert_eq!(manager.status_text(), "Watching paused (2 changes pending)");
+
+        manager.resume().expect("was paused").join().unwrap();
+        assert_eq!(paths(), ["Projects/plan.md"]);
+        Ok(())
+    }
+}